@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use tokio::fs;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::Mutex;
+
+use crate::{CheckpointStore, Error, Position};
+
+// FileStore keeps every checkpoint in one JSON file. The file is rewritten as a
+// whole on each save: the new contents go to a temporary file that is synced and
+// then renamed over the old one, so a crash leaves either the old or the new
+// checkpoints, never a torn file.
+pub struct FileStore {
+    path: PathBuf,
+    positions: Mutex<HashMap<String, Position>>,
+}
+
+impl FileStore {
+    pub async fn open(path: impl AsRef<Path>) -> Result<Self, Error> {
+        let path = path.as_ref().to_path_buf();
+        let positions = match fs::read(&path).await {
+            Ok(contents) => serde_json::from_slice(&contents)
+                .map_err(|e| format!("corrupt checkpoint file `{}`: {}", path.display(), e))?,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => HashMap::new(),
+            Err(e) => return Err(e.into()),
+        };
+        Ok(FileStore {
+            path,
+            positions: Mutex::new(positions),
+        })
+    }
+
+    async fn write(&self, positions: &HashMap<String, Position>) -> Result<(), Error> {
+        let mut temporary = self.path.clone().into_os_string();
+        temporary.push(".tmp");
+        let temporary = PathBuf::from(temporary);
+
+        let mut file = fs::File::create(&temporary).await?;
+        file.write_all(&serde_json::to_vec_pretty(positions)?)
+            .await?;
+        file.sync_all().await?;
+        drop(file);
+        fs::rename(&temporary, &self.path).await?;
+
+        // the rename itself is only durable once the directory is synced
+        let directory = match self.path.parent() {
+            Some(parent) if !parent.as_os_str().is_empty() => parent,
+            _ => Path::new("."),
+        };
+        fs::File::open(directory).await?.sync_all().await?;
+        Ok(())
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for FileStore {
+    async fn load(&self, source: &str) -> Result<Option<Position>, Error> {
+        Ok(self.positions.lock().await.get(source).cloned())
+    }
+
+    async fn save(&self, source: &str, position: &Position) -> Result<(), Error> {
+        let mut positions = self.positions.lock().await;
+        positions.insert(source.to_string(), position.clone());
+        self.write(&positions).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn saves_and_reloads_positions() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("checkpoints.json");
+
+        let store = FileStore::open(&path).await.unwrap();
+        assert_eq!(store.load("orders").await.unwrap(), None);
+        store
+            .save(
+                "orders",
+                &Position::Lsn {
+                    lsn: 0x16_B374_D848,
+                },
+            )
+            .await
+            .unwrap();
+        store
+            .save(
+                "customers",
+                &Position::Keyset {
+                    key: vec!["42".to_string()],
+                },
+            )
+            .await
+            .unwrap();
+        store.save("orders", &Position::Finished).await.unwrap();
+
+        let reopened = FileStore::open(&path).await.unwrap();
+        assert_eq!(
+            reopened.load("orders").await.unwrap(),
+            Some(Position::Finished)
+        );
+        assert_eq!(
+            reopened.load("customers").await.unwrap(),
+            Some(Position::Keyset {
+                key: vec!["42".to_string()]
+            })
+        );
+        assert!(!directory.path().join("checkpoints.json.tmp").exists());
+    }
+
+    #[tokio::test]
+    async fn rejects_corrupt_files() {
+        let directory = tempfile::tempdir().unwrap();
+        let path = directory.path().join("checkpoints.json");
+        std::fs::write(&path, "{not json").unwrap();
+        let error = FileStore::open(&path).await.err().unwrap();
+        assert!(error.to_string().starts_with("corrupt checkpoint file"));
+    }
+}
@@ -0,0 +1,104 @@
+mod file;
+mod pg;
+mod tracker;
+
+use std::sync::Arc;
+
+use async_trait::async_trait;
+use config::CheckpointConfig;
+use serde_derive::{Deserialize, Serialize};
+use util::pg::Pools;
+
+pub use file::FileStore;
+pub use pg::PgStore;
+pub use tracker::Tracker;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+// Position is how far a source has read, in the terms of that source
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum Position {
+    // Lsn is a write-ahead log position of a Postgres replication slot
+    Lsn { lsn: u64 },
+    // Gtid is the set of MySQL transactions read from the binlog, as MySQL prints it
+    Gtid { gtid_set: String },
+    // Keyset is the key of the last row a table snapshot has read
+    Keyset { key: Vec<String> },
+    // Finished marks a snapshot that has read every row
+    Finished,
+    // Chunks is how far a parallel snapshot has read its key ranges
+    Chunks { chunks: ChunkProgress },
+    // FileOffset is a byte offset into a file
+    FileOffset { offset: u64 },
+    // KafkaOffsets is the next offset to read for each partition of a topic
+    KafkaOffsets { offsets: Vec<PartitionOffset> },
+    // StreamSequence is the sequence of the last message read from a JetStream stream
+    StreamSequence { sequence: u64 },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PartitionOffset {
+    pub partition: i32,
+    pub offset: i64,
+}
+
+// ChunkProgress lists the key ranges of a parallel snapshot, by the last key of
+// every range but the last one, and the ranges that have been read in full
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ChunkProgress {
+    pub bounds: Vec<Vec<String>>,
+    pub finished: Vec<usize>,
+}
+
+// CheckpointStore keeps the last acknowledged position of every source, by source name
+#[async_trait]
+pub trait CheckpointStore: Send + Sync {
+    async fn load(&self, source: &str) -> Result<Option<Position>, Error>;
+    async fn save(&self, source: &str, position: &Position) -> Result<(), Error>;
+}
+
+// open creates the store a configuration asks for. A Postgres store shares its
+// connector's connections in `pools`.
+pub async fn open(
+    config: &CheckpointConfig,
+    pools: &Pools,
+) -> Result<Arc<dyn CheckpointStore>, Error> {
+    match config {
+        CheckpointConfig::File { path } => Ok(Arc::new(FileStore::open(path).await?)),
+        CheckpointConfig::Postgres {
+            connector_name,
+            connector,
+            database,
+            table,
+        } => {
+            let pool = pools.get(connector_name, connector, database, 1)?;
+            Ok(Arc::new(PgStore::open(pool, table).await?))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn positions_are_tagged() {
+        let position = Position::KafkaOffsets {
+            offsets: vec![PartitionOffset {
+                partition: 3,
+                offset: 42,
+            }],
+        };
+        let json = serde_json::to_string(&position).unwrap();
+        assert_eq!(
+            json,
+            r#"{"kind":"kafka_offsets","offsets":[{"partition":3,"offset":42}]}"#
+        );
+        assert_eq!(serde_json::from_str::<Position>(&json).unwrap(), position);
+        assert_eq!(
+            serde_json::to_string(&Position::Finished).unwrap(),
+            r#"{"kind":"finished"}"#
+        );
+    }
+}
@@ -0,0 +1,74 @@
+use async_trait::async_trait;
+use deadpool_postgres::Pool;
+
+use crate::{CheckpointStore, Error, Position};
+
+// PgStore keeps checkpoints in a Postgres table, one row per source, for
+// deployments where local disk does not survive a restart
+pub struct PgStore {
+    pool: Pool,
+    table: String,
+}
+
+impl PgStore {
+    // open creates the checkpoint table in the database `pool` connects to, if
+    // needed
+    pub async fn open(pool: Pool, table: &str) -> Result<Self, Error> {
+        let store = PgStore {
+            pool,
+            table: format!("\"{}\"", table.replace('"', "\"\"")),
+        };
+        store
+            .pool
+            .get()
+            .await?
+            .batch_execute(&format!(
+                "CREATE TABLE IF NOT EXISTS {} (\
+                 source text PRIMARY KEY, \
+                 position jsonb NOT NULL, \
+                 updated_at timestamptz NOT NULL DEFAULT now())",
+                store.table
+            ))
+            .await?;
+        Ok(store)
+    }
+}
+
+#[async_trait]
+impl CheckpointStore for PgStore {
+    async fn load(&self, source: &str) -> Result<Option<Position>, Error> {
+        let row = self
+            .pool
+            .get()
+            .await?
+            .query_opt(
+                &format!(
+                    "SELECT position::text FROM {} WHERE source = $1",
+                    self.table
+                ),
+                &[&source],
+            )
+            .await?;
+        match row {
+            Some(row) => Ok(Some(serde_json::from_str(row.get(0))?)),
+            None => Ok(None),
+        }
+    }
+
+    async fn save(&self, source: &str, position: &Position) -> Result<(), Error> {
+        self.pool
+            .get()
+            .await?
+            .execute(
+                &format!(
+                    "INSERT INTO {} (source, position) VALUES ($1, $2::text::jsonb) \
+                     ON CONFLICT (source) DO UPDATE \
+                     SET position = excluded.position, updated_at = now()",
+                    self.table
+                ),
+                &[&source, &serde_json::to_string(position)?],
+            )
+            .await?;
+        Ok(())
+    }
+}
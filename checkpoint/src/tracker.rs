@@ -0,0 +1,101 @@
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use crate::{CheckpointStore, Error, Position};
+
+// Tracker records a source's position only once everything read before it has been
+// acknowledged. Batches are tracked in the order the source produced them and may
+// be acknowledged in any order; the checkpoint only ever moves to the end of the
+// longest fully acknowledged prefix, which is what at-least-once delivery needs.
+pub struct Tracker {
+    store: Arc<dyn CheckpointStore>,
+    source: String,
+    next: u64,
+    pending: BTreeMap<u64, (Position, bool)>,
+}
+
+impl Tracker {
+    pub fn new(store: Arc<dyn CheckpointStore>, source: impl Into<String>) -> Self {
+        Tracker {
+            store,
+            source: source.into(),
+            next: 0,
+            pending: BTreeMap::new(),
+        }
+    }
+
+    // resume returns the position to restart the source from
+    pub async fn resume(&self) -> Result<Option<Position>, Error> {
+        self.store.load(&self.source).await
+    }
+
+    // track registers a batch that ends at `position` and returns its sequence number
+    pub fn track(&mut self, position: Position) -> u64 {
+        let sequence = self.next;
+        self.next += 1;
+        self.pending.insert(sequence, (position, false));
+        sequence
+    }
+
+    // ack marks a batch as delivered by every sink and, if that completes a prefix,
+    // saves the new checkpoint and returns it
+    pub async fn ack(&mut self, sequence: u64) -> Result<Option<Position>, Error> {
+        match self.pending.get_mut(&sequence) {
+            Some((_, acked)) => *acked = true,
+            None => return Err(format!("unknown batch {} for `{}`", sequence, self.source).into()),
+        }
+        let mut committed = None;
+        while let Some(entry) = self.pending.first_entry() {
+            if !entry.get().1 {
+                break;
+            }
+            committed = Some(entry.remove().0);
+        }
+        if let Some(position) = &committed {
+            self.store.save(&self.source, position).await?;
+        }
+        Ok(committed)
+    }
+
+    // pending is the number of batches still waiting for an acknowledgement
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::FileStore;
+
+    fn lsn(lsn: u64) -> Position {
+        Position::Lsn { lsn }
+    }
+
+    #[tokio::test]
+    async fn commits_the_acknowledged_prefix() {
+        let directory = tempfile::tempdir().unwrap();
+        let store = Arc::new(
+            FileStore::open(directory.path().join("checkpoints.json"))
+                .await
+                .unwrap(),
+        );
+        let mut tracker = Tracker::new(store.clone(), "orders");
+        assert_eq!(tracker.resume().await.unwrap(), None);
+
+        let first = tracker.track(lsn(10));
+        let second = tracker.track(lsn(20));
+        let third = tracker.track(lsn(30));
+
+        // out of order: nothing can be committed until the first batch is delivered
+        assert_eq!(tracker.ack(second).await.unwrap(), None);
+        assert_eq!(store.load("orders").await.unwrap(), None);
+
+        assert_eq!(tracker.ack(first).await.unwrap(), Some(lsn(20)));
+        assert_eq!(tracker.resume().await.unwrap(), Some(lsn(20)));
+        assert_eq!(tracker.pending(), 1);
+
+        assert_eq!(tracker.ack(third).await.unwrap(), Some(lsn(30)));
+        assert!(tracker.ack(third).await.is_err());
+    }
+}
@@ -0,0 +1,133 @@
+use std::collections::HashMap;
+
+use serde_derive::Deserialize;
+use toml::{Table, Value};
+
+use crate::config::{ConnectorConfig, RdsConfig};
+use crate::document::{child, key_path, Document};
+use crate::error::ConfigError;
+
+// DEFAULT_CHECKPOINT_PATH is where checkpoints are kept when no store is configured
+pub const DEFAULT_CHECKPOINT_PATH: &str = "fust.checkpoints.json";
+const DEFAULT_CHECKPOINT_TABLE: &str = "fust_checkpoints";
+
+// CheckpointConfig selects where the positions sources have reached are recorded
+#[derive(Debug, Clone)]
+pub enum CheckpointConfig {
+    File {
+        path: String,
+    },
+    Postgres {
+        // connector_name is the key of `connector` in the `[connectors]` section
+        connector_name: String,
+        connector: Box<RdsConfig>,
+        database: String,
+        table: String,
+    },
+}
+
+impl Default for CheckpointConfig {
+    fn default() -> Self {
+        CheckpointConfig::File {
+            path: DEFAULT_CHECKPOINT_PATH.to_string(),
+        }
+    }
+}
+
+// CheckpointTable is the `[checkpoint]` section as written
+#[derive(Debug, Deserialize)]
+struct CheckpointTable {
+    #[serde(default = "file_store")]
+    store: String,
+    path: Option<String>,
+    connector: Option<String>,
+    database: Option<String>,
+    table: Option<String>,
+}
+
+fn file_store() -> String {
+    "file".to_string()
+}
+
+pub(crate) fn from_checkpoint(
+    document: &Document,
+    root: &Table,
+    connectors: &HashMap<String, ConnectorConfig>,
+    errors: &mut Vec<ConfigError>,
+) -> CheckpointConfig {
+    let Some(value) = root.get("checkpoint") else {
+        return CheckpointConfig::default();
+    };
+    let path = key_path(&["checkpoint"]);
+    let checkpoint: CheckpointTable = match document.deserialize(value.clone(), &path) {
+        Ok(checkpoint) => checkpoint,
+        Err(e) => {
+            errors.push(e);
+            return CheckpointConfig::default();
+        }
+    };
+
+    match checkpoint.store.as_str() {
+        "file" => CheckpointConfig::File {
+            path: checkpoint
+                .path
+                .unwrap_or_else(|| DEFAULT_CHECKPOINT_PATH.to_string()),
+        },
+        "postgres" => {
+            let missing = |key: &str, errors: &mut Vec<ConfigError>| {
+                errors.push(ConfigError::MissingKey {
+                    location: document.locate(&child(&path, key)),
+                });
+            };
+            let Some(connector_name) = checkpoint.connector else {
+                missing("connector", errors);
+                return CheckpointConfig::default();
+            };
+            let Some(database) = checkpoint.database else {
+                missing("database", errors);
+                return CheckpointConfig::default();
+            };
+            match connectors.get(&connector_name) {
+                Some(ConnectorConfig::Postgres(connector)) => CheckpointConfig::Postgres {
+                    connector_name: connector_name.clone(),
+                    connector: Box::new(connector.clone()),
+                    database,
+                    table: checkpoint
+                        .table
+                        .unwrap_or_else(|| DEFAULT_CHECKPOINT_TABLE.to_string()),
+                },
+                Some(other) => {
+                    errors.push(document.value_error(
+                        &child(&path, "connector"),
+                        &format!("a {} connector cannot store checkpoints", other.kind()),
+                    ));
+                    CheckpointConfig::default()
+                }
+                None => {
+                    let declared = document
+                        .table
+                        .get("connectors")
+                        .and_then(Value::as_table)
+                        .is_some_and(|t| t.contains_key(&connector_name));
+                    if !declared {
+                        errors.push(ConfigError::UnknownConnector {
+                            location: document.locate(&child(&path, "connector")),
+                            connector: connector_name,
+                        });
+                    }
+                    CheckpointConfig::default()
+                }
+            }
+        }
+        other => {
+            errors.push(document.value_error(
+                &child(&path, "store"),
+                &format!(
+                    "unknown checkpoint store `{}`, expected `file` or `postgres`",
+                    other
+                ),
+            ));
+            CheckpointConfig::default()
+        }
+    }
+}
@@ -0,0 +1,249 @@
+use std::{cell::RefCell, fmt::Write, fs::read_to_string, ops::Range};
+
+use serde::de::DeserializeOwned;
+use toml::{Table, Value};
+use toml_edit::{ImDocument, Item};
+
+use crate::error::{ConfigError, Location};
+
+// Segment is one step of a key path such as `sources.mysrc1.fields[0]`
+#[derive(Debug, Clone, PartialEq)]
+pub(crate) enum Segment {
+    Key(String),
+    Index(usize),
+}
+
+pub(crate) type KeyPath = Vec<Segment>;
+
+pub(crate) fn key_path(keys: &[&str]) -> KeyPath {
+    keys.iter().map(|k| Segment::Key(k.to_string())).collect()
+}
+
+pub(crate) fn child(path: &[Segment], key: &str) -> KeyPath {
+    let mut path = path.to_vec();
+    path.push(Segment::Key(key.to_string()));
+    path
+}
+
+pub(crate) fn format_key(path: &[Segment]) -> String {
+    let mut key = String::new();
+    for segment in path {
+        match segment {
+            Segment::Key(k) => {
+                if !key.is_empty() {
+                    key.push('.');
+                }
+                key.push_str(k);
+            }
+            Segment::Index(i) => {
+                let _ = write!(key, "[{}]", i);
+            }
+        }
+    }
+    key
+}
+
+// Document is a parsed configuration file that remembers where every key was written
+pub(crate) struct Document {
+    file: String,
+    pub table: Table,
+    spans: ImDocument<String>,
+    ignored: RefCell<Vec<KeyPath>>,
+}
+
+impl Document {
+    pub fn read(file: &str) -> Result<Self, ConfigError> {
+        let contents = read_to_string(file).map_err(|source| ConfigError::Io {
+            file: file.to_string(),
+            source,
+        })?;
+        Self::parse(file, contents)
+    }
+
+    pub fn parse(file: &str, contents: String) -> Result<Self, ConfigError> {
+        let table: Table = toml::from_str(&contents).map_err(|e| {
+            let (line, column) = e
+                .span()
+                .map(|span| line_column(&contents, span.start))
+                .unwrap_or((1, 1));
+            ConfigError::Syntax {
+                location: Location {
+                    file: file.to_string(),
+                    key: String::new(),
+                    line,
+                    column,
+                },
+                message: e.message().to_string(),
+            }
+        })?;
+        let spans = ImDocument::parse(contents).map_err(|e| ConfigError::Syntax {
+            location: Location {
+                file: file.to_string(),
+                key: String::new(),
+                line: 1,
+                column: 1,
+            },
+            message: e.message().to_string(),
+        })?;
+        Ok(Document {
+            file: file.to_string(),
+            table,
+            spans,
+            ignored: RefCell::new(Vec::new()),
+        })
+    }
+
+    // locate resolves a key path to a line and column; keys that are absent
+    // resolve to the closest enclosing table that does exist
+    pub fn locate(&self, path: &[Segment]) -> Location {
+        let (line, column) = self
+            .span(path)
+            .map(|span| line_column(self.spans.raw(), span.start))
+            .unwrap_or((1, 1));
+        Location {
+            file: self.file.clone(),
+            key: format_key(path),
+            line,
+            column,
+        }
+    }
+
+    fn span(&self, path: &[Segment]) -> Option<Range<usize>> {
+        let mut best = None;
+        let mut node = Node::Item(self.spans.as_item());
+        for segment in path {
+            let Some((key_span, next)) = node.step(segment) else {
+                break;
+            };
+            // a `[table]` header reads better than the last key of a dotted header
+            let span = match next {
+                Node::Item(Item::Table(table)) => table.span().or(key_span),
+                _ => key_span.or_else(|| next.span()),
+            };
+            if let Some(span) = span {
+                best = Some(span);
+            }
+            node = next;
+        }
+        best
+    }
+
+    // deserialize converts a TOML value into a typed configuration struct,
+    // reporting the full key path of whatever went wrong and remembering
+    // every key the struct did not use
+    pub fn deserialize<T: DeserializeOwned>(
+        &self,
+        value: Value,
+        path: &[Segment],
+    ) -> Result<T, ConfigError> {
+        let mut ignored = Vec::new();
+        let result = serde_path_to_error::deserialize(serde_ignored::Deserializer::new(
+            value,
+            &mut |unused: serde_ignored::Path| {
+                let mut key = path.to_vec();
+                ignored_path(&unused, &mut key);
+                ignored.push(key);
+            },
+        ));
+        self.ignored.borrow_mut().extend(ignored);
+        result.map_err(|e| {
+            let mut path = path.to_vec();
+            for segment in e.path() {
+                match segment {
+                    serde_path_to_error::Segment::Map { key } => {
+                        path.push(Segment::Key(key.clone()))
+                    }
+                    serde_path_to_error::Segment::Seq { index } => {
+                        path.push(Segment::Index(*index))
+                    }
+                    _ => {}
+                }
+            }
+            self.value_error(&path, e.into_inner().message())
+        })
+    }
+
+    // ignored lists the keys that typed structs skipped over while loading
+    pub fn ignored(&self) -> Vec<KeyPath> {
+        self.ignored.borrow().clone()
+    }
+
+    pub fn value_error(&self, path: &[Segment], message: &str) -> ConfigError {
+        // serde reports a missing field on the enclosing struct, so point at the key itself
+        if let Some(field) = message
+            .strip_prefix("missing field `")
+            .and_then(|m| m.strip_suffix('`'))
+        {
+            let location = Location {
+                key: format_key(&child(path, field)),
+                ..self.locate(path)
+            };
+            return ConfigError::MissingKey { location };
+        }
+        ConfigError::InvalidValue {
+            location: self.locate(path),
+            message: message.to_string(),
+        }
+    }
+}
+
+// Node is any position in the span-preserving document a key path can walk through
+enum Node<'a> {
+    Item(&'a Item),
+    Value(&'a toml_edit::Value),
+    Table(&'a toml_edit::Table),
+}
+
+impl<'a> Node<'a> {
+    fn step(&self, segment: &Segment) -> Option<(Option<Range<usize>>, Node<'a>)> {
+        match (self, segment) {
+            (Node::Item(Item::Value(value)), _) => Node::Value(value).step(segment),
+            (Node::Item(Item::Table(table)), _) => Node::Table(table).step(segment),
+            (Node::Item(Item::ArrayOfTables(array)), Segment::Index(i)) => {
+                array.get(*i).map(|t| (t.span(), Node::Table(t)))
+            }
+            (Node::Table(table), Segment::Key(key)) => table
+                .get_key_value(key)
+                .map(|(k, v)| (k.span(), Node::Item(v))),
+            (Node::Value(toml_edit::Value::InlineTable(table)), Segment::Key(key)) => table
+                .get_key_value(key)
+                .map(|(k, v)| (k.span(), Node::Item(v))),
+            (Node::Value(toml_edit::Value::Array(array)), Segment::Index(i)) => {
+                array.get(*i).map(|v| (v.span(), Node::Value(v)))
+            }
+            _ => None,
+        }
+    }
+
+    fn span(&self) -> Option<Range<usize>> {
+        match self {
+            Node::Item(item) => item.span(),
+            Node::Value(value) => value.span(),
+            Node::Table(table) => table.span(),
+        }
+    }
+}
+
+fn ignored_path(path: &serde_ignored::Path, key: &mut KeyPath) {
+    match path {
+        serde_ignored::Path::Root => {}
+        serde_ignored::Path::Seq { parent, index } => {
+            ignored_path(parent, key);
+            key.push(Segment::Index(*index));
+        }
+        serde_ignored::Path::Map { parent, key: name } => {
+            ignored_path(parent, key);
+            key.push(Segment::Key(name.clone()));
+        }
+        serde_ignored::Path::Some { parent }
+        | serde_ignored::Path::NewtypeStruct { parent }
+        | serde_ignored::Path::NewtypeVariant { parent } => ignored_path(parent, key),
+    }
+}
+
+fn line_column(contents: &str, offset: usize) -> (usize, usize) {
+    let before = &contents[..offset.min(contents.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.rsplit('\n').next().unwrap_or("").chars().count() + 1;
+    (line, column)
+}
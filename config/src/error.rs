@@ -0,0 +1,185 @@
+use std::{error::Error, fmt, io};
+
+// Location points at a key inside a configuration file
+#[derive(Debug, Clone, PartialEq)]
+pub struct Location {
+    pub file: String,
+    pub key: String,
+    pub line: usize,
+    pub column: usize,
+}
+
+impl fmt::Display for Location {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}", self.file, self.line, self.column)?;
+        if !self.key.is_empty() {
+            write!(f, " `{}`", self.key)?;
+        }
+        Ok(())
+    }
+}
+
+// ConfigError is a single problem found while loading a configuration file
+#[derive(Debug)]
+pub enum ConfigError {
+    Io {
+        file: String,
+        source: io::Error,
+    },
+    Syntax {
+        location: Location,
+        message: String,
+    },
+    MissingKey {
+        location: Location,
+    },
+    InvalidValue {
+        location: Location,
+        message: String,
+    },
+    UnknownConnector {
+        location: Location,
+        connector: String,
+    },
+}
+
+impl ConfigError {
+    pub fn location(&self) -> Option<&Location> {
+        match self {
+            ConfigError::Io { .. } => None,
+            ConfigError::Syntax { location, .. }
+            | ConfigError::MissingKey { location }
+            | ConfigError::InvalidValue { location, .. }
+            | ConfigError::UnknownConnector { location, .. } => Some(location),
+        }
+    }
+}
+
+impl fmt::Display for ConfigError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigError::Io { file, source } => write!(f, "{}: {}", file, source),
+            ConfigError::Syntax { location, message } => {
+                write!(f, "{}: syntax error: {}", location, message)
+            }
+            ConfigError::MissingKey { location } => {
+                write!(f, "{}: missing required key", location)
+            }
+            ConfigError::InvalidValue { location, message } => {
+                write!(f, "{}: {}", location, message)
+            }
+            ConfigError::UnknownConnector {
+                location,
+                connector,
+            } => write!(f, "{}: unknown connector `{}`", location, connector),
+        }
+    }
+}
+
+impl Error for ConfigError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            ConfigError::Io { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+// ConfigErrors collects every problem found in one pass over a configuration file
+#[derive(Debug)]
+pub struct ConfigErrors(pub Vec<ConfigError>);
+
+impl ConfigErrors {
+    pub fn iter(&self) -> std::slice::Iter<'_, ConfigError> {
+        self.0.iter()
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl From<ConfigError> for ConfigErrors {
+    fn from(error: ConfigError) -> Self {
+        ConfigErrors(vec![error])
+    }
+}
+
+impl IntoIterator for ConfigErrors {
+    type Item = ConfigError;
+    type IntoIter = std::vec::IntoIter<ConfigError>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.0.into_iter()
+    }
+}
+
+impl fmt::Display for ConfigErrors {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, error) in self.0.iter().enumerate() {
+            if i > 0 {
+                writeln!(f)?;
+            }
+            write!(f, "{}", error)?;
+        }
+        Ok(())
+    }
+}
+
+impl Error for ConfigErrors {}
+
+// ConfigWarning is a problem that does not stop a configuration from loading
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigWarning {
+    UnknownKey {
+        location: Location,
+        context: String,
+    },
+    UnusedConnector {
+        location: Location,
+        connector: String,
+    },
+    UnusedStage {
+        location: Location,
+        stage: String,
+    },
+}
+
+impl ConfigWarning {
+    pub fn location(&self) -> &Location {
+        match self {
+            ConfigWarning::UnknownKey { location, .. }
+            | ConfigWarning::UnusedConnector { location, .. }
+            | ConfigWarning::UnusedStage { location, .. } => location,
+        }
+    }
+}
+
+impl fmt::Display for ConfigWarning {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ConfigWarning::UnknownKey { location, context } => {
+                write!(
+                    f,
+                    "{}: unknown {} setting, it will be ignored",
+                    location, context
+                )
+            }
+            ConfigWarning::UnusedConnector {
+                location,
+                connector,
+            } => write!(
+                f,
+                "{}: connector `{}` is not used by any source or sink",
+                location, connector
+            ),
+            ConfigWarning::UnusedStage { location, stage } => {
+                write!(f, "{}: {} is not part of any pipeline", location, stage)
+            }
+        }
+    }
+}
@@ -1,5 +1,10 @@
+use std::{fmt, str::FromStr};
+
+use serde_derive::Deserialize;
+
 // Field type enums definations
-#[derive(Debug, Clone, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Deserialize)]
+#[serde(try_from = "String")]
 pub enum FieldType {
     String,
     Number,
@@ -7,23 +12,17 @@ pub enum FieldType {
     Date,
     Object,
     Array,
+    Int,
+    BigInt,
+    Decimal,
+    Timestamp,
+    Uuid,
+    Json,
+    Bytes,
 }
 
 impl FieldType {
-
-    fn of(value: String) -> Self {
-        match value.to_lowercase().as_str() {
-            "string" => FieldType::String,
-            "number" => FieldType::Number,
-            "boolean" => FieldType::Boolean,
-            "date" => FieldType::Date,
-            "object" => FieldType::Object,
-            "array" => FieldType::Array,
-            _ => panic!("Invalid field type"),
-        }
-    }
-
-    fn string(&self) -> &str {
+    pub fn string(&self) -> &str {
         match self {
             FieldType::String => "string",
             FieldType::Number => "number",
@@ -31,14 +30,65 @@ impl FieldType {
             FieldType::Date => "date",
             FieldType::Object => "object",
             FieldType::Array => "array",
+            FieldType::Int => "int",
+            FieldType::BigInt => "bigint",
+            FieldType::Decimal => "decimal",
+            FieldType::Timestamp => "timestamp",
+            FieldType::Uuid => "uuid",
+            FieldType::Json => "json",
+            FieldType::Bytes => "bytes",
         }
     }
 }
 
-#[derive(Debug, Clone)]
+impl FromStr for FieldType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value.to_lowercase().as_str() {
+            "string" | "text" | "varchar" | "char" => Ok(FieldType::String),
+            "number" | "float" | "double" | "real" => Ok(FieldType::Number),
+            "boolean" | "bool" => Ok(FieldType::Boolean),
+            "date" => Ok(FieldType::Date),
+            "object" => Ok(FieldType::Object),
+            "array" => Ok(FieldType::Array),
+            "int" | "integer" | "smallint" | "int2" | "int4" => Ok(FieldType::Int),
+            "bigint" | "long" | "int8" => Ok(FieldType::BigInt),
+            "decimal" | "numeric" => Ok(FieldType::Decimal),
+            "timestamp" | "timestamptz" | "datetime" => Ok(FieldType::Timestamp),
+            "uuid" => Ok(FieldType::Uuid),
+            "json" | "jsonb" => Ok(FieldType::Json),
+            "bytes" | "bytea" | "binary" | "blob" => Ok(FieldType::Bytes),
+            _ => Err(format!("Invalid field type `{}`", value)),
+        }
+    }
+}
+
+impl TryFrom<String> for FieldType {
+    type Error = String;
+
+    fn try_from(value: String) -> Result<Self, Self::Error> {
+        value.parse()
+    }
+}
+
+impl fmt::Display for FieldType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.string())
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
 pub struct Field {
     pub name: String,
+    #[serde(rename = "type")]
     pub field_type: FieldType,
+    #[serde(default = "nullable")]
+    pub nullable: bool,
+}
+
+fn nullable() -> bool {
+    true
 }
 
 #[cfg(test)]
@@ -68,17 +118,22 @@ mod tests {
 
     #[test]
     fn test_of() {
-        assert_eq!(FieldType::of("String".to_string()), FieldType::String);
-        assert_eq!(FieldType::of("Number".to_string()), FieldType::Number);
-        assert_eq!(FieldType::of("Boolean".to_string()), FieldType::Boolean);
-        assert_eq!(FieldType::of("Date".to_string()), FieldType::Date);
-        assert_eq!(FieldType::of("Object".to_string()), FieldType::Object);
-        assert_eq!(FieldType::of("Array".to_string()), FieldType::Array);
+        assert_eq!("String".parse(), Ok(FieldType::String));
+        assert_eq!("Number".parse(), Ok(FieldType::Number));
+        assert_eq!("Boolean".parse(), Ok(FieldType::Boolean));
+        assert_eq!("Date".parse(), Ok(FieldType::Date));
+        assert_eq!("Object".parse(), Ok(FieldType::Object));
+        assert_eq!("Array".parse(), Ok(FieldType::Array));
+        assert_eq!("int".parse(), Ok(FieldType::Int));
+        assert_eq!("text".parse(), Ok(FieldType::String));
+        assert_eq!("jsonb".parse(), Ok(FieldType::Json));
     }
 
     #[test]
-    #[should_panic(expected = "Invalid field type")]
-    fn test_of_panic() {
-        FieldType::of("Invalid".to_string());
+    fn test_of_invalid() {
+        assert_eq!(
+            "Invalid".parse::<FieldType>(),
+            Err("Invalid field type `Invalid`".to_string())
+        );
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,158 @@
+use std::fs::read_to_string;
+
+use toml::{Table, Value};
+
+use crate::document::{child, Document, Segment};
+use crate::error::ConfigError;
+
+// resolve replaces `${ENV_VAR}`, `${ENV_VAR:-default}` and `${file:/path}` references
+// in every string value of `table`; `$${` escapes a literal `${`
+pub(crate) fn resolve(document: &Document, table: &mut Table, errors: &mut Vec<ConfigError>) {
+    let env = |name: &str| std::env::var(name).ok();
+    resolve_table(document, table, &[], &env, errors);
+}
+
+fn resolve_table(
+    document: &Document,
+    table: &mut Table,
+    path: &[Segment],
+    env: &dyn Fn(&str) -> Option<String>,
+    errors: &mut Vec<ConfigError>,
+) {
+    for (key, value) in table.iter_mut() {
+        resolve_value(document, value, &child(path, key), env, errors);
+    }
+}
+
+fn resolve_value(
+    document: &Document,
+    value: &mut Value,
+    path: &[Segment],
+    env: &dyn Fn(&str) -> Option<String>,
+    errors: &mut Vec<ConfigError>,
+) {
+    match value {
+        Value::String(s) => match interpolate(s, env) {
+            Ok(resolved) => *s = resolved,
+            Err(message) => errors.push(document.value_error(path, &message)),
+        },
+        Value::Array(values) => {
+            for (i, value) in values.iter_mut().enumerate() {
+                let mut path = path.to_vec();
+                path.push(Segment::Index(i));
+                resolve_value(document, value, &path, env, errors);
+            }
+        }
+        Value::Table(table) => resolve_table(document, table, path, env, errors),
+        _ => {}
+    }
+}
+
+// interpolate expands the references in a single string
+pub(crate) fn interpolate(
+    input: &str,
+    env: &dyn Fn(&str) -> Option<String>,
+) -> Result<String, String> {
+    let mut output = String::with_capacity(input.len());
+    let mut rest = input;
+    while let Some(start) = rest.find('$') {
+        output.push_str(&rest[..start]);
+        rest = &rest[start..];
+        if let Some(escaped) = rest.strip_prefix("$${") {
+            output.push_str("${");
+            rest = escaped;
+        } else if let Some(reference) = rest.strip_prefix("${") {
+            // the value may be a secret, so only where the reference starts is told
+            let end = reference.find('}').ok_or_else(|| {
+                format!(
+                    "unterminated `${{` reference at offset {}",
+                    input.len() - rest.len()
+                )
+            })?;
+            output.push_str(&lookup(&reference[..end], env)?);
+            rest = &reference[end + 1..];
+        } else {
+            output.push('$');
+            rest = &rest[1..];
+        }
+    }
+    output.push_str(rest);
+    Ok(output)
+}
+
+fn lookup(reference: &str, env: &dyn Fn(&str) -> Option<String>) -> Result<String, String> {
+    if let Some(path) = reference.strip_prefix("file:") {
+        let contents = read_to_string(path)
+            .map_err(|e| format!("cannot read secret file `{}`: {}", path, e))?;
+        // secret files usually end with a newline that is not part of the secret
+        let trimmed = contents.strip_suffix('\n').unwrap_or(&contents);
+        return Ok(trimmed.strip_suffix('\r').unwrap_or(trimmed).to_string());
+    }
+    let (name, default) = match reference.split_once(":-") {
+        Some((name, default)) => (name, Some(default)),
+        None => (reference, None),
+    };
+    if name.is_empty() {
+        return Err("empty variable name in `${}`".to_string());
+    }
+    match (env(name), default) {
+        (Some(value), _) => Ok(value),
+        (None, Some(default)) => Ok(default.to_string()),
+        (None, None) => Err(format!("environment variable `{}` is not set", name)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+
+    fn env(name: &str) -> Option<String> {
+        match name {
+            "PG_USER" => Some("replicator".to_string()),
+            _ => None,
+        }
+    }
+
+    #[test]
+    fn interpolate_environment_variables() {
+        assert_eq!(
+            interpolate("${PG_USER}", &env),
+            Ok("replicator".to_string())
+        );
+        assert_eq!(
+            interpolate("user=${PG_USER};host=${PG_HOST:-localhost}", &env),
+            Ok("user=replicator;host=localhost".to_string())
+        );
+        assert_eq!(interpolate("${PG_PORT:-}", &env), Ok(String::new()));
+        assert_eq!(
+            interpolate("${PG_PASSWORD}", &env),
+            Err("environment variable `PG_PASSWORD` is not set".to_string())
+        );
+    }
+
+    #[test]
+    fn interpolate_escapes_and_plain_dollars() {
+        assert_eq!(
+            interpolate("$${PG_USER}", &env),
+            Ok("${PG_USER}".to_string())
+        );
+        assert_eq!(interpolate("cost: $5", &env), Ok("cost: $5".to_string()));
+        assert_eq!(
+            interpolate("pa$$w0rd${PG_USER", &env),
+            Err("unterminated `${` reference at offset 8".to_string())
+        );
+    }
+
+    #[test]
+    fn interpolate_secret_files() {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        writeln!(file, "s3cr3t").unwrap();
+        let reference = format!("${{file:{}}}", file.path().display());
+        assert_eq!(interpolate(&reference, &env), Ok("s3cr3t".to_string()));
+
+        assert!(interpolate("${file:/nonexistent/secret}", &env)
+            .unwrap_err()
+            .starts_with("cannot read secret file `/nonexistent/secret`"));
+    }
+}
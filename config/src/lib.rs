@@ -1,5 +1,22 @@
+pub mod checkpoint;
 pub mod config;
+mod document;
+pub mod error;
 pub mod field;
+mod interpolate;
+pub mod pipeline;
+pub mod retry;
+pub mod secret;
+pub mod validate;
 
-pub use config::ConfigSpec;
-pub use field::{FieldType};
\ No newline at end of file
+pub use checkpoint::CheckpointConfig;
+pub use config::{
+    ConfigSpec, ConnectorConfig, KafkaConfig, MysqlConfig, NatsConfig, RdsConfig, SinkConfig,
+    SourceConfig, SqliteConfig, SslMode,
+};
+pub use error::{ConfigError, ConfigErrors, ConfigWarning};
+pub use field::{Field, FieldType};
+pub use pipeline::{PipelineConfig, ProcessorConfig, Stage};
+pub use retry::{OnUnhealthy, RetryConfig};
+pub use secret::Secret;
+pub use validate::{validate_file, Validation};
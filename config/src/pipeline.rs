@@ -0,0 +1,92 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_derive::Deserialize;
+
+// Stage is one source, processor or sink a pipeline runs
+#[derive(Debug, Clone, PartialEq, Eq, Hash, PartialOrd, Ord)]
+pub enum Stage {
+    Source(String),
+    Processor(String),
+    Sink(String),
+}
+
+impl Stage {
+    pub fn name(&self) -> &str {
+        match self {
+            Stage::Source(name) | Stage::Processor(name) | Stage::Sink(name) => name,
+        }
+    }
+}
+
+impl fmt::Display for Stage {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Stage::Source(name) => write!(f, "source `{}`", name),
+            Stage::Processor(name) => write!(f, "processor `{}`", name),
+            Stage::Sink(name) => write!(f, "sink `{}`", name),
+        }
+    }
+}
+
+// ProcessorConfig is the configuration for a processor stage
+#[derive(Debug, Clone)]
+pub struct ProcessorConfig {
+    pub processor_type: String,
+    pub config: HashMap<String, String>,
+}
+
+// PipelineConfig declares a pipeline that runs one or more sources through an
+// ordered chain of processors into one or more sinks
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct PipelineConfig {
+    pub sources: Vec<String>,
+    #[serde(default)]
+    pub processors: Vec<String>,
+    pub sinks: Vec<String>,
+}
+
+impl PipelineConfig {
+    pub fn stages(&self) -> Vec<Stage> {
+        let mut stages = vec![];
+        stages.extend(self.sources.iter().cloned().map(Stage::Source));
+        stages.extend(self.processors.iter().cloned().map(Stage::Processor));
+        stages.extend(self.sinks.iter().cloned().map(Stage::Sink));
+        stages
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pipeline(sources: &[&str], processors: &[&str], sinks: &[&str]) -> PipelineConfig {
+        let owned = |names: &[&str]| names.iter().map(|n| n.to_string()).collect();
+        PipelineConfig {
+            sources: owned(sources),
+            processors: owned(processors),
+            sinks: owned(sinks),
+        }
+    }
+
+    fn source(name: &str) -> Stage {
+        Stage::Source(name.to_string())
+    }
+
+    fn processor(name: &str) -> Stage {
+        Stage::Processor(name.to_string())
+    }
+
+    fn sink(name: &str) -> Stage {
+        Stage::Sink(name.to_string())
+    }
+
+    #[test]
+    fn stages_run_from_sources_to_sinks() {
+        let pipeline = pipeline(&["a", "b"], &["mask"], &["x"]);
+        assert_eq!(
+            pipeline.stages(),
+            vec![source("a"), source("b"), processor("mask"), sink("x")]
+        );
+    }
+}
@@ -0,0 +1,78 @@
+use serde_derive::Deserialize;
+
+// RetryConfig is how a connector retries calls that fail, and what happens once
+// it gives up. It is the optional `[connectors.<name>.retry]` table; every key
+// has a default.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default)]
+pub struct RetryConfig {
+    // initial_backoff_ms is how long the first retry waits
+    pub initial_backoff_ms: u64,
+    // max_backoff_ms caps how long any retry waits
+    pub max_backoff_ms: u64,
+    // multiplier grows the wait after every failed attempt
+    pub multiplier: f64,
+    // jitter randomizes each wait by up to this fraction of it, so connectors
+    // that fail together do not retry together
+    pub jitter: f64,
+    // max_attempts is how many times a call is tried before giving up
+    pub max_attempts: u32,
+    // max_elapsed_ms is how long a call is retried for before giving up
+    pub max_elapsed_ms: u64,
+    // open_ms is how long a connector that gave up stays unhealthy before it is
+    // tried again, when its pipeline pauses
+    pub open_ms: u64,
+    // on_unhealthy is what a pipeline does once a connector gives up
+    pub on_unhealthy: OnUnhealthy,
+}
+
+// MAX_MULTIPLIER bounds how fast waits grow; anything past it reaches the cap
+// after a retry or two anyway
+const MAX_MULTIPLIER: f64 = 10.0;
+
+impl Default for RetryConfig {
+    fn default() -> Self {
+        RetryConfig {
+            initial_backoff_ms: 500,
+            max_backoff_ms: 30_000,
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: 10,
+            max_elapsed_ms: 300_000,
+            open_ms: 60_000,
+            on_unhealthy: OnUnhealthy::Pause,
+        }
+    }
+}
+
+impl RetryConfig {
+    // check reports the first setting that is out of range
+    pub fn check(&self) -> Result<(), String> {
+        if self.max_attempts == 0 {
+            return Err("max_attempts must be at least 1".to_string());
+        }
+        if !(1.0..=MAX_MULTIPLIER).contains(&self.multiplier) {
+            return Err(format!(
+                "multiplier must be between 1 and {}",
+                MAX_MULTIPLIER
+            ));
+        }
+        if !(0.0..=1.0).contains(&self.jitter) {
+            return Err("jitter must be between 0 and 1".to_string());
+        }
+        if self.initial_backoff_ms > self.max_backoff_ms {
+            return Err("initial_backoff_ms must not exceed max_backoff_ms".to_string());
+        }
+        Ok(())
+    }
+}
+
+// OnUnhealthy is what a pipeline does with a connector that gave up retrying
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum OnUnhealthy {
+    // Pause waits `open_ms` and tries the connector again, for as long as it takes
+    Pause,
+    // Fail stops the pipeline with the connector's error
+    Fail,
+}
@@ -0,0 +1,50 @@
+use std::fmt;
+
+use serde_derive::Deserialize;
+
+// Secret holds a credential that must never end up in logs or `Debug` output
+#[derive(Clone, PartialEq, Eq, Deserialize)]
+#[serde(transparent)]
+pub struct Secret(String);
+
+impl Secret {
+    pub fn new(value: impl Into<String>) -> Self {
+        Secret(value.into())
+    }
+
+    // expose returns the secret value, to be passed straight to the client that needs it
+    pub fn expose(&self) -> &str {
+        &self.0
+    }
+}
+
+impl From<String> for Secret {
+    fn from(value: String) -> Self {
+        Secret(value)
+    }
+}
+
+impl fmt::Debug for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("Secret(***)")
+    }
+}
+
+impl fmt::Display for Secret {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("***")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn secret_is_redacted() {
+        let secret = Secret::new("hunter2");
+        assert_eq!(format!("{:?}", secret), "Secret(***)");
+        assert_eq!(secret.to_string(), "***");
+        assert_eq!(secret.expose(), "hunter2");
+    }
+}
@@ -0,0 +1,477 @@
+use std::collections::HashSet;
+
+use toml::{Table, Value};
+
+use crate::config::{ConfigSpec, ConnectorConfig};
+use crate::document::{child, key_path, Document, Segment};
+use crate::error::{ConfigError, ConfigWarning};
+use crate::pipeline::Stage;
+
+// Validation is the outcome of checking a configuration file beyond parsing it
+#[derive(Debug)]
+pub struct Validation {
+    pub spec: Option<ConfigSpec>,
+    pub errors: Vec<ConfigError>,
+    pub warnings: Vec<ConfigWarning>,
+}
+
+impl Validation {
+    pub fn is_valid(&self) -> bool {
+        self.errors.is_empty()
+    }
+}
+
+// Settings lists the keys a source or sink understands for one connector family
+struct Settings {
+    required: &'static [&'static str],
+    optional: &'static [&'static str],
+}
+
+impl Settings {
+    fn knows(&self, key: &str) -> bool {
+        self.required.contains(&key) || self.optional.contains(&key)
+    }
+}
+
+const ROOT_KEYS: &[&str] = &[
+    "name",
+    "description",
+    "version",
+    "connectors",
+    "sources",
+    "sinks",
+    "processors",
+    "pipelines",
+    "checkpoint",
+];
+
+// source_settings returns None for connectors that cannot act as a source
+fn source_settings(connector: &ConnectorConfig) -> Option<Settings> {
+    match connector {
+        ConnectorConfig::Postgres(_) => Some(Settings {
+            required: &["database", "table"],
+            optional: &[
+                "schema",
+                "key",
+                "batch_size",
+                "mode",
+                "slot",
+                "publication",
+                "parallelism",
+            ],
+        }),
+        ConnectorConfig::Nats(_) => Some(Settings {
+            required: &[],
+            optional: &[
+                "topic",
+                "jetstream",
+                "stream",
+                "durable",
+                "codec",
+                "key",
+                "batch_size",
+                "on_error",
+            ],
+        }),
+        ConnectorConfig::Kafka(_) => Some(Settings {
+            required: &["topic", "group"],
+            optional: &["start", "codec", "key", "batch_size", "on_error"],
+        }),
+        ConnectorConfig::Sqlite(_) => Some(Settings {
+            required: &["table"],
+            optional: &["key", "batch_size", "watermark", "mode", "poll_interval_ms"],
+        }),
+        ConnectorConfig::Mysql(_) => Some(Settings {
+            required: &["database", "table"],
+            optional: &["key", "batch_size", "mode"],
+        }),
+        ConnectorConfig::Mssql(_) | ConnectorConfig::Oracle(_) => None,
+    }
+}
+
+// sink_settings returns None for connectors that cannot act as a sink
+fn sink_settings(connector: &ConnectorConfig) -> Option<Settings> {
+    match connector {
+        ConnectorConfig::Postgres(_) => Some(Settings {
+            required: &["database", "table"],
+            optional: &["schema", "key", "batch_size", "copy", "schema_evolution"],
+        }),
+        ConnectorConfig::Kafka(_) => Some(Settings {
+            required: &["topic"],
+            optional: &[
+                "key",
+                "partitioner",
+                "acks",
+                "compression",
+                "idempotence",
+                "linger_ms",
+            ],
+        }),
+        ConnectorConfig::Nats(_) => Some(Settings {
+            required: &[],
+            optional: &["topic", "jetstream"],
+        }),
+        ConnectorConfig::Sqlite(_) => Some(Settings {
+            required: &["table"],
+            optional: &["key", "schema_evolution"],
+        }),
+        ConnectorConfig::Mysql(_) | ConnectorConfig::Mssql(_) | ConnectorConfig::Oracle(_) => None,
+    }
+}
+
+// processor_settings returns None for processor types fust does not have
+fn processor_settings(processor_type: &str) -> Option<Settings> {
+    match processor_type {
+        "drop_fields" | "rename_fields" => Some(Settings {
+            required: &["fields"],
+            optional: &["on_error"],
+        }),
+        "filter" => Some(Settings {
+            required: &["ops"],
+            optional: &["on_error"],
+        }),
+        _ => None,
+    }
+}
+
+// validate_file loads a configuration file and checks that it describes
+// something fust can actually run
+pub fn validate_file(config_path: &str) -> Validation {
+    match Document::read(config_path) {
+        Ok(document) => validate(&document),
+        Err(e) => Validation {
+            spec: None,
+            errors: vec![e],
+            warnings: vec![],
+        },
+    }
+}
+
+pub(crate) fn validate(document: &Document) -> Validation {
+    let loaded = ConfigSpec::from_document(document);
+    let mut validator = Validator {
+        document,
+        errors: vec![],
+        warnings: vec![],
+    };
+    let spec = match loaded {
+        Ok(spec) => {
+            validator.check(&spec);
+            Some(spec)
+        }
+        Err(errors) => {
+            validator.errors.extend(errors);
+            None
+        }
+    };
+    validator.unknown_root_keys();
+    validator.ignored_keys(spec.as_ref());
+    Validation {
+        spec,
+        errors: validator.errors,
+        warnings: validator.warnings,
+    }
+}
+
+struct Validator<'a> {
+    document: &'a Document,
+    errors: Vec<ConfigError>,
+    warnings: Vec<ConfigWarning>,
+}
+
+impl<'a> Validator<'a> {
+    fn check(&mut self, spec: &ConfigSpec) {
+        let mut used = HashSet::new();
+        for name in sorted(spec.sources.keys()) {
+            let connector = &spec.sources[name].connector;
+            let path = key_path(&["sources", name]);
+            used.insert(self.connector_name(&path));
+            match source_settings(connector) {
+                Some(settings) => {
+                    let context = format!("{} source", connector.kind());
+                    self.settings(&path, &settings, &context)
+                }
+                None => self.unsupported(&path, connector, "source"),
+            }
+        }
+        for name in sorted(spec.sinks.keys()) {
+            let connector = &spec.sinks[name].connector;
+            let path = key_path(&["sinks", name]);
+            used.insert(self.connector_name(&path));
+            match sink_settings(connector) {
+                Some(settings) => {
+                    let context = format!("{} sink", connector.kind());
+                    self.settings(&path, &settings, &context)
+                }
+                None => self.unsupported(&path, connector, "sink"),
+            }
+        }
+        for name in sorted(spec.processors.keys()) {
+            let processor_type = &spec.processors[name].processor_type;
+            let path = key_path(&["processors", name]);
+            match processor_settings(processor_type) {
+                Some(settings) => {
+                    let context = format!("{} processor", processor_type);
+                    self.settings(&path, &settings, &context)
+                }
+                None => self.errors.push(ConfigError::InvalidValue {
+                    location: self.document.locate(&child(&path, "type")),
+                    message: format!("unknown processor type `{}`", processor_type),
+                }),
+            }
+        }
+        for name in sorted(spec.connectors.keys()) {
+            if !used.contains(name.as_str()) {
+                self.warnings.push(ConfigWarning::UnusedConnector {
+                    location: self.document.locate(&key_path(&["connectors", name])),
+                    connector: name.clone(),
+                });
+            }
+        }
+        self.unused_stages(spec);
+    }
+
+    fn raw(&self, path: &[Segment]) -> Option<&'a Table> {
+        let document: &'a Document = self.document;
+        let mut table = &document.table;
+        for segment in path {
+            let Segment::Key(key) = segment else {
+                return None;
+            };
+            table = table.get(key)?.as_table()?;
+        }
+        Some(table)
+    }
+
+    fn connector_name(&self, path: &[Segment]) -> &'a str {
+        self.raw(path)
+            .and_then(|t| t.get("connector"))
+            .and_then(Value::as_str)
+            .unwrap_or_default()
+    }
+
+    fn unsupported(&mut self, path: &[Segment], connector: &ConnectorConfig, role: &str) {
+        self.errors.push(ConfigError::InvalidValue {
+            location: self.document.locate(&child(path, "connector")),
+            message: format!(
+                "a {} connector cannot be used as a {}",
+                connector.kind(),
+                role
+            ),
+        });
+    }
+
+    fn settings(&mut self, path: &[Segment], settings: &Settings, context: &str) {
+        let Some(table) = self.raw(path) else {
+            return;
+        };
+        let mut missing = vec![];
+        for key in settings.required {
+            if !table.contains_key(*key) {
+                missing.push(child(path, key));
+            }
+        }
+        let mut unknown = vec![];
+        for key in table.keys() {
+            let structural = match path.first() {
+                Some(Segment::Key(section)) if section == "processors" => key == "type",
+                Some(Segment::Key(section)) if section == "sources" => {
+                    key == "connector" || key == "fields"
+                }
+                Some(Segment::Key(section)) if section == "sinks" => {
+                    key == "connector" || key == "types"
+                }
+                _ => key == "connector",
+            };
+            if !structural && !settings.knows(key) {
+                unknown.push(child(path, key));
+            }
+        }
+        for key in missing {
+            self.errors.push(ConfigError::MissingKey {
+                location: self.document.locate(&key),
+            });
+        }
+        for key in unknown {
+            self.warnings.push(ConfigWarning::UnknownKey {
+                location: self.document.locate(&key),
+                context: context.to_string(),
+            });
+        }
+    }
+
+    // unused_stages warns about sources, processors and sinks no pipeline runs
+    fn unused_stages(&mut self, spec: &ConfigSpec) {
+        let mut used = HashSet::new();
+        for pipeline in spec.pipelines.values() {
+            used.extend(pipeline.stages());
+        }
+        let declared = [
+            (
+                "sources",
+                sorted(spec.sources.keys()),
+                Stage::Source as fn(String) -> Stage,
+            ),
+            (
+                "processors",
+                sorted(spec.processors.keys()),
+                Stage::Processor,
+            ),
+            ("sinks", sorted(spec.sinks.keys()), Stage::Sink),
+        ];
+        for (section, names, stage) in declared {
+            for name in names {
+                let stage = stage(name.clone());
+                if !used.contains(&stage) {
+                    self.warnings.push(ConfigWarning::UnusedStage {
+                        location: self.document.locate(&key_path(&[section, name])),
+                        stage: stage.to_string(),
+                    });
+                }
+            }
+        }
+    }
+
+    fn unknown_root_keys(&mut self) {
+        for key in self.document.table.keys() {
+            if !ROOT_KEYS.contains(&key.as_str()) {
+                self.warnings.push(ConfigWarning::UnknownKey {
+                    location: self.document.locate(&key_path(&[key])),
+                    context: "top-level".to_string(),
+                });
+            }
+        }
+    }
+
+    fn ignored_keys(&mut self, spec: Option<&ConfigSpec>) {
+        for path in self.document.ignored() {
+            let context = match path.as_slice() {
+                [Segment::Key(section), Segment::Key(name), ..] if section == "connectors" => spec
+                    .and_then(|s| s.connectors.get(name))
+                    .map(|c| format!("{} connector", c.kind()))
+                    .unwrap_or_else(|| "connector".to_string()),
+                [Segment::Key(section), _, Segment::Key(fields), ..]
+                    if section == "sources" && fields == "fields" =>
+                {
+                    "field".to_string()
+                }
+                _ => "known".to_string(),
+            };
+            self.warnings.push(ConfigWarning::UnknownKey {
+                location: self.document.locate(&path),
+                context,
+            });
+        }
+    }
+}
+
+fn sorted<'a>(keys: impl Iterator<Item = &'a String>) -> Vec<&'a String> {
+    let mut keys: Vec<&String> = keys.collect();
+    keys.sort();
+    keys
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn messages(validation: &Validation) -> (Vec<String>, Vec<String>) {
+        (
+            validation.errors.iter().map(|e| e.to_string()).collect(),
+            validation.warnings.iter().map(|w| w.to_string()).collect(),
+        )
+    }
+
+    #[test]
+    fn validate_sample_config() {
+        let validation = validate_file("tests/config.toml");
+        let (errors, warnings) = messages(&validation);
+        assert!(validation.is_valid(), "{:#?}", errors);
+        assert_eq!(
+            warnings,
+            vec![
+                "tests/config.toml:18:1 `connectors.nats`: connector `nats` is not used by any source or sink",
+                "tests/config.toml:16:1 `connectors.kafka.topic`: unknown kafka connector setting, it will be ignored",
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_reports_semantic_errors() {
+        let document = Document::parse(
+            "semantic.toml",
+            r#"name = "semantic"
+version = "1.0.0"
+owner = "data-team"
+
+[connectors.pg]
+type = "postgres"
+host = "localhost"
+port = 5432
+user = "postgres"
+password = "password"
+
+[connectors.kafka]
+type = "kafka"
+brokers = "localhost:9092"
+
+[sources.orders]
+connector = "pg"
+database = "shop"
+tabel = "orders"
+fields = [{ name = "id", type = "int", nulable = false }]
+
+[sources.events]
+connector = "kafka"
+topic = "events"
+
+[sinks.events]
+connector = "kafka"
+
+[processors.mask]
+type = "drop_fields"
+field = "email"
+
+[processors.enrich]
+type = "lookup"
+
+[pipelines.orders]
+sources = ["orders"]
+processors = ["mask", "enrich"]
+sinks = ["events"]
+"#
+            .to_string(),
+        )
+        .unwrap();
+
+        let validation = validate(&document);
+        let (errors, warnings) = messages(&validation);
+        assert_eq!(
+            errors,
+            vec![
+                "semantic.toml:22:1 `sources.events.group`: missing required key",
+                "semantic.toml:16:1 `sources.orders.table`: missing required key",
+                "semantic.toml:26:1 `sinks.events.topic`: missing required key",
+                "semantic.toml:34:1 `processors.enrich.type`: unknown processor type `lookup`",
+                "semantic.toml:29:1 `processors.mask.fields`: missing required key",
+            ]
+        );
+        assert_eq!(
+            warnings,
+            vec![
+                "semantic.toml:19:1 `sources.orders.tabel`: unknown postgres source setting, it will be ignored",
+                "semantic.toml:31:1 `processors.mask.field`: unknown drop_fields processor setting, it will be ignored",
+                "semantic.toml:22:1 `sources.events`: source `events` is not part of any pipeline",
+                "semantic.toml:3:1 `owner`: unknown top-level setting, it will be ignored",
+                "semantic.toml:20:40 `sources.orders.fields[0].nulable`: unknown field setting, it will be ignored",
+            ]
+        );
+    }
+
+    #[test]
+    fn validate_stops_at_load_errors() {
+        let validation = validate_file("tests/missing.toml");
+        assert!(!validation.is_valid());
+        assert!(validation.spec.is_none());
+    }
+}
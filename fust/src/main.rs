@@ -1,14 +1,178 @@
+use std::{env, process::ExitCode, sync::Arc};
+
+use checkpoint::CheckpointStore;
+use config::ConfigSpec;
+use record::Error;
 use tokio::{
     signal::{
         self,
         unix::{self, SignalKind},
     },
-    sync::broadcast,
+    sync::broadcast::{self, Sender},
+    task::JoinSet,
 };
-use tracing::info;
+use tracing::{error, info};
+use transform::Pipeline;
+use util::{pg::Pools, Health};
+
+fn main() -> ExitCode {
+    let args: Vec<String> = env::args().collect();
+    match args.get(1).map(String::as_str) {
+        Some("validate") => match args.get(2) {
+            Some(config_path) => validate(config_path),
+            None => {
+                eprintln!("usage: fust validate <file>");
+                ExitCode::from(2)
+            }
+        },
+        Some("run") => match args.get(2) {
+            Some(config_path) => run(config_path),
+            None => {
+                eprintln!("usage: fust run <file>");
+                ExitCode::from(2)
+            }
+        },
+        _ => {
+            serve();
+            ExitCode::SUCCESS
+        }
+    }
+}
+
+// validate checks a configuration file and exits non-zero if it has errors
+fn validate(config_path: &str) -> ExitCode {
+    let validation = config::validate_file(config_path);
+    for error in &validation.errors {
+        eprintln!("error: {}", error);
+    }
+    for warning in &validation.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    eprintln!(
+        "{}: {} error(s), {} warning(s)",
+        config_path,
+        validation.errors.len(),
+        validation.warnings.len()
+    );
+    if validation.is_valid() {
+        ExitCode::SUCCESS
+    } else {
+        ExitCode::FAILURE
+    }
+}
+
+// run executes every pipeline of a configuration file until its sources are
+// exhausted or a termination signal arrives
+fn run(config_path: &str) -> ExitCode {
+    let validation = config::validate_file(config_path);
+    for warning in &validation.warnings {
+        eprintln!("warning: {}", warning);
+    }
+    let spec = match validation.spec {
+        Some(spec) if validation.errors.is_empty() => spec,
+        _ => {
+            for error in &validation.errors {
+                eprintln!("error: {}", error);
+            }
+            return ExitCode::FAILURE;
+        }
+    };
+    match run_pipelines(spec) {
+        Ok(()) => ExitCode::SUCCESS,
+        Err(e) => {
+            error!("{}", e);
+            ExitCode::FAILURE
+        }
+    }
+}
+
+#[tokio::main]
+async fn run_pipelines(spec: ConfigSpec) -> Result<(), Error> {
+    tracing_subscriber::fmt::init();
+    info!("running {}", spec.name);
+
+    let (tx, _rx) = broadcast::channel(1);
+    tokio::spawn({
+        let tx = tx.clone();
+        async move {
+            let mut sigterm =
+                unix::signal(SignalKind::terminate()).expect("failed to install signal handler");
+            tokio::select! {
+                _ = signal::ctrl_c() => {},
+                _ = sigterm.recv() => {},
+            }
+            info!("received termination signal");
+            let _ = tx.send(());
+        }
+    });
+
+    // a postgres checkpoint store shares its connector's pool with the pipelines
+    let pools = Pools::new();
+    let store = checkpoint::open(&spec.checkpoint, &pools).await?;
+    let health = Health::new();
+    let mut names: Vec<&String> = spec.pipelines.keys().collect();
+    names.sort();
+    let mut pipelines = JoinSet::new();
+    for name in names {
+        let pipeline = pipeline(&spec, name, store.clone(), &health, &pools, &tx)?;
+        pipelines.spawn(pipeline.run());
+    }
+
+    let server_rx = tx.subscribe();
+    let webserver = tokio::spawn(async move {
+        webserver::serve(server_rx, health).await;
+    });
+
+    let mut result = Ok(());
+    while let Some(joined) = pipelines.join_next().await {
+        if let Err(e) = joined.map_err(Error::from).and_then(|result| result) {
+            // stop the other pipelines too, they are drained before they exit
+            error!("{}", e);
+            let _ = tx.send(());
+            result = Err(e);
+        }
+    }
+    let _ = tx.send(());
+    let _ = webserver.await;
+    result
+}
+
+// pipeline assembles a pipeline from the stages its configuration names. Each
+// source and sink retries under its connector's policy, reports to `health` and
+// shares the connections of its connector in `pools`.
+fn pipeline(
+    spec: &ConfigSpec,
+    name: &str,
+    store: Arc<dyn CheckpointStore>,
+    health: &Health,
+    pools: &Pools,
+    shutdown: &Sender<()>,
+) -> Result<Pipeline, Error> {
+    let config = &spec.pipelines[name];
+    let mut pipeline = Pipeline::new(name, store).with_health(health.clone());
+    for source in &config.sources {
+        let source_config = &spec.sources[source];
+        let built = source::build(source_config, shutdown.subscribe(), pools)
+            .map_err(|e| format!("source `{}`: {}", source, e))?;
+        pipeline.add_source_with_retry(source, built, source_config.connector.retry().clone());
+    }
+    for processor in &config.processors {
+        pipeline.add_processor(transform::processor::build(
+            processor,
+            &spec.processors[processor],
+        )?);
+    }
+    for sink in &config.sinks {
+        let sink_config = &spec.sinks[sink];
+        let built =
+            sink::build(sink_config, pools).map_err(|e| format!("sink `{}`: {}", sink, e))?;
+        pipeline.add_sink_with_retry(sink, built, sink_config.connector.retry().clone());
+    }
+    Ok(pipeline)
+}
 
 #[tokio::main]
-async fn main() {
+async fn serve() {
     tracing_subscriber::fmt::init();
     info!("starting");
 
@@ -34,8 +198,8 @@ async fn main() {
     });
 
     let webserver_handle = tokio::spawn(async move {
-        webserver::serve(server_rx).await;
+        webserver::serve(server_rx, Health::new()).await;
     });
 
-    tokio::join!(signal_handle, webserver_handle);
+    let _ = tokio::join!(signal_handle, webserver_handle);
 }
@@ -0,0 +1,202 @@
+mod value;
+
+use std::collections::BTreeMap;
+
+use checkpoint::Position;
+use chrono::{DateTime, Utc};
+use serde::{ser::SerializeMap, Serializer};
+use serde_derive::Serialize;
+
+pub use value::Value;
+
+pub type Error = Box<dyn std::error::Error + Send + Sync>;
+
+// Op is what happened to the row a record describes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Op {
+    // Snapshot is a row read by a full table scan rather than a change
+    Snapshot,
+    Insert,
+    Update,
+    Delete,
+}
+
+// Row is a set of named column values, in column order
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Row(Vec<(String, Value)>);
+
+impl Row {
+    pub fn new() -> Self {
+        Row(vec![])
+    }
+
+    pub fn get(&self, name: &str) -> Option<&Value> {
+        self.0.iter().find(|(n, _)| n == name).map(|(_, v)| v)
+    }
+
+    // insert sets a column, replacing its value if it is already present
+    pub fn insert(&mut self, name: impl Into<String>, value: Value) {
+        let name = name.into();
+        match self.0.iter_mut().find(|(n, _)| *n == name) {
+            Some((_, v)) => *v = value,
+            None => self.0.push((name, value)),
+        }
+    }
+
+    pub fn remove(&mut self, name: &str) -> Option<Value> {
+        let index = self.0.iter().position(|(n, _)| n == name)?;
+        Some(self.0.remove(index).1)
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &Value)> {
+        self.0.iter().map(|(n, v)| (n.as_str(), v))
+    }
+
+    pub fn iter_mut(&mut self) -> impl Iterator<Item = (&mut String, &mut Value)> {
+        self.0.iter_mut().map(|(n, v)| (n, v))
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+}
+
+impl FromIterator<(String, Value)> for Row {
+    fn from_iter<I: IntoIterator<Item = (String, Value)>>(iter: I) -> Self {
+        let mut row = Row::new();
+        for (name, value) in iter {
+            row.insert(name, value);
+        }
+        row
+    }
+}
+
+impl serde::Serialize for Row {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(Some(self.0.len()))?;
+        for (name, value) in &self.0 {
+            map.serialize_entry(name, value)?;
+        }
+        map.end()
+    }
+}
+
+// Metadata tells where a record comes from
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct Metadata {
+    // connector is the kind of system the record was read from, such as `postgres`
+    pub connector: String,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub database: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub schema: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub table: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub transaction: Option<String>,
+    // attributes holds anything else the source knows, such as an LSN or an offset
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub attributes: BTreeMap<String, String>,
+}
+
+// Record is one row-level event flowing from a source to its sinks
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Record {
+    pub op: Op,
+    // key names the columns that identify the row
+    pub key: Vec<String>,
+    // before is the row as it was, for updates and deletes when the source has it
+    pub before: Option<Row>,
+    // after is the row as it is now, for snapshots, inserts and updates
+    pub after: Option<Row>,
+    pub metadata: Metadata,
+    // event_time is when the change happened at the source, if it is known
+    pub event_time: Option<DateTime<Utc>>,
+}
+
+impl Record {
+    // row is the current image of the row, or the last one for a delete
+    pub fn row(&self) -> Option<&Row> {
+        self.after.as_ref().or(self.before.as_ref())
+    }
+
+    // key_values returns the key of the row, in key column order
+    pub fn key_values(&self) -> Vec<&Value> {
+        let row = self.row();
+        self.key
+            .iter()
+            .map(|name| row.and_then(|r| r.get(name)).unwrap_or(&Value::Null))
+            .collect()
+    }
+
+    pub fn to_json(&self) -> serde_json::Value {
+        serde_json::to_value(self).expect("records always serialize to JSON")
+    }
+}
+
+// Batch is what a source returns from one read: records and how far they reach
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Batch {
+    pub records: Vec<Record>,
+    // position is where to resume after this batch has been delivered
+    pub position: Option<Position>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    fn record() -> Record {
+        Record {
+            op: Op::Update,
+            key: vec!["id".to_string()],
+            before: Some(Row::from_iter([("id".to_string(), Value::Int(1))])),
+            after: Some(Row::from_iter([
+                ("id".to_string(), Value::Int(1)),
+                ("total".to_string(), Value::Decimal("9.50".to_string())),
+            ])),
+            metadata: Metadata {
+                connector: "postgres".to_string(),
+                table: Some("orders".to_string()),
+                ..Metadata::default()
+            },
+            event_time: None,
+        }
+    }
+
+    #[test]
+    fn rows_keep_column_order() {
+        let mut row = Row::new();
+        row.insert("b", Value::Int(1));
+        row.insert("a", Value::Int(2));
+        row.insert("b", Value::Int(3));
+        let names: Vec<&str> = row.iter().map(|(n, _)| n).collect();
+        assert_eq!(names, vec!["b", "a"]);
+        assert_eq!(row.get("b"), Some(&Value::Int(3)));
+        assert_eq!(row.remove("b"), Some(Value::Int(3)));
+        assert_eq!(row.len(), 1);
+    }
+
+    #[test]
+    fn records_serialize_to_json() {
+        let record = record();
+        assert_eq!(record.key_values(), vec![&Value::Int(1)]);
+        assert_eq!(
+            record.to_json(),
+            json!({
+                "op": "update",
+                "key": ["id"],
+                "before": {"id": 1},
+                "after": {"id": 1, "total": "9.50"},
+                "metadata": {"connector": "postgres", "table": "orders"},
+                "event_time": null,
+            })
+        );
+    }
+}
@@ -0,0 +1,299 @@
+use std::fmt::Write;
+
+use chrono::{DateTime, NaiveDate, NaiveDateTime, Utc};
+use config::{Field, FieldType};
+use serde::{Serialize, Serializer};
+
+// Value is one typed column value
+#[derive(Debug, Clone, PartialEq)]
+pub enum Value {
+    Null,
+    Bool(bool),
+    Int(i64),
+    Float(f64),
+    // Decimal keeps the exact digits rather than rounding through a float
+    Decimal(String),
+    String(String),
+    Bytes(Vec<u8>),
+    Date(NaiveDate),
+    Timestamp(NaiveDateTime),
+    TimestampTz(DateTime<Utc>),
+    Uuid(String),
+    Json(serde_json::Value),
+}
+
+impl Value {
+    // parse types a value from its text form, as databases print it
+    pub fn parse(field_type: FieldType, text: &str) -> Result<Value, String> {
+        let invalid = || format!("invalid {} value `{}`", field_type, text);
+        let value = match field_type {
+            FieldType::Int => Value::Int(text.parse::<i32>().map_err(|_| invalid())?.into()),
+            FieldType::BigInt => Value::Int(text.parse().map_err(|_| invalid())?),
+            FieldType::Number => match text.parse::<f64>() {
+                Ok(number) if number.is_finite() => Value::Float(number),
+                _ => return Err(invalid()),
+            },
+            FieldType::Boolean => match text.to_lowercase().as_str() {
+                "t" | "true" | "1" | "yes" | "on" => Value::Bool(true),
+                "f" | "false" | "0" | "no" | "off" => Value::Bool(false),
+                _ => return Err(invalid()),
+            },
+            FieldType::Decimal if is_decimal(text) => Value::Decimal(text.to_string()),
+            FieldType::Uuid if is_uuid(text) => Value::Uuid(text.to_lowercase()),
+            FieldType::Decimal | FieldType::Uuid => return Err(invalid()),
+            FieldType::Json => Value::Json(serde_json::from_str(text).map_err(|_| invalid())?),
+            FieldType::Object => match serde_json::from_str(text) {
+                Ok(json @ serde_json::Value::Object(_)) => Value::Json(json),
+                _ => return Err(invalid()),
+            },
+            FieldType::Array => match serde_json::from_str(text) {
+                Ok(json @ serde_json::Value::Array(_)) => Value::Json(json),
+                _ => return Err(invalid()),
+            },
+            FieldType::String => Value::String(text.to_string()),
+            FieldType::Date => {
+                Value::Date(NaiveDate::parse_from_str(text, "%Y-%m-%d").map_err(|_| invalid())?)
+            }
+            FieldType::Timestamp => parse_timestamp(text).ok_or_else(invalid)?,
+            FieldType::Bytes => match text.strip_prefix("\\x") {
+                Some(hex) => Value::Bytes(decode_hex(hex).ok_or_else(invalid)?),
+                None => Value::Bytes(text.as_bytes().to_vec()),
+            },
+        };
+        Ok(value)
+    }
+
+    // from_field types one column value, where `None` is SQL NULL
+    pub fn from_field(field: &Field, text: Option<&str>) -> Result<Value, String> {
+        match text {
+            Some(text) => Value::parse(field.field_type, text)
+                .map_err(|e| format!("field `{}`: {}", field.name, e)),
+            None if field.nullable => Ok(Value::Null),
+            None => Err(format!("field `{}` is not nullable", field.name)),
+        }
+    }
+
+    pub fn is_null(&self) -> bool {
+        matches!(self, Value::Null)
+    }
+
+    // field_type is the type of field that holds values like this one, or None
+    // for NULL, which fits any
+    pub fn field_type(&self) -> Option<FieldType> {
+        let field_type = match self {
+            Value::Null => return None,
+            Value::Bool(_) => FieldType::Boolean,
+            Value::Int(_) => FieldType::BigInt,
+            Value::Float(_) => FieldType::Number,
+            Value::Decimal(_) => FieldType::Decimal,
+            Value::String(_) => FieldType::String,
+            Value::Bytes(_) => FieldType::Bytes,
+            Value::Date(_) => FieldType::Date,
+            Value::Timestamp(_) | Value::TimestampTz(_) => FieldType::Timestamp,
+            Value::Uuid(_) => FieldType::Uuid,
+            Value::Json(_) => FieldType::Json,
+        };
+        Some(field_type)
+    }
+
+    // to_json renders the value for sinks that speak JSON: timestamps become RFC 3339
+    // strings, decimals stay strings and bytes are hex encoded
+    pub fn to_json(&self) -> serde_json::Value {
+        match self {
+            Value::Null => serde_json::Value::Null,
+            Value::Bool(b) => serde_json::Value::Bool(*b),
+            Value::Int(i) => serde_json::Value::from(*i),
+            Value::Float(f) => serde_json::Value::from(*f),
+            Value::Decimal(s) | Value::String(s) | Value::Uuid(s) => {
+                serde_json::Value::String(s.clone())
+            }
+            Value::Bytes(bytes) => serde_json::Value::String(encode_hex(bytes)),
+            Value::Date(date) => serde_json::Value::String(date.to_string()),
+            Value::Timestamp(timestamp) => {
+                serde_json::Value::String(timestamp.format("%Y-%m-%dT%H:%M:%S%.f").to_string())
+            }
+            Value::TimestampTz(timestamp) => serde_json::Value::String(timestamp.to_rfc3339()),
+            Value::Json(json) => json.clone(),
+        }
+    }
+
+    // to_text renders the value in the text form databases read, the reverse of
+    // `parse`. It is None for NULL.
+    pub fn to_text(&self) -> Option<String> {
+        let text = match self {
+            Value::Null => return None,
+            Value::Bool(b) => b.to_string(),
+            Value::Int(i) => i.to_string(),
+            Value::Float(f) => f.to_string(),
+            Value::Decimal(s) | Value::String(s) | Value::Uuid(s) => s.clone(),
+            Value::Bytes(bytes) => format!("\\x{}", encode_hex(bytes)),
+            Value::Date(date) => date.to_string(),
+            Value::Timestamp(timestamp) => timestamp.format("%Y-%m-%d %H:%M:%S%.f").to_string(),
+            Value::TimestampTz(timestamp) => timestamp.to_rfc3339(),
+            Value::Json(json) => json.to_string(),
+        };
+        Some(text)
+    }
+}
+
+impl Serialize for Value {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.to_json().serialize(serializer)
+    }
+}
+
+// is_decimal is whether the text is a plain decimal number, kept as text so no
+// precision is lost on the way through
+fn is_decimal(text: &str) -> bool {
+    let digits = text.strip_prefix(['-', '+']).unwrap_or(text);
+    let (whole, fraction) = digits.split_once('.').unwrap_or((digits, "0"));
+    let numeric = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    numeric(whole) && numeric(fraction) || text == "NaN"
+}
+
+fn is_uuid(text: &str) -> bool {
+    let groups: Vec<&str> = text.split('-').collect();
+    groups.iter().map(|g| g.len()).eq([8, 4, 4, 4, 12])
+        && groups
+            .iter()
+            .all(|g| g.bytes().all(|b| b.is_ascii_hexdigit()))
+}
+
+// parse_timestamp reads a timestamp with or without a time zone, in the form
+// Postgres prints (`2024-01-31 12:00:00.5+01`) or in RFC 3339
+fn parse_timestamp(text: &str) -> Option<Value> {
+    for format in ["%Y-%m-%d %H:%M:%S%.f%#z", "%Y-%m-%dT%H:%M:%S%.f%#z"] {
+        if let Ok(timestamp) = DateTime::parse_from_str(text, format) {
+            return Some(Value::TimestampTz(timestamp.with_timezone(&Utc)));
+        }
+    }
+    for format in ["%Y-%m-%d %H:%M:%S%.f", "%Y-%m-%dT%H:%M:%S%.f"] {
+        if let Ok(timestamp) = NaiveDateTime::parse_from_str(text, format) {
+            return Some(Value::Timestamp(timestamp));
+        }
+    }
+    None
+}
+
+fn decode_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(bytes: &[u8]) -> String {
+    let mut hex = String::with_capacity(bytes.len() * 2);
+    for byte in bytes {
+        let _ = write!(hex, "{:02x}", byte);
+    }
+    hex
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use serde_json::json;
+
+    #[test]
+    fn parses_typed_values() {
+        assert_eq!(Value::parse(FieldType::Int, "42"), Ok(Value::Int(42)));
+        assert_eq!(
+            Value::parse(FieldType::Decimal, "12.50"),
+            Ok(Value::Decimal("12.50".to_string()))
+        );
+        assert_eq!(
+            Value::parse(FieldType::Date, "2024-01-31"),
+            Ok(Value::Date(NaiveDate::from_ymd_opt(2024, 1, 31).unwrap()))
+        );
+        assert_eq!(
+            Value::parse(FieldType::Bytes, "\\x00ff"),
+            Ok(Value::Bytes(vec![0, 255]))
+        );
+        assert_eq!(
+            Value::parse(FieldType::Int, "4294967296"),
+            Err("invalid int value `4294967296`".to_string())
+        );
+        assert!(Value::parse(FieldType::Date, "yesterday").is_err());
+        assert_eq!(
+            Value::parse(FieldType::BigInt, "4294967296"),
+            Ok(Value::Int(4294967296))
+        );
+        assert_eq!(Value::parse(FieldType::Boolean, "t"), Ok(Value::Bool(true)));
+        assert!(Value::parse(FieldType::Decimal, "12.x").is_err());
+        assert_eq!(
+            Value::parse(FieldType::Uuid, "123E4567-e89b-12d3-a456-426614174000"),
+            Ok(Value::Uuid(
+                "123e4567-e89b-12d3-a456-426614174000".to_string()
+            ))
+        );
+        assert!(Value::parse(FieldType::Uuid, "123e4567").is_err());
+        assert_eq!(
+            Value::parse(FieldType::Json, r#"{"a":1}"#),
+            Ok(Value::Json(json!({"a": 1})))
+        );
+        assert!(Value::parse(FieldType::Array, r#"{"a":1}"#).is_err());
+    }
+
+    #[test]
+    fn types_nullable_fields() {
+        let field = Field {
+            name: "id".to_string(),
+            field_type: FieldType::Int,
+            nullable: false,
+        };
+        assert_eq!(Value::from_field(&field, Some("7")), Ok(Value::Int(7)));
+        assert_eq!(
+            Value::from_field(&field, None),
+            Err("field `id` is not nullable".to_string())
+        );
+
+        let field = Field {
+            nullable: true,
+            ..field
+        };
+        assert_eq!(Value::from_field(&field, None), Ok(Value::Null));
+    }
+
+    #[test]
+    fn parses_timestamps() {
+        let Ok(Value::TimestampTz(timestamp)) =
+            Value::parse(FieldType::Timestamp, "2024-01-31 12:00:00.5+01")
+        else {
+            panic!("expected a timestamp with time zone");
+        };
+        assert_eq!(timestamp.to_rfc3339(), "2024-01-31T11:00:00.500+00:00");
+
+        assert_eq!(
+            Value::parse(FieldType::Timestamp, "2024-01-31 12:00:00")
+                .unwrap()
+                .to_json(),
+            json!("2024-01-31T12:00:00")
+        );
+    }
+
+    #[test]
+    fn renders_json() {
+        assert_eq!(Value::Bytes(vec![0, 255]).to_json(), json!("00ff"));
+        assert_eq!(Value::Decimal("1.10".to_string()).to_json(), json!("1.10"));
+        assert_eq!(Value::Json(json!({"a": 1})).to_json(), json!({"a": 1}));
+        assert_eq!(Value::Null.to_json(), json!(null));
+    }
+
+    #[test]
+    fn renders_text() {
+        for (field_type, text) in [
+            (FieldType::Bytes, "\\x00ff"),
+            (FieldType::Timestamp, "2024-01-31 12:00:00.500"),
+            (FieldType::Boolean, "true"),
+            (FieldType::Json, r#"{"a":[1,2]}"#),
+        ] {
+            let value = Value::parse(field_type, text).unwrap();
+            assert_eq!(value.to_text().as_deref(), Some(text));
+        }
+        assert_eq!(Value::Null.to_text(), None);
+    }
+}
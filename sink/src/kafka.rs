@@ -0,0 +1,299 @@
+use std::{collections::HashMap, collections::VecDeque, time::Duration};
+
+use async_trait::async_trait;
+use config::{ConnectorConfig, KafkaConfig, SinkConfig};
+use rdkafka::{
+    error::{KafkaError, RDKafkaErrorCode},
+    producer::{DeliveryFuture, FutureProducer, FutureRecord, Producer},
+    ClientConfig,
+};
+use record::{Error, Record};
+use tracing::info;
+
+use crate::{
+    message::{key_json, Template},
+    Sink,
+};
+
+const PARTITIONERS: &[&str] = &[
+    "consistent_random",
+    "consistent",
+    "murmur2_random",
+    "murmur2",
+    "fnv1a_random",
+    "fnv1a",
+    "random",
+];
+const COMPRESSIONS: &[&str] = &["none", "gzip", "snappy", "lz4", "zstd"];
+const ACKS: &[&str] = &["all", "1", "0"];
+// FLUSH_TIMEOUT bounds how long closing the sink waits for queued messages
+const FLUSH_TIMEOUT: Duration = Duration::from_secs(30);
+
+// KafkaSink produces every record as a JSON message. Messages are keyed by the
+// record's key columns, so all changes to a row land on the same partition in
+// order, and `write` only returns once the brokers have acknowledged every message.
+//
+// Settings: `topic` (a template), `key` to key by other columns than the record's
+// key, `partitioner`, `acks`, `compression`, `idempotence` and `linger_ms`.
+pub struct KafkaSink {
+    producer: FutureProducer,
+    topic: Template,
+    key: Option<Vec<String>>,
+}
+
+impl KafkaSink {
+    pub fn new(config: &SinkConfig) -> Result<Self, Error> {
+        match &config.connector {
+            ConnectorConfig::Kafka(kafka) => Self::from_kafka(kafka, &config.config),
+            other => Err(format!("a {} connector cannot back a kafka sink", other.kind()).into()),
+        }
+    }
+
+    pub fn from_kafka(
+        kafka: &KafkaConfig,
+        settings: &HashMap<String, String>,
+    ) -> Result<Self, Error> {
+        let topic = Template::parse(
+            settings
+                .get("topic")
+                .ok_or("kafka sink is missing `topic`")?,
+        )?;
+        let choice =
+            |key: &str, default: &'static str, allowed: &[&str]| -> Result<String, Error> {
+                let value = settings.get(key).map(String::as_str).unwrap_or(default);
+                if allowed.contains(&value) {
+                    Ok(value.to_string())
+                } else {
+                    Err(format!(
+                        "invalid {} `{}`, expected one of {}",
+                        key,
+                        value,
+                        allowed.join(", ")
+                    )
+                    .into())
+                }
+            };
+        let acks = choice("acks", "all", ACKS)?;
+        let idempotence = choice("idempotence", "true", &["true", "false"])? == "true";
+        if idempotence && acks != "all" {
+            return Err(format!("idempotence needs acks `all`, not `{}`", acks).into());
+        }
+        let linger_ms = match settings.get("linger_ms") {
+            Some(linger) => linger
+                .parse::<u32>()
+                .map_err(|_| format!("invalid linger_ms `{}`", linger))?,
+            None => 5,
+        };
+
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", &kafka.brokers)
+            .set(
+                "partitioner",
+                choice("partitioner", "murmur2_random", PARTITIONERS)?,
+            )
+            .set("acks", acks)
+            .set("enable.idempotence", idempotence.to_string())
+            .set(
+                "compression.type",
+                choice("compression", "none", COMPRESSIONS)?,
+            )
+            .set("linger.ms", linger_ms.to_string())
+            .create()?;
+        Ok(KafkaSink {
+            producer,
+            topic,
+            key: settings.get("key").map(|key| {
+                key.split(',')
+                    .map(|column| column.trim().to_string())
+                    .collect()
+            }),
+        })
+    }
+}
+
+async fn delivered(delivery: DeliveryFuture) -> Result<(), Error> {
+    match delivery.await {
+        Ok(Ok(_)) => Ok(()),
+        Ok(Err((e, _))) => Err(e.into()),
+        Err(_) => Err("kafka producer dropped a message".into()),
+    }
+}
+
+#[async_trait]
+impl Sink for KafkaSink {
+    async fn open(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn write(&mut self, records: &[Record]) -> Result<(), Error> {
+        let mut pending = VecDeque::new();
+        for record in records {
+            let topic = self.topic.render(record)?;
+            let key = key_json(record, self.key.as_deref());
+            let payload = record.to_json().to_string();
+            loop {
+                let mut message = FutureRecord::to(&topic).payload(&payload);
+                if let Some(key) = &key {
+                    message = message.key(key);
+                }
+                match self.producer.send_result(message) {
+                    Ok(delivery) => {
+                        pending.push_back(delivery);
+                        break;
+                    }
+                    // wait for the oldest message to make room in the local queue
+                    Err((KafkaError::MessageProduction(RDKafkaErrorCode::QueueFull), _)) => {
+                        match pending.pop_front() {
+                            Some(delivery) => delivered(delivery).await?,
+                            None => tokio::time::sleep(Duration::from_millis(10)).await,
+                        }
+                    }
+                    Err((e, _)) => return Err(e.into()),
+                }
+            }
+        }
+        for delivery in pending {
+            delivered(delivery).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        info!("flushing kafka sink");
+        self.producer.flush(FLUSH_TIMEOUT)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::{
+        consumer::{BaseConsumer, Consumer},
+        mocking::MockCluster,
+        Message, Offset, TopicPartitionList,
+    };
+    use record::{Metadata, Op, Row, Value};
+
+    fn record(id: i64, total: &str) -> Record {
+        Record {
+            op: Op::Insert,
+            key: vec!["id".to_string()],
+            before: None,
+            after: Some(Row::from_iter([
+                ("id".to_string(), Value::Int(id)),
+                ("total".to_string(), Value::Decimal(total.to_string())),
+            ])),
+            metadata: Metadata {
+                connector: "postgres".to_string(),
+                schema: Some("public".to_string()),
+                table: Some("orders".to_string()),
+                ..Metadata::default()
+            },
+            event_time: None,
+        }
+    }
+
+    fn settings(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn kafka(brokers: &str) -> KafkaConfig {
+        KafkaConfig {
+            brokers: brokers.to_string(),
+            retry: Default::default(),
+        }
+    }
+
+    #[test]
+    fn checks_delivery_settings() {
+        let invalid = [
+            vec![("topic", "t"), ("acks", "1")],
+            vec![("topic", "t"), ("compression", "brotli")],
+            vec![("topic", "t"), ("partitioner", "hash")],
+            vec![("topic", "t"), ("linger_ms", "-1")],
+            vec![],
+        ];
+        for pairs in invalid {
+            assert!(KafkaSink::from_kafka(&kafka("localhost:9092"), &settings(&pairs)).is_err());
+        }
+        let error = KafkaSink::from_kafka(
+            &kafka("localhost:9092"),
+            &settings(&[("topic", "t"), ("compression", "brotli")]),
+        )
+        .err()
+        .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "invalid compression `brotli`, expected one of none, gzip, snappy, lz4, zstd"
+        );
+        let settings = settings(&[
+            ("topic", "t"),
+            ("acks", "1"),
+            ("idempotence", "false"),
+            ("compression", "zstd"),
+        ]);
+        assert!(KafkaSink::from_kafka(&kafka("localhost:9092"), &settings).is_ok());
+    }
+
+    #[tokio::test]
+    async fn produces_keyed_messages() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("cdc.public.orders", 4, 1).unwrap();
+        let brokers = cluster.bootstrap_servers();
+        let mut sink = KafkaSink::from_kafka(
+            &kafka(&brokers),
+            &settings(&[("topic", "cdc.{schema}.{table}"), ("compression", "lz4")]),
+        )
+        .unwrap();
+        sink.open().await.unwrap();
+        let records: Vec<Record> = (0..20)
+            .map(|i| record(i % 5, &format!("{}.00", i)))
+            .collect();
+        sink.write(&records).await.unwrap();
+        sink.close().await.unwrap();
+
+        let consumer: BaseConsumer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .set("group.id", "test")
+            .set("auto.offset.reset", "earliest")
+            .create()
+            .unwrap();
+        let mut assignment = TopicPartitionList::new();
+        for partition in 0..4 {
+            assignment
+                .add_partition_offset("cdc.public.orders", partition, Offset::Beginning)
+                .unwrap();
+        }
+        consumer.assign(&assignment).unwrap();
+        let mut partitions: HashMap<String, i32> = HashMap::new();
+        let mut totals: HashMap<String, Vec<String>> = HashMap::new();
+        let mut received = 0;
+        while received < records.len() {
+            let Some(message) = consumer.poll(Duration::from_secs(10)) else {
+                panic!("only received {} messages", received);
+            };
+            let message = message.unwrap();
+            let key = String::from_utf8(message.key().unwrap().to_vec()).unwrap();
+            let value: serde_json::Value =
+                serde_json::from_slice(message.payload().unwrap()).unwrap();
+            // every change to a row goes to the same partition
+            let partition = *partitions.entry(key.clone()).or_insert(message.partition());
+            assert_eq!(partition, message.partition());
+            totals
+                .entry(key)
+                .or_default()
+                .push(value["after"]["total"].as_str().unwrap().to_string());
+            received += 1;
+        }
+        assert_eq!(partitions.len(), 5);
+        // and arrives in the order it was written
+        assert_eq!(
+            totals[r#"{"id":2}"#],
+            vec!["2.00", "7.00", "12.00", "17.00"]
+        );
+    }
+}
@@ -1,4 +1,43 @@
-pub trait Sink {
-    fn new() -> Self;
-    async fn write(&self, records: Vec<String>) -> Result<(), Box<dyn std::error::Error>>;
+pub mod kafka;
+pub mod message;
+pub mod nats;
+pub mod pg;
+mod schema;
+pub mod sqlite;
+
+use async_trait::async_trait;
+use config::{ConnectorConfig, SinkConfig};
+use record::{Error, Record};
+use util::pg::Pools;
+
+pub use kafka::KafkaSink;
+pub use nats::NatsSink;
+pub use pg::PgSink;
+pub use sqlite::SqliteSink;
+
+// Sink writes records to an external system. It is object safe so a pipeline
+// can hold any sink as a `Box<dyn Sink>`.
+#[async_trait]
+pub trait Sink: Send {
+    async fn open(&mut self) -> Result<(), Error>;
+    // write returns once the records are durably accepted, so the pipeline can
+    // commit the batch's position after every sink has written it
+    async fn write(&mut self, records: &[Record]) -> Result<(), Error>;
+    async fn close(&mut self) -> Result<(), Error>;
+}
+
+// build creates the sink a configuration describes, picking the implementation
+// by the connector's type. Database sinks share connections through `pools`.
+pub fn build(config: &SinkConfig, pools: &Pools) -> Result<Box<dyn Sink>, Error> {
+    match &config.connector {
+        ConnectorConfig::Kafka(_) => Ok(Box::new(KafkaSink::new(config)?)),
+        ConnectorConfig::Nats(_) => Ok(Box::new(NatsSink::new(config)?)),
+        ConnectorConfig::Postgres(_) => Ok(Box::new(PgSink::new(config, pools)?)),
+        ConnectorConfig::Sqlite(_) => Ok(Box::new(SqliteSink::new(config)?)),
+        other @ (ConnectorConfig::Mysql(_)
+        | ConnectorConfig::Mssql(_)
+        | ConnectorConfig::Oracle(_)) => {
+            Err(format!("a {} connector cannot be used as a sink", other.kind()).into())
+        }
+    }
 }
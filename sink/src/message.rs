@@ -0,0 +1,111 @@
+use record::{Error, Record};
+
+const PLACEHOLDERS: &[&str] = &["database", "schema", "table", "connector"];
+
+// Template is a topic or subject name template, where `{database}`, `{schema}`,
+// `{table}` and `{connector}` are replaced with the record's metadata
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template(String);
+
+impl Template {
+    pub fn parse(template: &str) -> Result<Self, Error> {
+        let mut rest = template;
+        while let Some(start) = rest.find('{') {
+            let Some(end) = rest[start..].find('}') else {
+                return Err(format!("unclosed `{{` in `{}`", template).into());
+            };
+            let name = &rest[start + 1..start + end];
+            if !PLACEHOLDERS.contains(&name) {
+                return Err(format!("unknown placeholder `{{{}}}` in `{}`", name, template).into());
+            }
+            rest = &rest[start + end + 1..];
+        }
+        Ok(Template(template.to_string()))
+    }
+
+    pub fn render(&self, record: &Record) -> Result<String, Error> {
+        let metadata = &record.metadata;
+        let mut rendered = self.0.replace("{connector}", &metadata.connector);
+        for (placeholder, value) in [
+            ("{database}", &metadata.database),
+            ("{schema}", &metadata.schema),
+            ("{table}", &metadata.table),
+        ] {
+            if rendered.contains(placeholder) {
+                let value = value.as_deref().ok_or_else(|| {
+                    format!("`{}` needs {} but the record has none", self.0, placeholder)
+                })?;
+                rendered = rendered.replace(placeholder, value);
+            }
+        }
+        Ok(rendered)
+    }
+}
+
+// key_json renders a record's key columns, or `columns` when given, as a JSON
+// object. It is None when there are no key columns.
+pub fn key_json(record: &Record, columns: Option<&[String]>) -> Option<String> {
+    let columns = columns.unwrap_or(&record.key);
+    if columns.is_empty() {
+        return None;
+    }
+    let row = record.row();
+    let key: serde_json::Map<String, serde_json::Value> = columns
+        .iter()
+        .map(|column| {
+            let value = row
+                .and_then(|r| r.get(column))
+                .map(|v| v.to_json())
+                .unwrap_or(serde_json::Value::Null);
+            (column.clone(), value)
+        })
+        .collect();
+    Some(serde_json::Value::Object(key).to_string())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use record::{Metadata, Op, Row, Value};
+
+    fn record() -> Record {
+        Record {
+            op: Op::Insert,
+            key: vec!["id".to_string()],
+            before: None,
+            after: Some(Row::from_iter([
+                ("id".to_string(), Value::Int(7)),
+                ("tenant".to_string(), Value::String("a".to_string())),
+            ])),
+            metadata: Metadata {
+                connector: "postgres".to_string(),
+                schema: Some("public".to_string()),
+                table: Some("orders".to_string()),
+                ..Metadata::default()
+            },
+            event_time: None,
+        }
+    }
+
+    #[test]
+    fn renders_templates() {
+        let template = Template::parse("cdc.{schema}.{table}").unwrap();
+        assert_eq!(template.render(&record()).unwrap(), "cdc.public.orders");
+
+        let template = Template::parse("{database}.{table}").unwrap();
+        assert!(template.render(&record()).is_err());
+        assert!(Template::parse("{tabel}").is_err());
+        assert!(Template::parse("orders.{table").is_err());
+    }
+
+    #[test]
+    fn renders_keys() {
+        assert_eq!(key_json(&record(), None).as_deref(), Some(r#"{"id":7}"#));
+        let columns = ["tenant".to_string(), "region".to_string()];
+        assert_eq!(
+            key_json(&record(), Some(&columns)).as_deref(),
+            Some(r#"{"region":null,"tenant":"a"}"#)
+        );
+        assert_eq!(key_json(&record(), Some(&[])), None);
+    }
+}
@@ -0,0 +1,296 @@
+use std::collections::HashMap;
+
+use async_nats::{header::NATS_MESSAGE_ID, jetstream, Client, HeaderMap};
+use async_trait::async_trait;
+use config::{ConnectorConfig, NatsConfig, SinkConfig};
+use record::{Error, Op, Record};
+use tracing::info;
+
+use crate::{
+    message::{key_json, Template},
+    Sink,
+};
+
+// POSITIONS are the metadata attributes that tell two changes to the same row
+// apart, in order of preference: a Postgres WAL position, a MySQL binlog file and
+// position, a Kafka offset in its topic partition, a NATS sequence in its stream
+// or a SQLite poll cursor
+const POSITIONS: &[&[&str]] = &[
+    &["lsn"],
+    &["binlog_file", "binlog_position"],
+    &["topic", "partition", "offset"],
+    &["stream", "sequence"],
+    &["cursor"],
+];
+
+// NatsSink publishes every record as a JSON message to a subject rendered from
+// the `topic` template, which defaults to the connector's topic. Headers carry the
+// record's operation, origin and key.
+//
+// With `jetstream = "true"` every change read at a position also gets a
+// `Nats-Msg-Id` derived from the row and that position, so the server drops
+// duplicates when a batch is replayed, and `write` waits for the stream to
+// acknowledge every message. Core NATS only guarantees the messages were handed
+// to the server.
+pub struct NatsSink {
+    url: String,
+    subject: Template,
+    jetstream: bool,
+    client: Option<Client>,
+}
+
+impl NatsSink {
+    pub fn new(config: &SinkConfig) -> Result<Self, Error> {
+        match &config.connector {
+            ConnectorConfig::Nats(nats) => Self::from_nats(nats, &config.config),
+            other => Err(format!("a {} connector cannot back a nats sink", other.kind()).into()),
+        }
+    }
+
+    pub fn from_nats(nats: &NatsConfig, settings: &HashMap<String, String>) -> Result<Self, Error> {
+        let subject = Template::parse(settings.get("topic").unwrap_or(&nats.topic))?;
+        let jetstream = match settings.get("jetstream").map(String::as_str) {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(other) => {
+                return Err(
+                    format!("invalid jetstream `{}`, expected `true` or `false`", other).into(),
+                )
+            }
+        };
+        Ok(NatsSink {
+            url: nats.url.clone(),
+            subject,
+            jetstream,
+            client: None,
+        })
+    }
+
+    fn client(&self) -> Result<&Client, Error> {
+        self.client
+            .as_ref()
+            .ok_or_else(|| "nats sink written before it was opened".into())
+    }
+}
+
+// headers describes a record's operation and origin
+fn headers(record: &Record) -> HeaderMap {
+    let mut headers = HeaderMap::new();
+    let metadata = &record.metadata;
+    let op = match record.op {
+        Op::Snapshot => "snapshot",
+        Op::Insert => "insert",
+        Op::Update => "update",
+        Op::Delete => "delete",
+    };
+    headers.insert("Fust-Op", op);
+    headers.insert("Fust-Connector", metadata.connector.as_str());
+    for (name, value) in [
+        ("Fust-Database", &metadata.database),
+        ("Fust-Schema", &metadata.schema),
+        ("Fust-Table", &metadata.table),
+        ("Fust-Transaction", &metadata.transaction),
+    ] {
+        if let Some(value) = value {
+            headers.insert(name, value.as_str());
+        }
+    }
+    if let Some(key) = key_json(record, None) {
+        headers.insert("Fust-Key", key);
+    }
+    headers
+}
+
+// message_id identifies a change to a row, so the same change published twice gets
+// the same id. Records without a position, such as snapshot rows, cannot be told
+// apart from a later change to the same row and get none.
+fn message_id(record: &Record) -> Option<String> {
+    let attributes = &record.metadata.attributes;
+    let position = POSITIONS.iter().find_map(|names| {
+        names
+            .iter()
+            .map(|name| attributes.get(*name).map(String::as_str))
+            .collect::<Option<Vec<_>>>()
+            .map(|parts| parts.join(":"))
+    })?;
+    let metadata = &record.metadata;
+    let origin = [&metadata.database, &metadata.schema, &metadata.table]
+        .iter()
+        .filter_map(|part| part.as_deref())
+        .collect::<Vec<_>>()
+        .join(".");
+    Some(format!(
+        "{}|{}|{}",
+        origin,
+        key_json(record, None).unwrap_or_default(),
+        position
+    ))
+}
+
+#[async_trait]
+impl Sink for NatsSink {
+    async fn open(&mut self) -> Result<(), Error> {
+        info!("connecting nats sink to {}", self.url);
+        self.client = Some(async_nats::connect(&self.url).await?);
+        Ok(())
+    }
+
+    async fn write(&mut self, records: &[Record]) -> Result<(), Error> {
+        let client = self.client()?;
+        if !self.jetstream {
+            for record in records {
+                let subject = self.subject.render(record)?;
+                let payload = record.to_json().to_string();
+                client
+                    .publish_with_headers(subject, headers(record), payload.into())
+                    .await?;
+            }
+            client.flush().await?;
+            return Ok(());
+        }
+
+        let context = jetstream::new(client.clone());
+        let mut acks = Vec::with_capacity(records.len());
+        for record in records {
+            let subject = self.subject.render(record)?;
+            let mut headers = headers(record);
+            if let Some(id) = message_id(record) {
+                headers.insert(NATS_MESSAGE_ID, id);
+            }
+            let payload = record.to_json().to_string();
+            acks.push(
+                context
+                    .publish_with_headers(subject, headers, payload.into())
+                    .await?,
+            );
+        }
+        for ack in acks {
+            ack.await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        if let Some(client) = self.client.take() {
+            client.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use record::{Metadata, Row, Value};
+    use std::collections::BTreeMap;
+
+    fn record(op: Op, lsn: Option<&str>) -> Record {
+        Record {
+            op,
+            key: vec!["id".to_string()],
+            before: None,
+            after: Some(Row::from_iter([("id".to_string(), Value::Int(7))])),
+            metadata: Metadata {
+                connector: "postgres".to_string(),
+                database: Some("shop".to_string()),
+                schema: Some("public".to_string()),
+                table: Some("orders".to_string()),
+                transaction: lsn.map(|_| "42".to_string()),
+                attributes: lsn
+                    .map(|lsn| BTreeMap::from([("lsn".to_string(), lsn.to_string())]))
+                    .unwrap_or_default(),
+            },
+            event_time: None,
+        }
+    }
+
+    fn nats() -> NatsConfig {
+        NatsConfig {
+            url: "nats://localhost:4222".to_string(),
+            topic: "events".to_string(),
+            retry: Default::default(),
+        }
+    }
+
+    #[test]
+    fn reads_settings() {
+        let settings = HashMap::from([
+            ("topic".to_string(), "cdc.{table}".to_string()),
+            ("jetstream".to_string(), "true".to_string()),
+        ]);
+        let sink = NatsSink::from_nats(&nats(), &settings).unwrap();
+        assert!(sink.jetstream);
+        assert_eq!(
+            sink.subject.render(&record(Op::Insert, None)).unwrap(),
+            "cdc.orders"
+        );
+
+        let sink = NatsSink::from_nats(&nats(), &HashMap::new()).unwrap();
+        assert!(!sink.jetstream);
+        assert_eq!(sink.subject, Template::parse("events").unwrap());
+
+        let settings = HashMap::from([("jetstream".to_string(), "yes".to_string())]);
+        assert!(NatsSink::from_nats(&nats(), &settings).is_err());
+    }
+
+    #[test]
+    fn describes_records_in_headers() {
+        let headers = headers(&record(Op::Update, Some("0/16B3748")));
+        let header = |name: &str| headers.get(name).map(|v| v.as_str().to_string());
+        assert_eq!(header("Fust-Op").as_deref(), Some("update"));
+        assert_eq!(header("Fust-Table").as_deref(), Some("orders"));
+        assert_eq!(header("Fust-Transaction").as_deref(), Some("42"));
+        assert_eq!(header("Fust-Key").as_deref(), Some(r#"{"id":7}"#));
+    }
+
+    #[test]
+    fn identifies_changes() {
+        assert_eq!(
+            message_id(&record(Op::Update, Some("0/16B3748"))).as_deref(),
+            Some(r#"shop.public.orders|{"id":7}|0/16B3748"#)
+        );
+        assert_eq!(message_id(&record(Op::Snapshot, None)), None);
+        let mut keyless = record(Op::Insert, Some("0/16B3748"));
+        keyless.key.clear();
+        assert_eq!(
+            message_id(&keyless).as_deref(),
+            Some("shop.public.orders||0/16B3748")
+        );
+    }
+
+    #[test]
+    fn identifies_binlog_changes_by_position() {
+        let change = |position: &str| {
+            let mut record = record(Op::Update, None);
+            record.metadata.attributes = BTreeMap::from([
+                ("binlog_file".to_string(), "binlog.000003".to_string()),
+                ("binlog_position".to_string(), position.to_string()),
+            ]);
+            message_id(&record)
+        };
+        assert_eq!(
+            change("1542").as_deref(),
+            Some(r#"shop.public.orders|{"id":7}|binlog.000003:1542"#)
+        );
+        assert_ne!(change("1542"), change("2087"));
+    }
+
+    #[test]
+    fn identifies_kafka_messages_by_partition_and_offset() {
+        let message = |partition: &str| {
+            let mut record = record(Op::Insert, None);
+            record.key.clear();
+            record.metadata.database = None;
+            record.metadata.schema = None;
+            record.metadata.table = None;
+            record.metadata.attributes = BTreeMap::from([
+                ("topic".to_string(), "orders".to_string()),
+                ("partition".to_string(), partition.to_string()),
+                ("offset".to_string(), "5".to_string()),
+            ]);
+            message_id(&record)
+        };
+        assert_eq!(message("0").as_deref(), Some("||orders:0:5"));
+        assert_ne!(message("0"), message("1"));
+    }
+}
@@ -0,0 +1,788 @@
+use std::{
+    collections::{HashMap, HashSet},
+    pin::pin,
+};
+
+use async_trait::async_trait;
+use bytes::Bytes;
+use config::{ConnectorConfig, FieldType, RdsConfig, SinkConfig};
+use deadpool_postgres::{Client, Pool, Transaction};
+use futures_util::SinkExt;
+use record::{Error, Op, Record, Row};
+use tokio_postgres::types::ToSql;
+use tracing::info;
+use util::pg::Pools;
+
+use crate::{
+    message::Template,
+    schema::{columns, Evolution},
+    Sink,
+};
+
+const DEFAULT_SCHEMA: &str = "public";
+const DEFAULT_BATCH_SIZE: usize = 500;
+// MAX_PARAMETERS is the most bind parameters Postgres accepts in one statement
+const MAX_PARAMETERS: usize = 65535;
+
+// COLUMNS_QUERY finds no columns for a table that does not exist
+const COLUMNS_QUERY: &str = "SELECT a.attname::text, format_type(a.atttypid, a.atttypmod) \
+     FROM pg_attribute a \
+     WHERE a.attrelid = to_regclass($1) AND a.attnum > 0 AND NOT a.attisdropped \
+     ORDER BY a.attnum";
+
+const PRIMARY_KEY_QUERY: &str = "SELECT a.attname::text \
+     FROM pg_index i \
+     JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey) \
+     WHERE i.indrelid = $1::text::regclass AND i.indisprimary \
+     ORDER BY array_position(i.indkey::int2[], a.attnum)";
+
+// Values are the text form of a row's values, None for NULL
+type Values = Vec<Option<String>>;
+
+// Statement is a run of records applied together
+#[derive(Debug, Clone, PartialEq)]
+enum Statement {
+    // Upsert inserts rows, updating those whose key already exists
+    Upsert {
+        columns: Vec<String>,
+        rows: Vec<Values>,
+    },
+    // Copy loads rows with COPY, without looking for existing keys
+    Copy {
+        columns: Vec<String>,
+        rows: Vec<Values>,
+    },
+    // Delete removes rows by key
+    Delete {
+        keys: Vec<Values>,
+    },
+}
+
+// Table is what the sink knows of a target table
+#[derive(Debug, Clone)]
+struct Table {
+    // relation is the quoted, schema qualified name of the table
+    relation: String,
+    // columns maps every column to its type
+    columns: HashMap<String, String>,
+    key: Vec<String>,
+}
+
+// PgSink applies records to the table rendered from the `schema` and `table`
+// templates: inserts, updates and snapshot rows are upserted with `INSERT ... ON
+// CONFLICT DO UPDATE` on the key, and deletes remove the row with the key. Each
+// `write` is one transaction, and consecutive records of the same kind are applied
+// in a single statement of up to `batch_size` rows.
+//
+// The key is the table's primary key unless `key` names other columns, which need
+// a unique index. With `copy = "true"`, inserts and snapshot rows are loaded with
+// COPY instead, which is faster but fails on rows that already exist, so it suits
+// loading an empty table.
+//
+// `schema_evolution` lets the sink create missing tables, keyed by the record's
+// key, and with `additive` also add the columns records bring. New columns get the
+// type `types` maps their field type to.
+pub struct PgSink {
+    pool: Pool,
+    database: String,
+    schema: Template,
+    table: Template,
+    key: Option<Vec<String>>,
+    batch_size: usize,
+    copy: bool,
+    evolution: Evolution,
+    types: HashMap<FieldType, String>,
+    // tables caches the target tables by relation
+    tables: HashMap<String, Table>,
+}
+
+impl PgSink {
+    pub fn new(config: &SinkConfig, pools: &Pools) -> Result<Self, Error> {
+        match &config.connector {
+            ConnectorConfig::Postgres(rds) => {
+                Ok(
+                    Self::from_rds(&config.connector_name, rds, &config.config, pools)?
+                        .with_types(config.types.clone()),
+                )
+            }
+            other => {
+                Err(format!("a {} connector cannot back a postgres sink", other.kind()).into())
+            }
+        }
+    }
+
+    // from_rds builds a sink from the connector it writes through, taking its
+    // connections from the connector's pool in `pools`, and its own settings
+    pub fn from_rds(
+        connector: &str,
+        rds: &RdsConfig,
+        settings: &HashMap<String, String>,
+        pools: &Pools,
+    ) -> Result<Self, Error> {
+        let setting = |key: &str| -> Result<String, Error> {
+            settings
+                .get(key)
+                .cloned()
+                .ok_or_else(|| format!("postgres sink is missing `{}`", key).into())
+        };
+        let batch_size = match settings.get("batch_size") {
+            Some(size) => match size.parse::<usize>() {
+                Ok(size) if size > 0 => size,
+                _ => return Err(format!("invalid batch_size `{}`", size).into()),
+            },
+            None => DEFAULT_BATCH_SIZE,
+        };
+        let copy = match settings.get("copy").map(String::as_str) {
+            None | Some("false") => false,
+            Some("true") => true,
+            Some(other) => {
+                return Err(format!("invalid copy `{}`, expected `true` or `false`", other).into())
+            }
+        };
+        let evolution = Evolution::from_settings(settings)?;
+        let key = settings.get("key").map(|key| {
+            key.split(',')
+                .map(|column| column.trim().to_string())
+                .collect()
+        });
+        let database = setting("database")?;
+
+        let pool = pools.get(connector, rds, &database, 1)?;
+
+        Ok(PgSink {
+            pool,
+            database,
+            schema: Template::parse(
+                settings
+                    .get("schema")
+                    .map(String::as_str)
+                    .unwrap_or(DEFAULT_SCHEMA),
+            )?,
+            table: Template::parse(&setting("table")?)?,
+            key,
+            batch_size,
+            copy,
+            evolution,
+            types: HashMap::new(),
+            tables: HashMap::new(),
+        })
+    }
+
+    // with_types maps field types to the column types the sink creates
+    pub fn with_types(mut self, types: HashMap<FieldType, String>) -> Self {
+        self.types = types;
+        self
+    }
+
+    // column_type is the type of a new column holding `field_type` values. Columns
+    // that have only been NULL so far are text.
+    fn column_type(&self, field_type: Option<FieldType>) -> String {
+        let field_type = field_type.unwrap_or(FieldType::String);
+        if let Some(column_type) = self.types.get(&field_type) {
+            return column_type.clone();
+        }
+        match field_type {
+            FieldType::String => "text",
+            FieldType::Number => "double precision",
+            FieldType::Boolean => "boolean",
+            FieldType::Date => "date",
+            FieldType::Object | FieldType::Array | FieldType::Json => "jsonb",
+            FieldType::Int => "integer",
+            FieldType::BigInt => "bigint",
+            FieldType::Decimal => "numeric",
+            FieldType::Timestamp => "timestamptz",
+            FieldType::Uuid => "uuid",
+            FieldType::Bytes => "bytea",
+        }
+        .to_string()
+    }
+
+    // lookup finds a table's columns and key, or None when it does not exist
+    async fn lookup(&self, client: &Client, relation: &str) -> Result<Option<Table>, Error> {
+        let columns: HashMap<String, String> = client
+            .query(COLUMNS_QUERY, &[&relation])
+            .await?
+            .iter()
+            .map(|row| (row.get(0), row.get(1)))
+            .collect();
+        if columns.is_empty() {
+            return Ok(None);
+        }
+        let key = match &self.key {
+            Some(key) => key.clone(),
+            None => client
+                .query(PRIMARY_KEY_QUERY, &[&relation])
+                .await?
+                .iter()
+                .map(|row| row.get(0))
+                .collect(),
+        };
+        if let Some(column) = key.iter().find(|column| !columns.contains_key(*column)) {
+            return Err(format!("{} has no key column `{}`", relation, column).into());
+        }
+        Ok(Some(Table {
+            relation: relation.to_string(),
+            columns,
+            key,
+        }))
+    }
+
+    // prepare makes sure the table `records` go to exists and fits them, as far
+    // as `schema_evolution` allows
+    async fn prepare(
+        &mut self,
+        client: &Client,
+        schema: &str,
+        relation: &str,
+        records: &[&Record],
+    ) -> Result<(), Error> {
+        if !self.tables.contains_key(relation) {
+            let mut table = self.lookup(client, relation).await?;
+            if table.is_none() && self.evolution != Evolution::None {
+                let sql = self.create_sql(schema, relation, records);
+                info!("creating {}", relation);
+                client.batch_execute(&sql).await?;
+                table = self.lookup(client, relation).await?;
+            }
+            let table = table.ok_or_else(|| format!("{} does not exist", relation))?;
+            self.tables.insert(relation.to_string(), table);
+        }
+
+        let table = &self.tables[relation];
+        let missing: Vec<(String, Option<FieldType>)> = columns(records)
+            .into_iter()
+            .filter(|(column, _)| !table.columns.contains_key(column))
+            .collect();
+        match self.evolution {
+            Evolution::None => {}
+            Evolution::Strict => {
+                if let Some((column, _)) = missing.first() {
+                    return Err(format!("{} has no column `{}`", relation, column).into());
+                }
+                for row in records.iter().filter_map(|record| record.after.as_ref()) {
+                    if let Some(column) = table.columns.keys().find(|c| row.get(c).is_none()) {
+                        return Err(
+                            format!("record for {} has no column `{}`", relation, column).into(),
+                        );
+                    }
+                }
+            }
+            Evolution::Additive => {
+                for (column, field_type) in missing {
+                    let column_type = self.column_type(field_type);
+                    info!("adding column `{}` {} to {}", column, column_type, relation);
+                    client
+                        .batch_execute(&format!(
+                            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {} {}",
+                            relation,
+                            quote_ident(&column),
+                            column_type
+                        ))
+                        .await?;
+                    let table = self.tables.get_mut(relation).expect("table was prepared");
+                    table.columns.insert(column, column_type);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // create_sql creates a table with the columns of `records`, keyed by the
+    // configured key or the records' own
+    fn create_sql(&self, schema: &str, relation: &str, records: &[&Record]) -> String {
+        let mut definitions: Vec<String> = columns(records)
+            .into_iter()
+            .map(|(column, field_type)| {
+                format!("{} {}", quote_ident(&column), self.column_type(field_type))
+            })
+            .collect();
+        let key = match &self.key {
+            Some(key) => key.clone(),
+            None => records
+                .iter()
+                .map(|record| record.key.clone())
+                .find(|key| !key.is_empty())
+                .unwrap_or_default(),
+        };
+        if !key.is_empty() {
+            let key: Vec<String> = key.iter().map(|c| quote_ident(c)).collect();
+            definitions.push(format!("PRIMARY KEY ({})", key.join(", ")));
+        }
+        format!(
+            "CREATE SCHEMA IF NOT EXISTS {}; CREATE TABLE IF NOT EXISTS {} ({})",
+            quote_ident(schema),
+            relation,
+            definitions.join(", ")
+        )
+    }
+
+    // plan groups records into statements, in order. A statement never holds two
+    // changes to the same key, so applying it gives the same result as applying
+    // its records one by one.
+    fn plan(&self, table: &Table, records: &[&Record]) -> Result<Vec<Statement>, Error> {
+        let mut statements: Vec<Statement> = vec![];
+        let mut keys: HashSet<Values> = HashSet::new();
+        for record in records {
+            let mut changes = vec![];
+            match record.op {
+                Op::Delete => {
+                    let row = record.row().ok_or("delete record has no row")?;
+                    changes.push(Statement::Delete {
+                        keys: vec![key_values(table, row)?],
+                    });
+                }
+                Op::Insert | Op::Snapshot | Op::Update => {
+                    let row = record.after.as_ref().ok_or("record has no row to write")?;
+                    if table.key.is_empty() && record.op == Op::Update {
+                        return Err(format!(
+                            "{} has no primary key to apply updates by, set `key`",
+                            table.relation
+                        )
+                        .into());
+                    }
+                    if let (Some(before), false) = (&record.before, table.key.is_empty()) {
+                        // a change to the key moves the row. A before image
+                        // without the key cannot tell, and is taken as the same row.
+                        if let Ok(old) = key_values(table, before) {
+                            if old != key_values(table, row)? {
+                                changes.push(Statement::Delete { keys: vec![old] });
+                            }
+                        }
+                    }
+                    let columns: Vec<String> =
+                        row.iter().map(|(name, _)| name.to_string()).collect();
+                    for column in &columns {
+                        if !table.columns.contains_key(column) {
+                            return Err(
+                                format!("{} has no column `{}`", table.relation, column).into()
+                            );
+                        }
+                    }
+                    let values = row.iter().map(|(_, value)| value.to_text()).collect();
+                    changes.push(if self.copy && record.op != Op::Update {
+                        Statement::Copy {
+                            columns,
+                            rows: vec![values],
+                        }
+                    } else {
+                        Statement::Upsert {
+                            columns,
+                            rows: vec![values],
+                        }
+                    });
+                }
+            }
+
+            for change in changes {
+                let key = match &change {
+                    Statement::Delete { keys } => Some(keys[0].clone()),
+                    Statement::Upsert { .. } if !table.key.is_empty() => Some(key_values(
+                        table,
+                        record.after.as_ref().expect("upserts have rows"),
+                    )?),
+                    _ => None,
+                };
+                let merged = match (statements.last_mut(), change) {
+                    (Some(Statement::Delete { keys: run }), Statement::Delete { keys: new })
+                        if self.fits(run.len(), table.key.len())
+                            && !key.as_ref().is_some_and(|key| keys.contains(key)) =>
+                    {
+                        run.extend(new);
+                        None
+                    }
+                    (
+                        Some(Statement::Upsert { columns, rows }),
+                        Statement::Upsert {
+                            columns: new_columns,
+                            rows: new,
+                        },
+                    ) if *columns == new_columns
+                        && self.fits(rows.len(), columns.len())
+                        && !key.as_ref().is_some_and(|key| keys.contains(key)) =>
+                    {
+                        rows.extend(new);
+                        None
+                    }
+                    (
+                        Some(Statement::Copy { columns, rows }),
+                        Statement::Copy {
+                            columns: new_columns,
+                            rows: new,
+                        },
+                    ) if *columns == new_columns && rows.len() < self.batch_size => {
+                        rows.extend(new);
+                        None
+                    }
+                    (_, change) => Some(change),
+                };
+                if let Some(change) = merged {
+                    keys.clear();
+                    statements.push(change);
+                }
+                keys.extend(key);
+            }
+        }
+        Ok(statements)
+    }
+
+    // fits tells whether a statement of `rows` rows has room for another row of
+    // `columns` values
+    fn fits(&self, rows: usize, columns: usize) -> bool {
+        rows < self.batch_size && (rows + 1) * columns.max(1) <= MAX_PARAMETERS
+    }
+
+    async fn execute(
+        &self,
+        tx: &Transaction<'_>,
+        table: &Table,
+        statement: Statement,
+    ) -> Result<(), Error> {
+        let relation = &table.relation;
+        let cast =
+            |column: &str, index: usize| format!("${}::text::{}", index, table.columns[column]);
+        let (sql, values): (String, Vec<Option<String>>) = match statement {
+            Statement::Upsert { columns, rows } => {
+                let mut index = 0;
+                let tuples: Vec<String> = rows
+                    .iter()
+                    .map(|_| {
+                        let casts: Vec<String> = columns
+                            .iter()
+                            .map(|column| {
+                                index += 1;
+                                cast(column, index)
+                            })
+                            .collect();
+                        format!("({})", casts.join(", "))
+                    })
+                    .collect();
+                let names: Vec<String> = columns.iter().map(|c| quote_ident(c)).collect();
+                let mut sql = format!(
+                    "INSERT INTO {} ({}) VALUES {}",
+                    relation,
+                    names.join(", "),
+                    tuples.join(", ")
+                );
+                if !table.key.is_empty() {
+                    let key: Vec<String> = table.key.iter().map(|c| quote_ident(c)).collect();
+                    let updates: Vec<String> = columns
+                        .iter()
+                        .filter(|column| !table.key.contains(column))
+                        .map(|column| {
+                            let column = quote_ident(column);
+                            format!("{} = EXCLUDED.{}", column, column)
+                        })
+                        .collect();
+                    sql.push_str(&format!(" ON CONFLICT ({}) DO ", key.join(", ")));
+                    if updates.is_empty() {
+                        sql.push_str("NOTHING");
+                    } else {
+                        sql.push_str(&format!("UPDATE SET {}", updates.join(", ")));
+                    }
+                }
+                (sql, rows.into_iter().flatten().collect())
+            }
+            Statement::Delete { keys } => {
+                let mut index = 0;
+                let tuples: Vec<String> = keys
+                    .iter()
+                    .map(|_| {
+                        let casts: Vec<String> = table
+                            .key
+                            .iter()
+                            .map(|column| {
+                                index += 1;
+                                cast(column, index)
+                            })
+                            .collect();
+                        format!("({})", casts.join(", "))
+                    })
+                    .collect();
+                let key: Vec<String> = table.key.iter().map(|c| quote_ident(c)).collect();
+                let sql = format!(
+                    "DELETE FROM {} WHERE ({}) IN ({})",
+                    relation,
+                    key.join(", "),
+                    tuples.join(", ")
+                );
+                (sql, keys.into_iter().flatten().collect())
+            }
+            Statement::Copy { columns, rows } => {
+                let names: Vec<String> = columns.iter().map(|c| quote_ident(c)).collect();
+                let sink = tx
+                    .copy_in(&format!(
+                        "COPY {} ({}) FROM STDIN",
+                        relation,
+                        names.join(", ")
+                    ))
+                    .await?;
+                let mut sink = pin!(sink);
+                sink.send(Bytes::from(copy_text(&rows))).await?;
+                sink.finish().await?;
+                return Ok(());
+            }
+        };
+        let params: Vec<&(dyn ToSql + Sync)> = values
+            .iter()
+            .map(|value| value as &(dyn ToSql + Sync))
+            .collect();
+        tx.execute(&sql, &params).await?;
+        Ok(())
+    }
+}
+
+// key_values returns the text form of a row's key
+fn key_values(table: &Table, row: &Row) -> Result<Values, Error> {
+    if table.key.is_empty() {
+        return Err("the table has no primary key to apply deletes by, set `key`".into());
+    }
+    table
+        .key
+        .iter()
+        .map(|column| match row.get(column) {
+            Some(value) => Ok(value.to_text()),
+            None => Err(format!("record is missing key column `{}`", column).into()),
+        })
+        .collect()
+}
+
+// copy_text renders rows in the text format of COPY
+fn copy_text(rows: &[Values]) -> String {
+    let mut text = String::new();
+    for row in rows {
+        for (i, value) in row.iter().enumerate() {
+            if i > 0 {
+                text.push('\t');
+            }
+            match value {
+                None => text.push_str("\\N"),
+                Some(value) => {
+                    for c in value.chars() {
+                        match c {
+                            '\\' => text.push_str("\\\\"),
+                            '\t' => text.push_str("\\t"),
+                            '\n' => text.push_str("\\n"),
+                            '\r' => text.push_str("\\r"),
+                            c => text.push(c),
+                        }
+                    }
+                }
+            }
+        }
+        text.push('\n');
+    }
+    text
+}
+
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+#[async_trait]
+impl Sink for PgSink {
+    // open checks the database can be reached. Tables are looked up as records
+    // for them arrive.
+    async fn open(&mut self) -> Result<(), Error> {
+        info!("connecting postgres sink to {}", self.database);
+        let _ = self.pool.get().await?;
+        Ok(())
+    }
+
+    async fn write(&mut self, records: &[Record]) -> Result<(), Error> {
+        // records go to their tables in order, whatever the other tables get
+        let mut groups: Vec<((String, String), Vec<&Record>)> = vec![];
+        for record in records {
+            let schema = self.schema.render(record)?;
+            let relation = format!(
+                "{}.{}",
+                quote_ident(&schema),
+                quote_ident(&self.table.render(record)?)
+            );
+            match groups.iter_mut().find(|((_, r), _)| *r == relation) {
+                Some((_, group)) => group.push(record),
+                None => groups.push(((schema, relation), vec![record])),
+            }
+        }
+
+        let mut client = self.pool.get().await?;
+        let mut statements = vec![];
+        for ((schema, relation), records) in &groups {
+            self.prepare(&client, schema, relation, records).await?;
+            let table = &self.tables[relation];
+            statements.extend(
+                self.plan(table, records)?
+                    .into_iter()
+                    .map(|statement| (relation, statement)),
+            );
+        }
+        let tx = client.transaction().await?;
+        for (relation, statement) in statements {
+            self.execute(&tx, &self.tables[relation], statement).await?;
+        }
+        tx.commit().await?;
+        Ok(())
+    }
+
+    // close leaves the pool open for the connector's other sources and sinks
+    async fn close(&mut self) -> Result<(), Error> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{Secret, SslMode};
+    use record::{Metadata, Value};
+
+    fn sink(pairs: &[(&str, &str)]) -> Result<PgSink, Error> {
+        let rds = RdsConfig {
+            host: "localhost".to_string(),
+            port: 5432,
+            user: "postgres".to_string(),
+            password: Secret::new("password"),
+            sslmode: SslMode::Disable,
+            sslrootcert: None,
+            sslcert: None,
+            sslkey: None,
+            pool_max_size: None,
+            connect_timeout: 10,
+            pool_timeout: 30,
+            statement_timeout: None,
+            application_name: "fust".to_string(),
+            keepalives_idle: 60,
+            retry: Default::default(),
+        };
+        let mut settings: HashMap<String, String> = pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        settings.insert("database".to_string(), "shop".to_string());
+        settings.insert("table".to_string(), "orders".to_string());
+        PgSink::from_rds("db", &rds, &settings, &Pools::new())
+    }
+
+    fn table() -> Table {
+        Table {
+            relation: "\"public\".\"orders\"".to_string(),
+            columns: HashMap::from([
+                ("id".to_string(), "bigint".to_string()),
+                ("total".to_string(), "numeric(10,2)".to_string()),
+            ]),
+            key: vec!["id".to_string()],
+        }
+    }
+
+    fn record(op: Op, before: Option<i64>, after: Option<(i64, &str)>) -> Record {
+        let row = |id: i64, total: &str| {
+            Row::from_iter([
+                ("id".to_string(), Value::Int(id)),
+                ("total".to_string(), Value::Decimal(total.to_string())),
+            ])
+        };
+        Record {
+            op,
+            key: vec!["id".to_string()],
+            before: before.map(|id| row(id, "0")),
+            after: after.map(|(id, total)| row(id, total)),
+            metadata: Metadata::default(),
+            event_time: None,
+        }
+    }
+
+    fn text(values: &[&str]) -> Values {
+        values.iter().map(|v| Some(v.to_string())).collect()
+    }
+
+    #[test]
+    fn batches_changes_by_kind_and_key() {
+        let sink = sink(&[("batch_size", "3")]).unwrap();
+        let records = [
+            record(Op::Snapshot, None, Some((1, "1.00"))),
+            record(Op::Insert, None, Some((2, "2.00"))),
+            record(Op::Update, Some(1), Some((1, "1.50"))),
+            record(Op::Insert, None, Some((3, "3.00"))),
+            record(Op::Delete, Some(2), None),
+            record(Op::Delete, Some(3), None),
+            record(Op::Update, Some(4), Some((5, "5.00"))),
+        ];
+        let columns = vec!["id".to_string(), "total".to_string()];
+        assert_eq!(
+            sink.plan(&table(), &records.iter().collect::<Vec<_>>())
+                .unwrap(),
+            vec![
+                Statement::Upsert {
+                    columns: columns.clone(),
+                    rows: vec![text(&["1", "1.00"]), text(&["2", "2.00"])],
+                },
+                // the second change to row 1 starts a new statement
+                Statement::Upsert {
+                    columns: columns.clone(),
+                    rows: vec![text(&["1", "1.50"]), text(&["3", "3.00"])],
+                },
+                Statement::Delete {
+                    keys: vec![text(&["2"]), text(&["3"]), text(&["4"])],
+                },
+                Statement::Upsert {
+                    columns,
+                    rows: vec![text(&["5", "5.00"])],
+                },
+            ]
+        );
+
+        let mut unknown = record(Op::Insert, None, Some((6, "6.00")));
+        unknown.after.as_mut().unwrap().insert("note", Value::Null);
+        assert!(sink.plan(&table(), &[&unknown]).is_err());
+    }
+
+    #[test]
+    fn copies_inserts() {
+        let sink = sink(&[("copy", "true")]).unwrap();
+        let records = [
+            record(Op::Snapshot, None, Some((1, "1.00"))),
+            record(Op::Insert, None, Some((2, "2.00"))),
+            record(Op::Update, Some(2), Some((2, "2.50"))),
+        ];
+        let statements = sink
+            .plan(&table(), &records.iter().collect::<Vec<_>>())
+            .unwrap();
+        assert!(matches!(&statements[0], Statement::Copy { rows, .. } if rows.len() == 2));
+        assert!(matches!(&statements[1], Statement::Upsert { .. }));
+
+        assert_eq!(
+            copy_text(&[
+                vec![Some("a\tb\\c".to_string()), None],
+                text(&["line\nbreak", "x"])
+            ]),
+            "a\\tb\\\\c\t\\N\nline\\nbreak\tx\n"
+        );
+        assert!(self::sink(&[("copy", "yes")]).is_err());
+    }
+
+    #[test]
+    fn creates_tables_from_records() {
+        let sink = sink(&[("schema_evolution", "additive")])
+            .unwrap()
+            .with_types(HashMap::from([(
+                FieldType::Decimal,
+                "numeric(38, 10)".to_string(),
+            )]));
+        let mut first = record(Op::Insert, None, Some((1, "1.00")));
+        first.after.as_mut().unwrap().insert("note", Value::Null);
+        first.after.as_mut().unwrap().insert("seen", Value::Null);
+        let mut second = record(Op::Insert, None, Some((2, "2.00")));
+        second
+            .after
+            .as_mut()
+            .unwrap()
+            .insert("note", Value::String("gift".to_string()));
+        assert_eq!(
+            sink.create_sql("public", "\"public\".\"orders\"", &[&first, &second]),
+            "CREATE SCHEMA IF NOT EXISTS \"public\"; \
+             CREATE TABLE IF NOT EXISTS \"public\".\"orders\" \
+             (\"id\" bigint, \"total\" numeric(38, 10), \"note\" text, \"seen\" text, \
+             PRIMARY KEY (\"id\"))"
+        );
+        assert!(self::sink(&[("schema_evolution", "loose")]).is_err());
+    }
+}
@@ -0,0 +1,51 @@
+use std::collections::HashMap;
+
+use config::FieldType;
+use record::{Error, Record};
+
+// Evolution is how far a sink may change target tables to fit its records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Evolution {
+    // None leaves tables as they are, so they must exist with every column
+    None,
+    // Additive creates missing tables and adds the columns records bring
+    Additive,
+    // Strict creates missing tables but never alters one: a record must have
+    // exactly the columns of its table
+    Strict,
+}
+
+impl Evolution {
+    // from_settings reads a sink's `schema_evolution` setting
+    pub fn from_settings(settings: &HashMap<String, String>) -> Result<Self, Error> {
+        match settings.get("schema_evolution").map(String::as_str) {
+            None | Some("none") => Ok(Evolution::None),
+            Some("additive") => Ok(Evolution::Additive),
+            Some("strict") => Ok(Evolution::Strict),
+            Some(other) => Err(format!(
+                "invalid schema_evolution `{}`, expected `none`, `additive` or `strict`",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
+// columns lists the columns of the rows records write, in the order they first
+// appear, with the type of their first value that is not NULL
+pub fn columns(records: &[&Record]) -> Vec<(String, Option<FieldType>)> {
+    let mut columns: Vec<(String, Option<FieldType>)> = vec![];
+    for row in records.iter().filter_map(|record| record.row()) {
+        for (name, value) in row.iter() {
+            match columns.iter_mut().find(|(column, _)| column == name) {
+                Some((_, field_type)) => {
+                    if field_type.is_none() {
+                        *field_type = value.field_type();
+                    }
+                }
+                None => columns.push((name.to_string(), value.field_type())),
+            }
+        }
+    }
+    columns
+}
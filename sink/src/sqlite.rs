@@ -0,0 +1,533 @@
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use config::{ConnectorConfig, FieldType, SinkConfig, SqliteConfig};
+use record::{Error, Op, Record, Row, Value};
+use rusqlite::{params_from_iter, types::Value as SqlValue, Connection, Transaction};
+use tracing::info;
+use util::sqlite::Database;
+
+use crate::{
+    message::Template,
+    schema::{columns, Evolution},
+    Sink,
+};
+
+// Table is what the sink knows of a target table
+#[derive(Debug, Clone)]
+struct Table {
+    // name is the quoted name of the table
+    name: String,
+    // columns maps every column to its declared type
+    columns: HashMap<String, String>,
+    key: Vec<String>,
+}
+
+// SqliteSink applies records to the table rendered from the `table` template:
+// inserts, updates and snapshot rows are upserted with `INSERT ... ON CONFLICT DO
+// UPDATE` on the key, and deletes remove the row with the key. Each `write` is one
+// transaction.
+//
+// The key is the table's primary key unless `key` names other columns, which need
+// a unique index. `schema_evolution` works as for Postgres sinks, creating missing
+// tables and, with `additive`, adding the columns records bring.
+pub struct SqliteSink {
+    sqlite: SqliteConfig,
+    database: Option<Database>,
+    table: Template,
+    writer: Arc<Mutex<Writer>>,
+}
+
+// Writer applies records through a connection. It lives apart from the sink so
+// that it can be handed to the blocking thread a write runs on.
+struct Writer {
+    key: Option<Vec<String>>,
+    evolution: Evolution,
+    types: HashMap<FieldType, String>,
+    // tables caches the target tables by name
+    tables: HashMap<String, Table>,
+}
+
+impl SqliteSink {
+    pub fn new(config: &SinkConfig) -> Result<Self, Error> {
+        match &config.connector {
+            ConnectorConfig::Sqlite(sqlite) => {
+                Ok(Self::from_sqlite(sqlite, &config.config)?.with_types(config.types.clone()))
+            }
+            other => Err(format!("a {} connector cannot back a sqlite sink", other.kind()).into()),
+        }
+    }
+
+    // from_sqlite builds a sink from the connector's database file and its own
+    // settings
+    pub fn from_sqlite(
+        sqlite: &SqliteConfig,
+        settings: &HashMap<String, String>,
+    ) -> Result<Self, Error> {
+        let table = settings
+            .get("table")
+            .ok_or("sqlite sink is missing `table`")?;
+        let key = settings.get("key").map(|key| {
+            key.split(',')
+                .map(|column| column.trim().to_string())
+                .collect()
+        });
+        Ok(SqliteSink {
+            sqlite: sqlite.clone(),
+            database: None,
+            table: Template::parse(table)?,
+            writer: Arc::new(Mutex::new(Writer {
+                key,
+                evolution: Evolution::from_settings(settings)?,
+                types: HashMap::new(),
+                tables: HashMap::new(),
+            })),
+        })
+    }
+
+    // with_types maps field types to the column types the sink creates
+    pub fn with_types(self, types: HashMap<FieldType, String>) -> Self {
+        self.writer.lock().expect("sqlite writer lock").types = types;
+        self
+    }
+}
+
+impl Writer {
+    // column_type is the declared type of a new column holding `field_type`
+    // values. The names keep the field type when a SQLite source reads the table.
+    fn column_type(&self, field_type: Option<FieldType>) -> String {
+        let field_type = field_type.unwrap_or(FieldType::String);
+        if let Some(column_type) = self.types.get(&field_type) {
+            return column_type.clone();
+        }
+        match field_type {
+            FieldType::String => "TEXT",
+            FieldType::Number => "REAL",
+            FieldType::Boolean => "BOOLEAN",
+            FieldType::Date => "DATE",
+            FieldType::Object | FieldType::Array | FieldType::Json => "JSON",
+            FieldType::Int => "INTEGER",
+            FieldType::BigInt => "BIGINT",
+            FieldType::Decimal => "DECIMAL",
+            FieldType::Timestamp => "TIMESTAMP",
+            FieldType::Uuid => "UUID",
+            FieldType::Bytes => "BLOB",
+        }
+        .to_string()
+    }
+
+    // lookup finds a table's columns and key, or None when it does not exist
+    fn lookup(&self, tx: &Transaction, name: &str) -> Result<Option<Table>, Error> {
+        let mut statement = tx.prepare(&format!("PRAGMA table_info({})", name))?;
+        let mut primary_key = vec![];
+        let mut columns = HashMap::new();
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let column: String = row.get("name")?;
+            let pk: i64 = row.get("pk")?;
+            if pk > 0 {
+                primary_key.push((pk, column.clone()));
+            }
+            columns.insert(column, row.get::<_, String>("type")?);
+        }
+        if columns.is_empty() {
+            return Ok(None);
+        }
+        primary_key.sort();
+        let key = match &self.key {
+            Some(key) => key.clone(),
+            None => primary_key.into_iter().map(|(_, column)| column).collect(),
+        };
+        if let Some(column) = key.iter().find(|column| !columns.contains_key(*column)) {
+            return Err(format!("{} has no key column `{}`", name, column).into());
+        }
+        Ok(Some(Table {
+            name: name.to_string(),
+            columns,
+            key,
+        }))
+    }
+
+    // prepare makes sure the table `records` go to exists and fits them, as far
+    // as `schema_evolution` allows
+    fn prepare(&mut self, tx: &Transaction, name: &str, records: &[&Record]) -> Result<(), Error> {
+        if !self.tables.contains_key(name) {
+            let mut table = self.lookup(tx, name)?;
+            if table.is_none() && self.evolution != Evolution::None {
+                info!("creating {}", name);
+                tx.execute_batch(&self.create_sql(name, records))?;
+                table = self.lookup(tx, name)?;
+            }
+            let table = table.ok_or_else(|| format!("{} does not exist", name))?;
+            self.tables.insert(name.to_string(), table);
+        }
+
+        let table = &self.tables[name];
+        let missing: Vec<(String, Option<FieldType>)> = columns(records)
+            .into_iter()
+            .filter(|(column, _)| !table.columns.contains_key(column))
+            .collect();
+        match self.evolution {
+            Evolution::None => {}
+            Evolution::Strict => {
+                if let Some((column, _)) = missing.first() {
+                    return Err(format!("{} has no column `{}`", name, column).into());
+                }
+                for row in records.iter().filter_map(|record| record.after.as_ref()) {
+                    if let Some(column) = table.columns.keys().find(|c| row.get(c).is_none()) {
+                        return Err(
+                            format!("record for {} has no column `{}`", name, column).into()
+                        );
+                    }
+                }
+            }
+            Evolution::Additive => {
+                for (column, field_type) in missing {
+                    let column_type = self.column_type(field_type);
+                    info!("adding column `{}` {} to {}", column, column_type, name);
+                    tx.execute_batch(&format!(
+                        "ALTER TABLE {} ADD COLUMN {} {}",
+                        name,
+                        quote_ident(&column),
+                        column_type
+                    ))?;
+                    let table = self.tables.get_mut(name).expect("table was prepared");
+                    table.columns.insert(column, column_type);
+                }
+            }
+        }
+        Ok(())
+    }
+
+    // create_sql creates a table with the columns of `records`, keyed by the
+    // configured key or the records' own
+    fn create_sql(&self, name: &str, records: &[&Record]) -> String {
+        let mut definitions: Vec<String> = columns(records)
+            .into_iter()
+            .map(|(column, field_type)| {
+                format!("{} {}", quote_ident(&column), self.column_type(field_type))
+            })
+            .collect();
+        let key = match &self.key {
+            Some(key) => key.clone(),
+            None => records
+                .iter()
+                .map(|record| record.key.clone())
+                .find(|key| !key.is_empty())
+                .unwrap_or_default(),
+        };
+        if !key.is_empty() {
+            let key: Vec<String> = key.iter().map(|c| quote_ident(c)).collect();
+            definitions.push(format!("PRIMARY KEY ({})", key.join(", ")));
+        }
+        format!(
+            "CREATE TABLE IF NOT EXISTS {} ({})",
+            name,
+            definitions.join(", ")
+        )
+    }
+
+    // write applies records grouped by table in one transaction. The cached
+    // tables are dropped when it fails, as the transaction may have created or
+    // altered them.
+    fn write(
+        &mut self,
+        conn: &mut Connection,
+        groups: &[(String, Vec<Record>)],
+    ) -> Result<(), Error> {
+        let result = (|| {
+            let tx = conn.transaction()?;
+            for (name, records) in groups {
+                let records: Vec<&Record> = records.iter().collect();
+                self.prepare(&tx, name, &records)?;
+                let table = &self.tables[name];
+                for record in records {
+                    apply(&tx, table, record)?;
+                }
+            }
+            tx.commit()?;
+            Ok(())
+        })();
+        if result.is_err() {
+            self.tables.clear();
+        }
+        result
+    }
+}
+
+// apply writes one record to its table
+fn apply(tx: &Transaction, table: &Table, record: &Record) -> Result<(), Error> {
+    match record.op {
+        Op::Delete => {
+            let row = record.row().ok_or("delete record has no row")?;
+            delete(tx, table, key_values(table, row)?)
+        }
+        Op::Insert | Op::Snapshot | Op::Update => {
+            let row = record.after.as_ref().ok_or("record has no row to write")?;
+            if table.key.is_empty() && record.op == Op::Update {
+                return Err(format!(
+                    "{} has no primary key to apply updates by, set `key`",
+                    table.name
+                )
+                .into());
+            }
+            if let (Some(before), false) = (&record.before, table.key.is_empty()) {
+                // a change to the key moves the row. A before image without the
+                // key cannot tell, and is taken as the same row.
+                if let Ok(old) = key_values(table, before) {
+                    if old != key_values(table, row)? {
+                        delete(tx, table, old)?;
+                    }
+                }
+            }
+            let columns: Vec<&str> = row.iter().map(|(name, _)| name).collect();
+            for column in &columns {
+                if !table.columns.contains_key(*column) {
+                    return Err(format!("{} has no column `{}`", table.name, column).into());
+                }
+            }
+            let values: Vec<SqlValue> = row.iter().map(|(_, value)| sql_value(value)).collect();
+            tx.prepare_cached(&upsert_sql(table, &columns))?
+                .execute(params_from_iter(values))?;
+            Ok(())
+        }
+    }
+}
+
+fn delete(tx: &Transaction, table: &Table, key: Vec<SqlValue>) -> Result<(), Error> {
+    let conditions: Vec<String> = table
+        .key
+        .iter()
+        .enumerate()
+        .map(|(i, column)| format!("{} = ?{}", quote_ident(column), i + 1))
+        .collect();
+    let sql = format!(
+        "DELETE FROM {} WHERE {}",
+        table.name,
+        conditions.join(" AND ")
+    );
+    tx.prepare_cached(&sql)?.execute(params_from_iter(key))?;
+    Ok(())
+}
+
+// upsert_sql inserts a row of `columns`, updating the row with its key when
+// there is one
+fn upsert_sql(table: &Table, columns: &[&str]) -> String {
+    let names: Vec<String> = columns.iter().map(|c| quote_ident(c)).collect();
+    let params: Vec<String> = (1..=columns.len()).map(|i| format!("?{}", i)).collect();
+    let mut sql = format!(
+        "INSERT INTO {} ({}) VALUES ({})",
+        table.name,
+        names.join(", "),
+        params.join(", ")
+    );
+    if !table.key.is_empty() {
+        let key: Vec<String> = table.key.iter().map(|c| quote_ident(c)).collect();
+        let updates: Vec<String> = columns
+            .iter()
+            .filter(|column| !table.key.iter().any(|key| key == *column))
+            .map(|column| {
+                let column = quote_ident(column);
+                format!("{} = excluded.{}", column, column)
+            })
+            .collect();
+        sql.push_str(&format!(" ON CONFLICT ({}) DO ", key.join(", ")));
+        if updates.is_empty() {
+            sql.push_str("NOTHING");
+        } else {
+            sql.push_str(&format!("UPDATE SET {}", updates.join(", ")));
+        }
+    }
+    sql
+}
+
+// key_values returns a row's key, in key column order
+fn key_values(table: &Table, row: &Row) -> Result<Vec<SqlValue>, Error> {
+    if table.key.is_empty() {
+        return Err("the table has no primary key to apply deletes by, set `key`".into());
+    }
+    table
+        .key
+        .iter()
+        .map(|column| match row.get(column) {
+            Some(value) => Ok(sql_value(value)),
+            None => Err(format!("record is missing key column `{}`", column).into()),
+        })
+        .collect()
+}
+
+// sql_value stores numbers, booleans and bytes natively, and everything else in
+// its text form
+fn sql_value(value: &Value) -> SqlValue {
+    match value {
+        Value::Null => SqlValue::Null,
+        Value::Bool(b) => SqlValue::Integer(*b as i64),
+        Value::Int(i) => SqlValue::Integer(*i),
+        Value::Float(f) => SqlValue::Real(*f),
+        Value::Bytes(bytes) => SqlValue::Blob(bytes.clone()),
+        other => other.to_text().map_or(SqlValue::Null, SqlValue::Text),
+    }
+}
+
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+#[async_trait]
+impl Sink for SqliteSink {
+    // open opens the database file, creating it when it does not exist. Tables
+    // are looked up as records for them arrive.
+    async fn open(&mut self) -> Result<(), Error> {
+        info!("opening sqlite sink {}", self.sqlite.path);
+        self.database = Some(Database::open(&self.sqlite, true).await?);
+        Ok(())
+    }
+
+    async fn write(&mut self, records: &[Record]) -> Result<(), Error> {
+        let database = self
+            .database
+            .as_ref()
+            .ok_or("sqlite sink written before it was opened")?;
+        // records go to their tables in order, whatever the other tables get
+        let mut groups: Vec<(String, Vec<Record>)> = vec![];
+        for record in records {
+            let name = quote_ident(&self.table.render(record)?);
+            match groups.iter_mut().find(|(n, _)| *n == name) {
+                Some((_, group)) => group.push(record.clone()),
+                None => groups.push((name, vec![record.clone()])),
+            }
+        }
+
+        let writer = self.writer.clone();
+        database
+            .call(move |conn| {
+                writer
+                    .lock()
+                    .expect("sqlite writer lock")
+                    .write(conn, &groups)
+            })
+            .await
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        info!("closing sqlite sink {}", self.sqlite.path);
+        self.database = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use record::Metadata;
+
+    fn record(op: Op, id: i64, name: Option<&str>) -> Record {
+        let mut row = Row::new();
+        row.insert("id", Value::Int(id));
+        if let Some(name) = name {
+            row.insert("name", Value::String(name.to_string()));
+        }
+        let (before, after) = match op {
+            Op::Delete => (Some(row), None),
+            _ => (None, Some(row)),
+        };
+        Record {
+            op,
+            key: vec!["id".to_string()],
+            before,
+            after,
+            metadata: Metadata {
+                connector: "sqlite".to_string(),
+                table: Some("people".to_string()),
+                ..Metadata::default()
+            },
+            event_time: None,
+        }
+    }
+
+    #[tokio::test]
+    async fn applies_upserts_and_deletes() {
+        let dir = tempfile::tempdir().unwrap();
+        let sqlite = SqliteConfig {
+            path: dir.path().join("edge.db").to_str().unwrap().to_string(),
+            busy_timeout: 1000,
+            retry: Default::default(),
+        };
+        let settings = HashMap::from([
+            ("table".to_string(), "{table}_copy".to_string()),
+            ("schema_evolution".to_string(), "additive".to_string()),
+        ]);
+        let mut sink = SqliteSink::from_sqlite(&sqlite, &settings).unwrap();
+        sink.open().await.unwrap();
+
+        sink.write(&[
+            record(Op::Snapshot, 1, Some("ada")),
+            record(Op::Snapshot, 2, Some("grace")),
+            record(Op::Insert, 3, None),
+        ])
+        .await
+        .unwrap();
+        let mut renamed = record(Op::Update, 4, Some("barbara"));
+        renamed.before = record(Op::Delete, 2, None).before;
+        sink.write(&[
+            record(Op::Update, 1, Some("ada lovelace")),
+            renamed,
+            record(Op::Delete, 3, None),
+        ])
+        .await
+        .unwrap();
+
+        let rows: Vec<(i64, Option<String>)> = sink
+            .database
+            .as_ref()
+            .unwrap()
+            .call(|conn| {
+                let mut statement = conn.prepare("SELECT id, name FROM people_copy ORDER BY id")?;
+                let rows = statement
+                    .query_map([], |row| Ok((row.get(0)?, row.get(1)?)))?
+                    .collect::<Result<_, _>>()?;
+                Ok(rows)
+            })
+            .await
+            .unwrap();
+        assert_eq!(
+            rows,
+            vec![
+                (1, Some("ada lovelace".to_string())),
+                (4, Some("barbara".to_string())),
+            ]
+        );
+
+        let mut nicknamed = record(Op::Insert, 5, None);
+        nicknamed
+            .after
+            .as_mut()
+            .unwrap()
+            .insert("nickname", Value::Null);
+        sink.write(&[nicknamed]).await.unwrap();
+        let writer = sink.writer.lock().unwrap();
+        assert_eq!(writer.tables["\"people_copy\""].columns["nickname"], "TEXT");
+        assert_eq!(writer.tables["\"people_copy\""].key, vec!["id"]);
+    }
+
+    #[test]
+    fn builds_upserts() {
+        let table = Table {
+            name: "\"people\"".to_string(),
+            columns: HashMap::new(),
+            key: vec!["id".to_string()],
+        };
+        assert_eq!(
+            upsert_sql(&table, &["id", "name"]),
+            "INSERT INTO \"people\" (\"id\", \"name\") VALUES (?1, ?2) \
+             ON CONFLICT (\"id\") DO UPDATE SET \"name\" = excluded.\"name\""
+        );
+        assert_eq!(
+            upsert_sql(&table, &["id"]),
+            "INSERT INTO \"people\" (\"id\") VALUES (?1) ON CONFLICT (\"id\") DO NOTHING"
+        );
+    }
+}
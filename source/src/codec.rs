@@ -0,0 +1,285 @@
+use chrono::{DateTime, Utc};
+use config::{Field, FieldType};
+use record::{Error, Metadata, Op, Record, Row, Value};
+use serde_json::Map;
+
+// Codec is how a message payload is turned into a record
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Codec {
+    // Json reads a JSON object as the row of an insert
+    Json,
+    // Record reads a record in the JSON form fust's own sinks write, keeping its
+    // operation, images and metadata
+    Record,
+    // Raw keeps the payload as is, in a `payload` column
+    Raw,
+}
+
+impl Codec {
+    pub fn parse(name: &str) -> Result<Self, Error> {
+        match name {
+            "json" => Ok(Codec::Json),
+            "record" => Ok(Codec::Record),
+            "raw" => Ok(Codec::Raw),
+            other => Err(format!(
+                "invalid codec `{}`, expected `json`, `record` or `raw`",
+                other
+            )
+            .into()),
+        }
+    }
+}
+
+// Decoder decodes payloads with a codec. Rows are typed by `fields` when they are
+// configured, keeping only those columns, and by the JSON values otherwise.
+#[derive(Debug, Clone)]
+pub struct Decoder {
+    codec: Codec,
+    key: Vec<String>,
+    fields: Vec<Field>,
+}
+
+impl Decoder {
+    pub fn new(codec: Codec, key: Vec<String>, fields: Vec<Field>) -> Self {
+        Decoder { codec, key, fields }
+    }
+
+    // decode turns a payload into a record described by `metadata`. A decoded
+    // `record` keeps its own metadata, with the attributes of `metadata` added.
+    pub fn decode(
+        &self,
+        payload: &[u8],
+        metadata: Metadata,
+        event_time: Option<DateTime<Utc>>,
+    ) -> Result<Record, Error> {
+        match self.codec {
+            Codec::Raw => {
+                let value = match std::str::from_utf8(payload) {
+                    Ok(text) => Value::String(text.to_string()),
+                    Err(_) => Value::Bytes(payload.to_vec()),
+                };
+                Ok(Record {
+                    op: Op::Insert,
+                    key: vec![],
+                    before: None,
+                    after: Some(Row::from_iter([("payload".to_string(), value)])),
+                    metadata,
+                    event_time,
+                })
+            }
+            Codec::Json => {
+                let object = match serde_json::from_slice(payload)? {
+                    serde_json::Value::Object(object) => object,
+                    _ => return Err("payload is not a JSON object".into()),
+                };
+                Ok(Record {
+                    op: Op::Insert,
+                    key: self.key.clone(),
+                    before: None,
+                    after: Some(self.row(&object)?),
+                    metadata,
+                    event_time,
+                })
+            }
+            Codec::Record => self.record(payload, metadata, event_time),
+        }
+    }
+
+    fn record(
+        &self,
+        payload: &[u8],
+        metadata: Metadata,
+        event_time: Option<DateTime<Utc>>,
+    ) -> Result<Record, Error> {
+        let json: serde_json::Value = serde_json::from_slice(payload)?;
+        let op = match json["op"].as_str() {
+            Some("snapshot") => Op::Snapshot,
+            Some("insert") => Op::Insert,
+            Some("update") => Op::Update,
+            Some("delete") => Op::Delete,
+            _ => return Err(format!("invalid op {}", json["op"]).into()),
+        };
+        let image = |name: &str| -> Result<Option<Row>, Error> {
+            match &json[name] {
+                serde_json::Value::Null => Ok(None),
+                serde_json::Value::Object(object) => Ok(Some(self.row(object)?)),
+                other => Err(format!("invalid {} image {}", name, other).into()),
+            }
+        };
+        let key = match &json["key"] {
+            serde_json::Value::Null => self.key.clone(),
+            serde_json::Value::Array(key) => key
+                .iter()
+                .map(|k| k.as_str().map(str::to_string))
+                .collect::<Option<_>>()
+                .ok_or("key must list column names")?,
+            other => return Err(format!("invalid key {}", other).into()),
+        };
+
+        let text = |value: &serde_json::Value| value.as_str().map(str::to_string);
+        let source = &json["metadata"];
+        let mut attributes = metadata.attributes;
+        if let Some(decoded) = source["attributes"].as_object() {
+            for (name, value) in decoded {
+                if let Some(value) = value.as_str() {
+                    attributes.insert(name.clone(), value.to_string());
+                }
+            }
+        }
+        let event_time = match json["event_time"].as_str() {
+            Some(time) => Some(DateTime::parse_from_rfc3339(time)?.with_timezone(&Utc)),
+            None => event_time,
+        };
+        Ok(Record {
+            op,
+            key,
+            before: image("before")?,
+            after: image("after")?,
+            metadata: Metadata {
+                connector: text(&source["connector"]).unwrap_or(metadata.connector),
+                database: text(&source["database"]).or(metadata.database),
+                schema: text(&source["schema"]).or(metadata.schema),
+                table: text(&source["table"]).or(metadata.table),
+                transaction: text(&source["transaction"]).or(metadata.transaction),
+                attributes,
+            },
+            event_time,
+        })
+    }
+
+    fn row(&self, object: &Map<String, serde_json::Value>) -> Result<Row, Error> {
+        if self.fields.is_empty() {
+            return Ok(object
+                .iter()
+                .map(|(name, value)| (name.clone(), untyped(value)))
+                .collect());
+        }
+        let mut row = Row::new();
+        for field in &self.fields {
+            let value = match object.get(&field.name) {
+                None | Some(serde_json::Value::Null) => Value::from_field(field, None)?,
+                Some(value) => typed(field, value)?,
+            };
+            row.insert(field.name.clone(), value);
+        }
+        Ok(row)
+    }
+}
+
+fn untyped(value: &serde_json::Value) -> Value {
+    match value {
+        serde_json::Value::Null => Value::Null,
+        serde_json::Value::Bool(b) => Value::Bool(*b),
+        serde_json::Value::Number(n) => match n.as_i64() {
+            Some(i) => Value::Int(i),
+            None => Value::Float(n.as_f64().unwrap_or(f64::NAN)),
+        },
+        serde_json::Value::String(s) => Value::String(s.clone()),
+        other => Value::Json(other.clone()),
+    }
+}
+
+fn typed(field: &Field, value: &serde_json::Value) -> Result<Value, String> {
+    match (field.field_type, value) {
+        (FieldType::Json | FieldType::Object | FieldType::Array, _) => {
+            Value::from_field(field, Some(&value.to_string()))
+        }
+        (_, serde_json::Value::String(text)) => Value::from_field(field, Some(text)),
+        (_, value) => Value::from_field(field, Some(&value.to_string())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::BTreeMap;
+
+    fn metadata() -> Metadata {
+        Metadata {
+            connector: "nats".to_string(),
+            attributes: BTreeMap::from([("subject".to_string(), "orders".to_string())]),
+            ..Metadata::default()
+        }
+    }
+
+    fn field(name: &str, field_type: FieldType, nullable: bool) -> Field {
+        Field {
+            name: name.to_string(),
+            field_type,
+            nullable,
+        }
+    }
+
+    #[test]
+    fn decodes_json_objects() {
+        let payload = br#"{"id": 7, "total": "9.50", "tags": ["a"], "extra": true}"#;
+        let decoder = Decoder::new(Codec::Json, vec!["id".to_string()], vec![]);
+        let record = decoder.decode(payload, metadata(), None).unwrap();
+        assert_eq!(record.op, Op::Insert);
+        assert_eq!(record.key_values(), vec![&Value::Int(7)]);
+        assert_eq!(record.row().unwrap().len(), 4);
+
+        let fields = vec![
+            field("id", FieldType::BigInt, false),
+            field("total", FieldType::Decimal, false),
+            field("tags", FieldType::Array, true),
+            field("note", FieldType::String, true),
+        ];
+        let decoder = Decoder::new(Codec::Json, vec!["id".to_string()], fields);
+        let record = decoder.decode(payload, metadata(), None).unwrap();
+        let row = record.row().unwrap();
+        assert_eq!(row.get("total"), Some(&Value::Decimal("9.50".to_string())));
+        assert_eq!(row.get("note"), Some(&Value::Null));
+        assert_eq!(row.get("extra"), None);
+
+        let missing = Decoder::new(
+            Codec::Json,
+            vec![],
+            vec![field("id", FieldType::Int, false)],
+        );
+        assert!(missing.decode(b"{}", metadata(), None).is_err());
+        assert!(decoder.decode(b"[1]", metadata(), None).is_err());
+    }
+
+    #[test]
+    fn decodes_records() {
+        let payload = br#"{
+            "op": "update",
+            "key": ["id"],
+            "before": {"id": 7, "total": "1.00"},
+            "after": {"id": 7, "total": "2.00"},
+            "metadata": {"connector": "postgres", "table": "orders", "attributes": {"lsn": "0/10"}},
+            "event_time": "2024-01-31T12:00:00+00:00"
+        }"#;
+        let decoder = Decoder::new(Codec::Record, vec![], vec![]);
+        let record = decoder.decode(payload, metadata(), None).unwrap();
+        assert_eq!(record.op, Op::Update);
+        assert_eq!(record.key, vec!["id"]);
+        assert!(record.before.is_some());
+        assert_eq!(record.metadata.connector, "postgres");
+        assert_eq!(record.metadata.table.as_deref(), Some("orders"));
+        assert_eq!(record.metadata.attributes["lsn"], "0/10");
+        assert_eq!(record.metadata.attributes["subject"], "orders");
+        assert!(record.event_time.is_some());
+
+        assert!(decoder
+            .decode(br#"{"op": "upsert"}"#, metadata(), None)
+            .is_err());
+    }
+
+    #[test]
+    fn keeps_raw_payloads() {
+        let decoder = Decoder::new(Codec::Raw, vec![], vec![]);
+        let record = decoder.decode(b"hello", metadata(), None).unwrap();
+        assert_eq!(
+            record.row().unwrap().get("payload"),
+            Some(&Value::String("hello".to_string()))
+        );
+        let record = decoder.decode(&[0xff, 0], metadata(), None).unwrap();
+        assert_eq!(
+            record.row().unwrap().get("payload"),
+            Some(&Value::Bytes(vec![0xff, 0]))
+        );
+        assert!(Codec::parse("avro").is_err());
+    }
+}
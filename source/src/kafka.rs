@@ -0,0 +1,632 @@
+use std::{
+    collections::{BTreeMap, HashMap},
+    sync::Mutex,
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use checkpoint::{PartitionOffset, Position};
+use chrono::DateTime;
+use config::{ConnectorConfig, Field, KafkaConfig, SourceConfig};
+use rdkafka::{
+    consumer::{CommitMode, Consumer, ConsumerContext, Rebalance, StreamConsumer},
+    error::KafkaResult,
+    message::BorrowedMessage,
+    ClientConfig, ClientContext, Message, Offset, TopicPartitionList,
+};
+use record::{Batch, Error, Metadata, Record};
+use tokio::sync::broadcast::Receiver;
+use tracing::{info, warn};
+
+use crate::{
+    codec::{Codec, Decoder},
+    Source,
+};
+
+const DEFAULT_BATCH_SIZE: usize = 100;
+// LINGER is how long a read waits for more messages once it has one
+const LINGER: Duration = Duration::from_millis(50);
+// TIMEOUT bounds the calls to the group coordinator made while positioning partitions
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+// Start is where a partition is read from when neither the checkpoint nor the
+// consumer group has an offset for it
+#[derive(Debug, Clone, PartialEq)]
+enum Start {
+    Earliest,
+    Latest,
+    // Timestamp starts at the first message at or after a time, in milliseconds
+    Timestamp(i64),
+    // Offsets starts every listed partition at an offset, and the others at the earliest
+    Offsets(HashMap<i32, i64>),
+}
+
+impl Start {
+    fn parse(start: &str) -> Result<Self, Error> {
+        match start {
+            "earliest" => return Ok(Start::Earliest),
+            "latest" => return Ok(Start::Latest),
+            _ => {}
+        }
+        if let Ok(time) = DateTime::parse_from_rfc3339(start) {
+            return Ok(Start::Timestamp(time.timestamp_millis()));
+        }
+        let offsets = start
+            .split(',')
+            .map(|pair| {
+                let (partition, offset) = pair.trim().split_once(':')?;
+                Some((partition.parse().ok()?, offset.parse().ok()?))
+            })
+            .collect::<Option<HashMap<i32, i64>>>();
+        match offsets {
+            Some(offsets) if offsets.values().all(|offset| *offset >= 0) => {
+                Ok(Start::Offsets(offsets))
+            }
+            _ => Err(format!(
+                "invalid start `{}`, expected `earliest`, `latest`, an RFC 3339 time or `partition:offset` pairs",
+                start
+            )
+            .into()),
+        }
+    }
+}
+
+// Rebalances collects the partitions the group assigns and revokes, so the source
+// can position new partitions before it reads from them
+#[derive(Default)]
+struct Rebalances {
+    assigned: Mutex<Vec<i32>>,
+    revoked: Mutex<Vec<i32>>,
+}
+
+impl ClientContext for Rebalances {}
+
+impl ConsumerContext for Rebalances {
+    fn post_rebalance(&self, rebalance: &Rebalance) {
+        match rebalance {
+            Rebalance::Assign(partitions) => {
+                let partitions: Vec<i32> = partitions
+                    .elements()
+                    .iter()
+                    .map(|p| p.partition())
+                    .collect();
+                info!("kafka source was assigned partitions {:?}", partitions);
+                let mut assigned = self.assigned.lock().expect("assigned lock poisoned");
+                assigned.extend(partitions);
+            }
+            Rebalance::Revoke(partitions) => {
+                let partitions: Vec<i32> = partitions
+                    .elements()
+                    .iter()
+                    .map(|p| p.partition())
+                    .collect();
+                info!("kafka source lost partitions {:?}", partitions);
+                let mut assigned = self.assigned.lock().expect("assigned lock poisoned");
+                assigned.retain(|partition| !partitions.contains(partition));
+                let mut revoked = self.revoked.lock().expect("revoked lock poisoned");
+                revoked.extend(partitions);
+            }
+            Rebalance::Error(e) => warn!("kafka source rebalance failed: {}", e),
+        }
+    }
+
+    fn commit_callback(&self, result: KafkaResult<()>, _offsets: &TopicPartitionList) {
+        if let Err(e) = result {
+            warn!("committing kafka offsets failed: {}", e);
+        }
+    }
+}
+
+// KafkaSource reads `topic` as a member of the consumer group `group`, decoding
+// messages with `codec`. Offsets are committed to the group only once the sinks
+// have written the messages before them.
+//
+// A partition the group assigns resumes from the later of the checkpoint and the
+// group's committed offset. When there is neither it starts at `start`: `earliest`
+// (the default), `latest`, an RFC 3339 time, or `partition:offset` pairs.
+pub struct KafkaSource {
+    client: ClientConfig,
+    topic: String,
+    start: Start,
+    decoder: Decoder,
+    batch_size: usize,
+    skip_errors: bool,
+    shutdown_rx: Receiver<()>,
+    consumer: Option<StreamConsumer<Rebalances>>,
+    // delivered is the next offset of every partition as of the last ack
+    delivered: HashMap<i32, i64>,
+    // read is the next offset of every partition this consumer has read
+    read: BTreeMap<i32, i64>,
+}
+
+impl KafkaSource {
+    pub fn new(config: &SourceConfig, shutdown_rx: Receiver<()>) -> Result<Self, Error> {
+        match &config.connector {
+            ConnectorConfig::Kafka(kafka) => {
+                Self::from_kafka(kafka, &config.config, config.fields.clone(), shutdown_rx)
+            }
+            other => Err(format!("a {} connector cannot back a kafka source", other.kind()).into()),
+        }
+    }
+
+    pub fn from_kafka(
+        kafka: &KafkaConfig,
+        settings: &HashMap<String, String>,
+        fields: Vec<Field>,
+        shutdown_rx: Receiver<()>,
+    ) -> Result<Self, Error> {
+        let topic = settings
+            .get("topic")
+            .map(|topic| topic.trim().to_string())
+            .filter(|topic| !topic.is_empty())
+            .ok_or("kafka source is missing `topic`")?;
+        let group = settings
+            .get("group")
+            .ok_or("kafka source is missing `group`")?;
+        let start = Start::parse(
+            settings
+                .get("start")
+                .map(String::as_str)
+                .unwrap_or("earliest"),
+        )?;
+        let batch_size = match settings.get("batch_size") {
+            Some(size) => match size.parse::<usize>() {
+                Ok(size) if size > 0 => size,
+                _ => return Err(format!("invalid batch_size `{}`", size).into()),
+            },
+            None => DEFAULT_BATCH_SIZE,
+        };
+        let skip_errors = match settings.get("on_error").map(String::as_str) {
+            None | Some("fail") => false,
+            Some("skip") => true,
+            Some(other) => {
+                return Err(
+                    format!("invalid on_error `{}`, expected `fail` or `skip`", other).into(),
+                )
+            }
+        };
+        let codec = Codec::parse(settings.get("codec").map(String::as_str).unwrap_or("json"))?;
+        let key = settings
+            .get("key")
+            .map(|key| {
+                key.split(',')
+                    .map(|column| column.trim().to_string())
+                    .filter(|column| !column.is_empty())
+                    .collect()
+            })
+            .unwrap_or_default();
+
+        let mut client = ClientConfig::new();
+        client
+            .set("bootstrap.servers", &kafka.brokers)
+            .set("group.id", group)
+            .set("enable.auto.commit", "false")
+            .set(
+                "auto.offset.reset",
+                if start == Start::Latest {
+                    "latest"
+                } else {
+                    "earliest"
+                },
+            );
+        Ok(KafkaSource {
+            client,
+            topic,
+            start,
+            decoder: Decoder::new(codec, key, fields),
+            batch_size,
+            skip_errors,
+            shutdown_rx,
+            consumer: None,
+            delivered: HashMap::new(),
+            read: BTreeMap::new(),
+        })
+    }
+
+    // position seeks the partitions assigned since it last ran and returns them.
+    // Anything already read from them is discarded, as it is read again from
+    // the new position.
+    fn position(&mut self) -> Result<Vec<i32>, Error> {
+        let consumer = self
+            .consumer
+            .as_ref()
+            .ok_or("kafka source read before it was opened")?;
+        let assigned = std::mem::take(
+            &mut *consumer
+                .context()
+                .assigned
+                .lock()
+                .expect("assigned lock poisoned"),
+        );
+        if assigned.is_empty() {
+            return Ok(assigned);
+        }
+
+        let mut partitions = TopicPartitionList::new();
+        for partition in &assigned {
+            partitions.add_partition(&self.topic, *partition);
+        }
+        let committed = consumer.committed_offsets(partitions, TIMEOUT)?;
+        let mut targets = HashMap::new();
+        let mut timestamps = TopicPartitionList::new();
+        for partition in &assigned {
+            let committed = committed
+                .find_partition(&self.topic, *partition)
+                .and_then(|p| match p.offset() {
+                    Offset::Offset(offset) => Some(offset),
+                    _ => None,
+                });
+            let target = match (self.delivered.get(partition).copied(), committed) {
+                (Some(delivered), Some(committed)) => Offset::Offset(delivered.max(committed)),
+                (Some(offset), None) | (None, Some(offset)) => Offset::Offset(offset),
+                (None, None) => match &self.start {
+                    Start::Earliest => Offset::Beginning,
+                    Start::Latest => Offset::End,
+                    Start::Offsets(offsets) => offsets
+                        .get(partition)
+                        .map(|offset| Offset::Offset(*offset))
+                        .unwrap_or(Offset::Beginning),
+                    Start::Timestamp(time) => {
+                        timestamps.add_partition_offset(
+                            &self.topic,
+                            *partition,
+                            Offset::Offset(*time),
+                        )?;
+                        continue;
+                    }
+                },
+            };
+            targets.insert(*partition, target);
+        }
+        if timestamps.count() > 0 {
+            for p in consumer.offsets_for_times(timestamps, TIMEOUT)?.elements() {
+                targets.insert(p.partition(), p.offset());
+            }
+        }
+
+        for (partition, target) in targets {
+            self.read.remove(&partition);
+            info!(
+                "kafka source reads {} partition {} from {:?}",
+                self.topic, partition, target
+            );
+            consumer.seek(&self.topic, partition, target, TIMEOUT)?;
+        }
+        Ok(assigned)
+    }
+
+    // forget drops the partitions the group revoked, so later positions do not
+    // carry offsets another consumer now owns
+    fn forget(&mut self) {
+        let Some(consumer) = &self.consumer else {
+            return;
+        };
+        let revoked = std::mem::take(
+            &mut *consumer
+                .context()
+                .revoked
+                .lock()
+                .expect("revoked lock poisoned"),
+        );
+        for partition in revoked {
+            self.read.remove(&partition);
+        }
+    }
+
+    // commit commits `offsets` for the partitions still assigned to this consumer
+    fn commit(&self, offsets: &HashMap<i32, i64>, mode: CommitMode) -> Result<(), Error> {
+        let Some(consumer) = &self.consumer else {
+            return Ok(());
+        };
+        let assignment = consumer.assignment()?;
+        let mut committed = TopicPartitionList::new();
+        for (partition, offset) in offsets {
+            if assignment.find_partition(&self.topic, *partition).is_some() {
+                committed.add_partition_offset(&self.topic, *partition, Offset::Offset(*offset))?;
+            }
+        }
+        if committed.count() > 0 {
+            consumer.commit(&committed, mode)?;
+        }
+        Ok(())
+    }
+}
+
+fn decode(
+    decoder: &Decoder,
+    message: &BorrowedMessage,
+    skip_errors: bool,
+) -> Result<Option<Record>, Error> {
+    let mut metadata = Metadata {
+        connector: "kafka".to_string(),
+        ..Metadata::default()
+    };
+    let attributes = &mut metadata.attributes;
+    attributes.insert("topic".to_string(), message.topic().to_string());
+    attributes.insert("partition".to_string(), message.partition().to_string());
+    attributes.insert("offset".to_string(), message.offset().to_string());
+    if let Some(Ok(key)) = message.key().map(std::str::from_utf8) {
+        attributes.insert("key".to_string(), key.to_string());
+    }
+    let event_time = message
+        .timestamp()
+        .to_millis()
+        .and_then(DateTime::from_timestamp_millis);
+    match decoder.decode(message.payload().unwrap_or_default(), metadata, event_time) {
+        Ok(record) => Ok(Some(record)),
+        Err(e) if skip_errors => {
+            warn!(
+                "skipped the message at {} partition {} offset {}: {}",
+                message.topic(),
+                message.partition(),
+                message.offset(),
+                e
+            );
+            Ok(None)
+        }
+        Err(e) => Err(format!(
+            "message at {} partition {} offset {}: {}",
+            message.topic(),
+            message.partition(),
+            message.offset(),
+            e
+        )
+        .into()),
+    }
+}
+
+#[async_trait]
+impl Source for KafkaSource {
+    async fn open(&mut self, checkpoint: Option<Position>) -> Result<(), Error> {
+        match checkpoint {
+            Some(Position::KafkaOffsets { offsets }) => {
+                self.delivered = offsets.iter().map(|p| (p.partition, p.offset)).collect();
+            }
+            Some(position) => {
+                return Err(format!("cannot resume a kafka source from {:?}", position).into())
+            }
+            None => {}
+        }
+        info!("subscribing kafka source to {}", self.topic);
+        let consumer: StreamConsumer<Rebalances> =
+            self.client.create_with_context(Rebalances::default())?;
+        consumer.subscribe(&[&self.topic])?;
+        self.consumer = Some(consumer);
+        Ok(())
+    }
+
+    // read waits for the next message, then takes whatever else arrives within a
+    // moment, up to `batch_size` messages
+    async fn read(&mut self) -> Result<Option<Batch>, Error> {
+        let mut records = Vec::new();
+        let mut offsets = Vec::new();
+        let mut received = 0;
+        while received < self.batch_size {
+            self.position()?;
+            let Some(consumer) = &self.consumer else {
+                return Err("kafka source read before it was opened".into());
+            };
+            let message = if received == 0 {
+                tokio::select! {
+                    message = consumer.recv() => message?,
+                    _ = self.shutdown_rx.recv() => return Ok(None),
+                }
+            } else {
+                match tokio::time::timeout(LINGER, consumer.recv()).await {
+                    Ok(message) => message?,
+                    Err(_) => break,
+                }
+            };
+            // the message may belong to a partition the group just assigned
+            let (partition, offset) = (message.partition(), message.offset());
+            let record = decode(&self.decoder, &message, self.skip_errors)?;
+            drop(message);
+            let repositioned = self.position()?;
+            if repositioned.contains(&partition) {
+                let before = records.len();
+                let kept: Vec<_> = records
+                    .into_iter()
+                    .zip(offsets)
+                    .filter(|(_, (p, _))| !repositioned.contains(p))
+                    .collect();
+                received -= before - kept.len();
+                (records, offsets) = kept.into_iter().unzip();
+                continue;
+            }
+            records.push(record);
+            offsets.push((partition, offset));
+            received += 1;
+        }
+
+        for (partition, offset) in &offsets {
+            self.read.insert(*partition, offset + 1);
+        }
+        self.forget();
+        let position = (!self.read.is_empty()).then(|| Position::KafkaOffsets {
+            offsets: self
+                .read
+                .iter()
+                .map(|(partition, offset)| PartitionOffset {
+                    partition: *partition,
+                    offset: *offset,
+                })
+                .collect(),
+        });
+        Ok(Some(Batch {
+            records: records.into_iter().flatten().collect(),
+            position,
+        }))
+    }
+
+    // ack commits the offsets of the position to the consumer group
+    async fn ack(&mut self, position: &Position) -> Result<(), Error> {
+        let Position::KafkaOffsets { offsets } = position else {
+            return Ok(());
+        };
+        for p in offsets {
+            let delivered = self.delivered.entry(p.partition).or_insert(p.offset);
+            *delivered = (*delivered).max(p.offset);
+        }
+        self.commit(&self.delivered, CommitMode::Async)
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        self.commit(&self.delivered, CommitMode::Sync)?;
+        if let Some(consumer) = self.consumer.take() {
+            consumer.unsubscribe();
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rdkafka::{
+        mocking::MockCluster,
+        producer::{FutureProducer, FutureRecord},
+    };
+    use record::Value;
+    use tokio::sync::broadcast::{self, Sender};
+
+    fn settings(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn source(
+        brokers: &str,
+        pairs: &[(&str, &str)],
+        shutdown: &Sender<()>,
+    ) -> Result<KafkaSource, Error> {
+        let kafka = KafkaConfig {
+            brokers: brokers.to_string(),
+            retry: Default::default(),
+        };
+        let mut source =
+            KafkaSource::from_kafka(&kafka, &settings(pairs), vec![], shutdown.subscribe())?;
+        // the mock cluster only rebalances once a departed member's session expires
+        source.client.set("session.timeout.ms", "6000");
+        Ok(source)
+    }
+
+    async fn read_all(source: &mut KafkaSource, count: usize) -> (Vec<Record>, Position) {
+        let mut records = vec![];
+        let mut position = None;
+        while records.len() < count {
+            let batch = tokio::time::timeout(Duration::from_secs(30), source.read())
+                .await
+                .expect("timed out reading")
+                .unwrap()
+                .unwrap();
+            records.extend(batch.records);
+            position = batch.position.or(position);
+        }
+        (records, position.unwrap())
+    }
+
+    #[test]
+    fn reads_start_positions() {
+        let start = |value: &str| Start::parse(value);
+        assert_eq!(start("latest").unwrap(), Start::Latest);
+        assert_eq!(
+            start("2024-01-31T12:00:00Z").unwrap(),
+            Start::Timestamp(1_706_702_400_000)
+        );
+        assert_eq!(
+            start("0:42, 1:17").unwrap(),
+            Start::Offsets(HashMap::from([(0, 42), (1, 17)]))
+        );
+        assert!(start("0:-1").is_err());
+        assert!(start("yesterday").is_err());
+
+        let (shutdown, _) = broadcast::channel(1);
+        assert!(source("localhost:9092", &[("topic", "orders")], &shutdown).is_err());
+        assert!(source("localhost:9092", &[("group", "fust")], &shutdown).is_err());
+        assert!(source(
+            "localhost:9092",
+            &[("topic", "orders"), ("group", "fust"), ("codec", "avro")],
+            &shutdown
+        )
+        .is_err());
+    }
+
+    #[tokio::test]
+    async fn resumes_from_acknowledged_offsets() {
+        let cluster = MockCluster::new(1).unwrap();
+        cluster.create_topic("orders", 2, 1).unwrap();
+        let brokers = cluster.bootstrap_servers();
+        let producer: FutureProducer = ClientConfig::new()
+            .set("bootstrap.servers", &brokers)
+            .create()
+            .unwrap();
+        for id in 0..10 {
+            let payload = format!(r#"{{"id": {}}}"#, id);
+            let message = FutureRecord::to("orders")
+                .key("k")
+                .partition(id % 2)
+                .payload(&payload);
+            producer
+                .send(message, Duration::from_secs(5))
+                .await
+                .unwrap();
+        }
+        let pairs = [
+            ("topic", "orders"),
+            ("group", "fust"),
+            ("key", "id"),
+            ("start", "0:2"),
+        ];
+
+        let (shutdown, _) = broadcast::channel(1);
+        let mut first = source(&brokers, &pairs, &shutdown).unwrap();
+        first.open(None).await.unwrap();
+        // partition 0 starts at its third message
+        let (records, position) = read_all(&mut first, 8).await;
+        assert_eq!(records.len(), 8);
+        assert_eq!(records[0].metadata.connector, "kafka");
+        assert!(records
+            .iter()
+            .all(|r| r.key_values() != vec![&Value::Int(0)]));
+        assert_eq!(
+            position,
+            Position::KafkaOffsets {
+                offsets: vec![
+                    PartitionOffset {
+                        partition: 0,
+                        offset: 5
+                    },
+                    PartitionOffset {
+                        partition: 1,
+                        offset: 5
+                    },
+                ]
+            }
+        );
+        let acked = Position::KafkaOffsets {
+            offsets: vec![
+                PartitionOffset {
+                    partition: 0,
+                    offset: 4,
+                },
+                PartitionOffset {
+                    partition: 1,
+                    offset: 5,
+                },
+            ],
+        };
+        first.ack(&acked).await.unwrap();
+        first.close().await.unwrap();
+
+        // the group resumes after the acknowledged messages, whatever `start` says
+        let mut second = source(&brokers, &pairs, &shutdown).unwrap();
+        second.open(None).await.unwrap();
+        let (records, _) = read_all(&mut second, 1).await;
+        assert_eq!(records.len(), 1);
+        assert_eq!(records[0].key_values(), vec![&Value::Int(8)]);
+        assert_eq!(records[0].metadata.attributes["offset"], "4");
+        second.close().await.unwrap();
+    }
+}
@@ -1,11 +1,58 @@
+pub mod codec;
+pub mod kafka;
+pub mod mysql;
+pub mod nats;
 pub mod pg;
-use tokio::sync::broadcast::Receiver;
+pub mod sqlite;
 
-use std::error::Error;
+use async_trait::async_trait;
+use checkpoint::Position;
+use config::{ConnectorConfig, SourceConfig};
+use record::{Batch, Error};
+use tokio::sync::broadcast::{error::TryRecvError, Receiver};
+use util::pg::Pools;
 
-pub trait Source {
-    fn new(shutdown_rx: Receiver<()>) -> Self;
-    async fn read(&self) -> Result<Vec<String>, Box<dyn Error>>;
-    async fn run(&self);
-    async fn get_conn(&self);
+// Source reads records from an external system. It is object safe so a pipeline
+// can hold any source as a `Box<dyn Source>`.
+#[async_trait]
+pub trait Source: Send {
+    // open connects and positions the source right after `checkpoint`, or at the
+    // start when there is none
+    async fn open(&mut self, checkpoint: Option<Position>) -> Result<(), Error>;
+    // read returns the next batch, or None once the source is exhausted or shutting down
+    async fn read(&mut self) -> Result<Option<Batch>, Error>;
+    // ack tells the source every batch up to `position` has been delivered
+    async fn ack(&mut self, position: &Position) -> Result<(), Error>;
+    async fn close(&mut self) -> Result<(), Error>;
+}
+
+// build creates the source a configuration describes, picking the
+// implementation by the connector's type. The source stops reading once
+// `shutdown_rx` fires. Database sources share connections through `pools`.
+pub fn build(
+    config: &SourceConfig,
+    shutdown_rx: Receiver<()>,
+    pools: &Pools,
+) -> Result<Box<dyn Source>, Error> {
+    match &config.connector {
+        ConnectorConfig::Postgres(_) => {
+            Ok(Box::new(pg::PgSource::new(config, shutdown_rx, pools)?))
+        }
+        ConnectorConfig::Nats(_) => Ok(Box::new(nats::NatsSource::new(config, shutdown_rx)?)),
+        ConnectorConfig::Kafka(_) => Ok(Box::new(kafka::KafkaSource::new(config, shutdown_rx)?)),
+        ConnectorConfig::Sqlite(_) => Ok(Box::new(sqlite::SqliteSource::new(config, shutdown_rx)?)),
+        ConnectorConfig::Mysql(_) => Ok(Box::new(mysql::MysqlSource::new(config, shutdown_rx)?)),
+        other @ (ConnectorConfig::Mssql(_) | ConnectorConfig::Oracle(_)) => {
+            Err(format!("a {} connector cannot be used as a source", other.kind()).into())
+        }
+    }
+}
+
+// shutting_down reports whether shutdown was signalled, without waiting for it,
+// so a snapshot can stop between pages instead of reading the whole table first
+pub(crate) fn shutting_down(shutdown_rx: &mut Receiver<()>) -> bool {
+    matches!(
+        shutdown_rx.try_recv(),
+        Ok(()) | Err(TryRecvError::Lagged(_))
+    )
 }
@@ -0,0 +1,692 @@
+use std::{collections::BTreeMap, fmt, time::Duration};
+
+use chrono::{DateTime, Datelike, Timelike, Utc};
+use mysql_async::{
+    binlog::{
+        events::{Event, EventData, RowsEventData, TableMapEvent},
+        row::BinlogRow,
+        value::BinlogValue,
+    },
+    BinlogStream, BinlogStreamRequest, Conn, GnoInterval, Sid, Value as MysqlValue,
+};
+use record::{Error, Op, Record, Row};
+use tokio_stream::StreamExt;
+use util::mysql::Server;
+
+use super::snapshot::{self, Column, Table};
+
+// IDLE is how long a batch waits for the next transaction before it is returned
+// with what it has
+const IDLE: Duration = Duration::from_millis(200);
+
+// GtidSet is a set of transaction ids, by the uuid of the server that ran them,
+// as MySQL prints it: `uuid:1-5:7,uuid2:1-3`
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct GtidSet(BTreeMap<String, Vec<(u64, u64)>>);
+
+impl GtidSet {
+    pub fn parse(text: &str) -> Result<Self, Error> {
+        let invalid = || format!("invalid GTID set `{}`", text);
+        let mut set = GtidSet::default();
+        for sid in text.split(',').map(str::trim).filter(|s| !s.is_empty()) {
+            let mut parts = sid.split(':');
+            let uuid = parts.next().unwrap_or_default().to_lowercase();
+            uuid_bytes(&uuid).ok_or_else(invalid)?;
+            for interval in parts {
+                let (start, end) = match interval.split_once('-') {
+                    Some((start, end)) => (start.parse::<u64>(), end.parse::<u64>()),
+                    None => (interval.parse::<u64>(), interval.parse::<u64>()),
+                };
+                match (start, end) {
+                    (Ok(start), Ok(end)) if 0 < start && start <= end => {
+                        set.0.entry(uuid.clone()).or_default().push((start, end));
+                        set.merge(&uuid);
+                    }
+                    _ => return Err(invalid().into()),
+                }
+            }
+        }
+        Ok(set)
+    }
+
+    // add puts one transaction in the set
+    pub fn add(&mut self, uuid: &str, gno: u64) {
+        self.0.entry(uuid.to_string()).or_default().push((gno, gno));
+        self.merge(uuid);
+    }
+
+    fn merge(&mut self, uuid: &str) {
+        let Some(intervals) = self.0.get_mut(uuid) else {
+            return;
+        };
+        intervals.sort();
+        let mut merged: Vec<(u64, u64)> = Vec::with_capacity(intervals.len());
+        for &(start, end) in intervals.iter() {
+            match merged.last_mut() {
+                Some(last) if start <= last.1.saturating_add(1) => last.1 = last.1.max(end),
+                _ => merged.push((start, end)),
+            }
+        }
+        *intervals = merged;
+    }
+
+    // sids is the set in the form a binlog request takes it
+    fn sids(&self) -> Vec<Sid<'static>> {
+        self.0
+            .iter()
+            .map(|(uuid, intervals)| {
+                Sid::new(uuid_bytes(uuid).expect("uuids are checked when added")).with_intervals(
+                    intervals
+                        .iter()
+                        .map(|&(start, end)| GnoInterval::new(start, end + 1))
+                        .collect(),
+                )
+            })
+            .collect()
+    }
+}
+
+impl fmt::Display for GtidSet {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for (i, (uuid, intervals)) in self.0.iter().enumerate() {
+            if i > 0 {
+                f.write_str(",")?;
+            }
+            f.write_str(uuid)?;
+            for (start, end) in intervals {
+                match start == end {
+                    true => write!(f, ":{}", start)?,
+                    false => write!(f, ":{}-{}", start, end)?,
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn uuid_bytes(uuid: &str) -> Option<[u8; 16]> {
+    let hex: String = uuid.chars().filter(|c| *c != '-').collect();
+    if hex.len() != 32 || uuid.len() != 36 {
+        return None;
+    }
+    let mut bytes = [0; 16];
+    for (i, byte) in bytes.iter_mut().enumerate() {
+        *byte = u8::from_str_radix(hex.get(2 * i..2 * i + 2)?, 16).ok()?;
+    }
+    Some(bytes)
+}
+
+fn uuid_string(bytes: [u8; 16]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+// Transaction is the transaction being decoded
+struct Transaction {
+    // gtid is the transaction's id, as `uuid:gno`
+    gtid: Option<(String, u64)>,
+    commit_time: Option<DateTime<Utc>>,
+    records: Vec<Record>,
+}
+
+// Decoder turns binlog events into records of one table. Changes are held back
+// until the transaction that made them commits, and the set of transactions
+// read grows by every transaction that does, whichever tables it touched.
+pub struct Decoder {
+    table: Table,
+    gtid_set: GtidSet,
+    // table_map is the last table map of the table, which its row events refer to
+    // by id
+    table_map: Option<TableMapEvent<'static>>,
+    transaction: Option<Transaction>,
+    // file is the binlog file being read, once the server has said which
+    file: Option<String>,
+}
+
+impl Decoder {
+    pub fn new(table: Table, gtid_set: GtidSet) -> Self {
+        Decoder {
+            table,
+            gtid_set,
+            table_map: None,
+            transaction: None,
+            file: None,
+        }
+    }
+
+    pub fn gtid_set(&self) -> &GtidSet {
+        &self.gtid_set
+    }
+
+    // stale tells whether the table has changed shape since it was described,
+    // so its rows no longer line up with its columns
+    pub fn stale(&self) -> bool {
+        self.table_map
+            .as_ref()
+            .is_some_and(|tme| tme.columns_count() as usize != self.table.columns.len())
+    }
+
+    pub fn set_table(&mut self, table: Table) {
+        self.table = table;
+    }
+
+    // decode reads one event, returning the records of a transaction once it
+    // commits, which is an empty list for transactions that did not touch the table
+    pub fn decode(&mut self, event: &Event) -> Result<Option<Vec<Record>>, Error> {
+        let Some(data) = event.read_data()? else {
+            return Ok(None);
+        };
+        match data {
+            EventData::GtidEvent(gtid) => {
+                let micros = gtid.immediate_commit_timestamp();
+                let commit_time = match micros {
+                    0 => DateTime::from_timestamp(event.header().timestamp() as i64, 0),
+                    _ => DateTime::from_timestamp_micros(micros as i64),
+                };
+                self.transaction = Some(Transaction {
+                    gtid: Some((uuid_string(gtid.sid()), gtid.gno())),
+                    commit_time,
+                    records: vec![],
+                });
+            }
+            EventData::AnonymousGtidEvent(_) => {
+                return Err(
+                    "the binlog holds transactions without a GTID, gtid_mode must be ON".into(),
+                )
+            }
+            EventData::QueryEvent(query) => match query.query().trim() {
+                "BEGIN" => {
+                    if self.transaction.is_none() {
+                        self.transaction = Some(Transaction {
+                            gtid: None,
+                            commit_time: DateTime::from_timestamp(
+                                event.header().timestamp() as i64,
+                                0,
+                            ),
+                            records: vec![],
+                        });
+                    }
+                }
+                // anything else, a COMMIT of non-transactional tables or a DDL
+                // statement, ends the transaction
+                _ => return Ok(self.commit()),
+            },
+            EventData::XidEvent(_) => return Ok(self.commit()),
+            EventData::RotateEvent(rotate) => self.file = Some(rotate.name().into_owned()),
+            EventData::TableMapEvent(tme)
+                if *tme.database_name() == self.table.database
+                    && *tme.table_name() == self.table.name =>
+            {
+                self.table_map = Some(tme.into_owned());
+            }
+            EventData::RowsEvent(rows) => {
+                let Some(tme) = &self.table_map else {
+                    return Ok(None);
+                };
+                if rows.table_id() != tme.table_id() {
+                    return Ok(None);
+                }
+                let op = match &rows {
+                    RowsEventData::WriteRowsEvent(_) | RowsEventData::WriteRowsEventV1(_) => {
+                        Op::Insert
+                    }
+                    RowsEventData::UpdateRowsEvent(_) | RowsEventData::UpdateRowsEventV1(_) => {
+                        Op::Update
+                    }
+                    RowsEventData::DeleteRowsEvent(_) | RowsEventData::DeleteRowsEventV1(_) => {
+                        Op::Delete
+                    }
+                    RowsEventData::PartialUpdateRowsEvent(_) => {
+                        return Err(
+                            "partial JSON updates are not supported, binlog_row_value_options must be empty"
+                                .into(),
+                        )
+                    }
+                };
+                let mut records = vec![];
+                for row in rows.rows(tme) {
+                    let (before, after) = row?;
+                    let before = before.map(|row| self.image(row)).transpose()?;
+                    let after = after.map(|row| self.image(row)).transpose()?;
+                    records.push(self.change(op, event, before, after));
+                }
+                match self.transaction.as_mut() {
+                    Some(transaction) => transaction.records.extend(records),
+                    None => return Err("row changes outside of a transaction".into()),
+                }
+            }
+            _ => {}
+        }
+        Ok(None)
+    }
+
+    fn commit(&mut self) -> Option<Vec<Record>> {
+        let transaction = self.transaction.take()?;
+        if let Some((uuid, gno)) = &transaction.gtid {
+            self.gtid_set.add(uuid, *gno);
+        }
+        Some(transaction.records)
+    }
+
+    fn change(&self, op: Op, event: &Event, before: Option<Row>, after: Option<Row>) -> Record {
+        let mut record = self.table.record(op, before, after);
+        if let Some(transaction) = &self.transaction {
+            record.metadata.transaction = transaction
+                .gtid
+                .as_ref()
+                .map(|(uuid, gno)| format!("{}:{}", uuid, gno));
+            record.event_time = transaction.commit_time;
+        }
+        if let Some(file) = &self.file {
+            let attributes = &mut record.metadata.attributes;
+            attributes.insert("binlog_file".to_string(), file.clone());
+            attributes.insert(
+                "binlog_position".to_string(),
+                event.header().log_pos().to_string(),
+            );
+        }
+        record
+    }
+
+    // image types a row image by the table's fields. The image has to hold every
+    // column, which takes binlog_row_image = FULL.
+    fn image(&self, row: BinlogRow) -> Result<Row, Error> {
+        if row.len() != self.table.columns.len() {
+            return Err(format!(
+                "a row image of `{}`.`{}` has {} of its {} columns, binlog_row_image must be FULL",
+                self.table.database,
+                self.table.name,
+                row.len(),
+                self.table.columns.len()
+            )
+            .into());
+        }
+        let mut image = Row::new();
+        for (i, column) in self.table.columns.iter().enumerate() {
+            let Some(field) = self.table.fields.iter().find(|f| f.name == column.name) else {
+                continue;
+            };
+            let value = match row.as_ref(i) {
+                Some(value) => binlog_value(column, value)?,
+                None => MysqlValue::NULL,
+            };
+            image.insert(
+                column.name.clone(),
+                snapshot::mysql_value(column, field, value)?,
+            );
+        }
+        Ok(image)
+    }
+}
+
+// binlog_value brings a value the binlog encodes its own way to the form a query
+// returns it in: TIMESTAMP columns come as seconds since the epoch, enum and set
+// columns as indexes and bits, JSON as MySQL's binary JSON, and integers as
+// signed unless the table map says otherwise
+fn binlog_value(column: &Column, value: &BinlogValue) -> Result<MysqlValue, Error> {
+    let value = match value {
+        BinlogValue::Value(value) => value.clone(),
+        BinlogValue::Jsonb(json) => {
+            let json = serde_json::Value::try_from(json.clone())
+                .map_err(|e| format!("column `{}`: {}", column.name, e))?;
+            return Ok(MysqlValue::Bytes(json.to_string().into_bytes()));
+        }
+        BinlogValue::JsonDiff(_) => {
+            return Err(format!("column `{}` holds a partial JSON update", column.name).into())
+        }
+    };
+    let value = match (value, column.data_type.as_str()) {
+        (MysqlValue::Bytes(seconds), "timestamp") => {
+            let seconds = String::from_utf8_lossy(&seconds).into_owned();
+            let micros = seconds
+                .split_once('.')
+                .map(|(s, us)| (s.parse::<i64>(), us.parse::<u32>()))
+                .unwrap_or((seconds.parse::<i64>(), Ok(0)));
+            let timestamp = match micros {
+                (Ok(s), Ok(us)) => DateTime::from_timestamp(s, us * 1000),
+                _ => None,
+            }
+            .ok_or_else(|| format!("column `{}`: invalid timestamp `{}`", column.name, seconds))?
+            .naive_utc();
+            MysqlValue::Date(
+                timestamp.year() as u16,
+                timestamp.month() as u8,
+                timestamp.day() as u8,
+                timestamp.hour() as u8,
+                timestamp.minute() as u8,
+                timestamp.second() as u8,
+                timestamp.nanosecond() / 1000,
+            )
+        }
+        (MysqlValue::Int(index), "enum") => snapshot::enum_label(column, index),
+        (MysqlValue::Bytes(bits), "set") => snapshot::set_labels(column, &bits),
+        (MysqlValue::Int(i), _) => snapshot::unsigned(column, i),
+        (value, _) => value,
+    };
+    Ok(value)
+}
+
+// Binlog follows the binlog of a server from a set of transactions, decoding the
+// changes of one table
+pub struct Binlog {
+    server_id: u32,
+    decoder: Decoder,
+    stream: Option<BinlogStream>,
+}
+
+impl Binlog {
+    pub fn new(server_id: u32, decoder: Decoder) -> Self {
+        Binlog {
+            server_id,
+            decoder,
+            stream: None,
+        }
+    }
+
+    // position is every transaction read so far
+    pub fn position(&self) -> &GtidSet {
+        self.decoder.gtid_set()
+    }
+
+    pub fn started(&self) -> bool {
+        self.stream.is_some()
+    }
+
+    // start turns `conn` into a stream of every transaction past the position
+    pub async fn start(&mut self, conn: Conn) -> Result<(), Error> {
+        let request = BinlogStreamRequest::new(self.server_id)
+            .with_gtid()
+            .with_gtid_set(self.position().sids());
+        self.stream = Some(conn.get_binlog_stream(request).await?);
+        Ok(())
+    }
+
+    // next_batch waits for the next committed transactions, and returns their
+    // changes once `batch_size` of either have been read, or no more come in for a
+    // moment. A batch holds whole transactions, and may be empty when none of them
+    // touched the table. When the table's shape changes it is described again
+    // through `server`.
+    pub async fn next_batch(
+        &mut self,
+        server: &Server,
+        batch_size: usize,
+    ) -> Result<Vec<Record>, Error> {
+        let stream = self
+            .stream
+            .as_mut()
+            .ok_or("binlog read before it was started")?;
+        let mut records = vec![];
+        let mut transactions = 0;
+        while records.len() < batch_size && transactions < batch_size {
+            let next = match transactions {
+                0 => stream.next().await,
+                _ => match tokio::time::timeout(IDLE, stream.next()).await {
+                    Ok(next) => next,
+                    Err(_) => break,
+                },
+            };
+            let event = next.ok_or("the server closed the binlog stream")??;
+            if let Some(committed) = self.decoder.decode(&event)? {
+                transactions += 1;
+                records.extend(committed);
+            }
+            if self.decoder.stale() {
+                let table = &self.decoder.table;
+                let mut conn = server.get_conn().await?;
+                let fields = table.fields.clone();
+                let described = Table::describe(
+                    &mut conn,
+                    &table.database,
+                    &table.name,
+                    Some(&table.key),
+                    &fields,
+                )
+                .await?;
+                self.decoder.set_table(described);
+            }
+        }
+        Ok(records)
+    }
+
+    pub async fn close(&mut self) -> Result<(), Error> {
+        if let Some(stream) = self.stream.take() {
+            stream.close().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::NaiveDate;
+    use config::Field;
+    use mysql_async::binlog::{events::FormatDescriptionEvent, BinlogVersion};
+    use record::Value;
+
+    const UUID: &str = "3e11fa47-71ca-11e1-9e33-c80aa9429562";
+
+    // events reads a binlog file of the fixtures, which hold the binlog of
+    // `shop.orders` and `shop.customers` as a MySQL 8.0 server writes it
+    fn events(name: &str) -> Vec<Event> {
+        let path = format!(
+            "{}/tests/fixtures/mysql/{}.binlog",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        );
+        let bytes = std::fs::read(&path).unwrap();
+        let mut input = bytes.strip_prefix(b"\xfebin").expect("a binlog file");
+        let mut fde = FormatDescriptionEvent::new(BinlogVersion::Version4);
+        let mut events = vec![];
+        while !input.is_empty() {
+            let event = Event::read(&fde, &mut input).unwrap();
+            if let Some(EventData::FormatDescriptionEvent(read)) = event.read_data().unwrap() {
+                fde = read.into_owned().with_footer(event.footer());
+            }
+            events.push(event);
+        }
+        events
+    }
+
+    fn column(name: &str, data_type: &str, column_type: &str) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type: data_type.to_string(),
+            column_type: column_type.to_string(),
+            nullable: true,
+        }
+    }
+
+    fn orders() -> Table {
+        let columns = vec![
+            column("id", "int", "int"),
+            column("tenant", "varchar", "varchar(32)"),
+            column("total", "decimal", "decimal(10,2)"),
+            column("paid", "tinyint", "tinyint(1)"),
+            column("status", "enum", "enum('new','paid','shipped')"),
+            column("created_at", "datetime", "datetime"),
+            column("updated_at", "timestamp", "timestamp"),
+            column("notes", "json", "json"),
+        ];
+        Table {
+            database: "shop".to_string(),
+            name: "orders".to_string(),
+            fields: columns
+                .iter()
+                .map(|c| Field {
+                    name: c.name.clone(),
+                    field_type: snapshot::field_type(c),
+                    nullable: c.nullable,
+                })
+                .collect(),
+            columns,
+            key: vec!["id".to_string()],
+        }
+    }
+
+    fn decode_all(decoder: &mut Decoder, events: &[Event]) -> Vec<Vec<Record>> {
+        events
+            .iter()
+            .filter_map(|event| decoder.decode(event).unwrap())
+            .collect()
+    }
+
+    fn at(day: u32, h: u32, m: u32) -> chrono::NaiveDateTime {
+        NaiveDate::from_ymd_opt(2024, 3, day)
+            .unwrap()
+            .and_hms_opt(h, m, 0)
+            .unwrap()
+    }
+
+    #[test]
+    fn decodes_inserts() {
+        let mut decoder = Decoder::new(orders(), GtidSet::default());
+        let transactions = decode_all(&mut decoder, &events("inserts"));
+        // the second transaction only wrote to another table
+        assert_eq!(transactions.len(), 2);
+        assert!(transactions[1].is_empty());
+        assert_eq!(decoder.gtid_set().to_string(), format!("{}:1-2", UUID));
+
+        let inserts = &transactions[0];
+        assert_eq!(inserts.len(), 2);
+        let first = &inserts[0];
+        assert_eq!(first.op, Op::Insert);
+        assert_eq!(first.key, vec!["id".to_string()]);
+        assert!(first.before.is_none());
+        let after = first.after.as_ref().unwrap();
+        assert_eq!(after.get("id"), Some(&Value::Int(1)));
+        assert_eq!(
+            after.get("tenant"),
+            Some(&Value::String("acme".to_string()))
+        );
+        assert_eq!(
+            after.get("total"),
+            Some(&Value::Decimal("12.50".to_string()))
+        );
+        assert_eq!(after.get("paid"), Some(&Value::Bool(true)));
+        assert_eq!(
+            after.get("status"),
+            Some(&Value::String("paid".to_string()))
+        );
+        assert_eq!(
+            after.get("created_at"),
+            Some(&Value::Timestamp(at(1, 10, 15)))
+        );
+        assert_eq!(
+            after.get("updated_at"),
+            Some(&Value::TimestampTz(at(1, 10, 15).and_utc()))
+        );
+        assert_eq!(
+            after.get("notes"),
+            Some(&Value::Json(serde_json::json!({"gift": true})))
+        );
+        assert_eq!(
+            first.metadata.transaction.as_deref(),
+            Some(format!("{}:1", UUID).as_str())
+        );
+        assert_eq!(first.metadata.database.as_deref(), Some("shop"));
+        assert_eq!(first.event_time, Some(at(1, 10, 15).and_utc()));
+
+        let second = inserts[1].after.as_ref().unwrap();
+        assert_eq!(second.get("paid"), Some(&Value::Bool(false)));
+        assert_eq!(second.get("notes"), Some(&Value::Null));
+    }
+
+    #[test]
+    fn decodes_updates_and_deletes() {
+        let set = GtidSet::parse(&format!("{}:1-2", UUID)).unwrap();
+        let mut decoder = Decoder::new(orders(), set);
+        let transactions = decode_all(&mut decoder, &events("updates_and_deletes"));
+        // the DDL statement commits on its own, without a COMMIT or XID event
+        assert_eq!(transactions.len(), 2);
+        assert!(transactions[1].is_empty());
+        assert_eq!(decoder.gtid_set().to_string(), format!("{}:1-4", UUID));
+
+        let changes = &transactions[0];
+        assert_eq!(changes.len(), 2);
+        let update = &changes[0];
+        assert_eq!(update.op, Op::Update);
+        let before = update.before.as_ref().unwrap();
+        let after = update.after.as_ref().unwrap();
+        assert_eq!(
+            before.get("total"),
+            Some(&Value::Decimal("12.50".to_string()))
+        );
+        assert_eq!(
+            after.get("total"),
+            Some(&Value::Decimal("15.00".to_string()))
+        );
+        assert_eq!(
+            after.get("status"),
+            Some(&Value::String("shipped".to_string()))
+        );
+        assert_eq!(
+            after.get("updated_at"),
+            Some(&Value::TimestampTz(at(1, 12, 0).and_utc()))
+        );
+
+        let delete = &changes[1];
+        assert_eq!(delete.op, Op::Delete);
+        assert!(delete.after.is_none());
+        assert_eq!(
+            delete.key_values(),
+            vec![&Value::Int(2)],
+            "deletes carry the key of the row"
+        );
+    }
+
+    #[test]
+    fn projects_fields_and_notices_new_columns() {
+        let mut table = orders();
+        table.fields.retain(|f| f.name == "id" || f.name == "total");
+        let mut decoder = Decoder::new(table, GtidSet::default());
+        let transactions = decode_all(&mut decoder, &events("inserts"));
+        let names: Vec<&str> = transactions[0][0]
+            .after
+            .as_ref()
+            .unwrap()
+            .iter()
+            .map(|(name, _)| name)
+            .collect();
+        assert_eq!(names, vec!["id", "total"]);
+        assert!(!decoder.stale());
+
+        // a table described before a column was added no longer lines up
+        let mut table = orders();
+        table.columns.pop();
+        let mut decoder = Decoder::new(table, GtidSet::default());
+        let events = events("inserts");
+        for event in &events[..5] {
+            decoder.decode(event).unwrap();
+        }
+        assert!(decoder.stale());
+    }
+
+    #[test]
+    fn gtid_sets_round_trip() {
+        let other = "8f5a5c1e-0a3b-11ef-8d3c-0242ac120002";
+        let text = format!("{}:1-5:7:9-10,\n{}:1-3", UUID.to_uppercase(), other);
+        let mut set = GtidSet::parse(&text).unwrap();
+        assert_eq!(
+            set.to_string(),
+            format!("{}:1-5:7:9-10,{}:1-3", UUID, other)
+        );
+        set.add(UUID, 6);
+        set.add(UUID, 8);
+        set.add(other, 5);
+        assert_eq!(set.to_string(), format!("{}:1-10,{}:1-3:5", UUID, other));
+        assert_eq!(set.sids().len(), 2);
+        assert_eq!(set.sids()[0].intervals(), &[GnoInterval::new(1, 11)]);
+
+        assert_eq!(GtidSet::parse("").unwrap(), GtidSet::default());
+        assert!(GtidSet::parse("not-a-uuid:1-2").is_err());
+        assert!(GtidSet::parse(&format!("{}:5-2", UUID)).is_err());
+    }
+}
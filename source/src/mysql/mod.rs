@@ -0,0 +1,420 @@
+mod binlog;
+mod snapshot;
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use checkpoint::Position;
+use config::{ConnectorConfig, Field, MysqlConfig, SourceConfig};
+use mysql_async::{prelude::Queryable, Conn};
+use record::{Batch, Error, Record};
+use tokio::sync::broadcast::Receiver;
+use tracing::info;
+use util::mysql::Server;
+
+pub use binlog::{Binlog, Decoder, GtidSet};
+pub use snapshot::{field_type, quote_ident, Column, Snapshot, Table};
+
+use crate::Source;
+
+const DEFAULT_BATCH_SIZE: usize = 1000;
+
+// Mode is how a MysqlSource reads its table
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    // Snapshot reads every row once, in key order
+    Snapshot,
+    // Cdc follows every committed change in the binlog, from the transactions the
+    // server had run when the source first opened
+    Cdc,
+    // SnapshotCdc snapshots the table in one consistent read, then follows the
+    // binlog from the transactions that read could see
+    SnapshotCdc,
+}
+
+// MysqlSource reads one table of a MySQL server. Change data capture follows
+// the row-based binlog as a replica does, and checkpoints the GTID set of the
+// transactions it has read, so it needs `binlog_format = ROW`,
+// `binlog_row_image = FULL` and `gtid_mode = ON`.
+pub struct MysqlSource {
+    server: Server,
+    server_id: Option<u32>,
+    shutdown_rx: Receiver<()>,
+    database: String,
+    table: String,
+    key: Option<Vec<String>>,
+    fields: Vec<Field>,
+    batch_size: usize,
+    mode: Mode,
+    snapshot: Option<Snapshot>,
+    // snapshot_conn holds the transaction a snapshot_cdc snapshot reads in
+    snapshot_conn: Option<Conn>,
+    binlog: Option<Binlog>,
+}
+
+impl MysqlSource {
+    pub fn new(config: &SourceConfig, shutdown_rx: Receiver<()>) -> Result<Self, Error> {
+        match &config.connector {
+            ConnectorConfig::Mysql(mysql) => {
+                Self::from_mysql(mysql, &config.config, config.fields.clone(), shutdown_rx)
+            }
+            other => Err(format!("a {} connector cannot back a mysql source", other.kind()).into()),
+        }
+    }
+
+    // from_mysql builds a source for one table from the server the connector
+    // points at and the source's own settings: `database`, `table`, `key` and
+    // `batch_size`, plus `mode = "cdc"` to follow the binlog rather than snapshot
+    // the table, or `mode = "snapshot_cdc"` to snapshot it and then follow the
+    // binlog. Both read the binlog as the connector's `server_id`.
+    pub fn from_mysql(
+        mysql: &MysqlConfig,
+        settings: &HashMap<String, String>,
+        fields: Vec<Field>,
+        shutdown_rx: Receiver<()>,
+    ) -> Result<Self, Error> {
+        let setting = |key: &str| -> Result<String, Error> {
+            settings
+                .get(key)
+                .cloned()
+                .ok_or_else(|| format!("mysql source is missing `{}`", key).into())
+        };
+        let batch_size = match settings.get("batch_size") {
+            Some(size) => match size.parse::<usize>() {
+                Ok(size) if size > 0 => size,
+                _ => return Err(format!("invalid batch_size `{}`", size).into()),
+            },
+            None => DEFAULT_BATCH_SIZE,
+        };
+        let mode = match settings.get("mode").map(String::as_str) {
+            None | Some("snapshot") => Mode::Snapshot,
+            Some("cdc") => Mode::Cdc,
+            Some("snapshot_cdc") => Mode::SnapshotCdc,
+            Some(other) => {
+                return Err(format!(
+                    "invalid mode `{}`, expected `snapshot`, `cdc` or `snapshot_cdc`",
+                    other
+                )
+                .into())
+            }
+        };
+        if mode != Mode::Snapshot && mysql.server_id.is_none() {
+            return Err("reading the binlog takes a `server_id` on the mysql connector".into());
+        }
+        let key = settings.get("key").map(|key| {
+            key.split(',')
+                .map(|column| column.trim().to_string())
+                .collect()
+        });
+
+        Ok(MysqlSource {
+            server: Server::new(mysql)?,
+            server_id: mysql.server_id,
+            shutdown_rx,
+            database: setting("database")?,
+            table: setting("table")?,
+            key,
+            fields,
+            batch_size,
+            mode,
+            snapshot: None,
+            snapshot_conn: None,
+            binlog: None,
+        })
+    }
+
+    // position is how far the batches read so far reach
+    fn position(&self) -> Option<Position> {
+        match (&self.mode, &self.snapshot, &self.binlog) {
+            (Mode::Snapshot, Some(snapshot), _) => snapshot.position(),
+            // nothing is checkpointed until the binlog is followed: an interrupted
+            // snapshot starts over
+            (Mode::Cdc | Mode::SnapshotCdc, None, Some(binlog)) => Some(Position::Gtid {
+                gtid_set: binlog.position().to_string(),
+            }),
+            _ => None,
+        }
+    }
+
+    fn batch(&self, records: Vec<Record>) -> Batch {
+        Batch {
+            records,
+            position: self.position(),
+        }
+    }
+
+    // binlog prepares to follow the binlog from `gtid_set`
+    async fn binlog(&self, conn: &mut Conn, gtid_set: GtidSet) -> Result<Binlog, Error> {
+        let table = Table::describe(
+            conn,
+            &self.database,
+            &self.table,
+            self.key.as_deref(),
+            &self.fields,
+        )
+        .await?;
+        let server_id = self
+            .server_id
+            .ok_or("the mysql connector has no `server_id`")?;
+        Ok(Binlog::new(server_id, Decoder::new(table, gtid_set)))
+    }
+}
+
+// check_binlog makes sure the server writes a binlog the source can follow
+async fn check_binlog(conn: &mut Conn) -> Result<(), Error> {
+    let settings: Option<(String, String, String)> = conn
+        .query_first("SELECT @@global.binlog_format, @@global.binlog_row_image, @@global.gtid_mode")
+        .await?;
+    let (format, row_image, gtid_mode) =
+        settings.ok_or("the server did not report its binlog settings")?;
+    for (name, value, expected) in [
+        ("binlog_format", format, "ROW"),
+        ("binlog_row_image", row_image, "FULL"),
+        ("gtid_mode", gtid_mode, "ON"),
+    ] {
+        if !value.eq_ignore_ascii_case(expected) {
+            return Err(format!(
+                "{} is `{}`, change data capture needs `{}`",
+                name, value, expected
+            )
+            .into());
+        }
+    }
+    Ok(())
+}
+
+// gtid_executed is the set of transactions the server has run
+async fn gtid_executed(conn: &mut Conn) -> Result<GtidSet, Error> {
+    let executed: Option<String> = conn.query_first("SELECT @@global.gtid_executed").await?;
+    GtidSet::parse(&executed.unwrap_or_default())
+}
+
+#[async_trait]
+impl Source for MysqlSource {
+    async fn open(&mut self, checkpoint: Option<Position>) -> Result<(), Error> {
+        let resumable = match &checkpoint {
+            None => true,
+            Some(position) => matches!(
+                (&self.mode, position),
+                (Mode::Snapshot, Position::Keyset { .. } | Position::Finished)
+                    | (Mode::Cdc | Mode::SnapshotCdc, Position::Gtid { .. })
+            ),
+        };
+        if !resumable {
+            return Err(format!(
+                "cannot resume `{}`.`{}` from {:?}",
+                self.database, self.table, checkpoint
+            )
+            .into());
+        }
+
+        let mut conn = self.server.get_conn().await?;
+        if self.mode != Mode::Snapshot {
+            check_binlog(&mut conn).await?;
+        }
+        match (&self.mode, checkpoint) {
+            (Mode::Snapshot, checkpoint) => {
+                let table = Table::describe(
+                    &mut conn,
+                    &self.database,
+                    &self.table,
+                    self.key.as_deref(),
+                    &self.fields,
+                )
+                .await?;
+                let mut snapshot = Snapshot::new(table, self.batch_size);
+                if let Some(position) = &checkpoint {
+                    snapshot.resume(position)?;
+                }
+                self.snapshot = Some(snapshot);
+            }
+            (_, Some(Position::Gtid { gtid_set })) => {
+                let mut binlog = self.binlog(&mut conn, GtidSet::parse(&gtid_set)?).await?;
+                binlog.start(conn).await?;
+                self.binlog = Some(binlog);
+            }
+            (Mode::Cdc, _) => {
+                let gtid_set = gtid_executed(&mut conn).await?;
+                let mut binlog = self.binlog(&mut conn, gtid_set).await?;
+                binlog.start(conn).await?;
+                self.binlog = Some(binlog);
+            }
+            (Mode::SnapshotCdc, _) => {
+                // the transactions run before the snapshot starts are all in it. Ones
+                // that commit in between are both in it and replayed from the
+                // binlog, which converges on the same rows as sinks apply changes by key.
+                let gtid_set = gtid_executed(&mut conn).await?;
+                let binlog = self.binlog(&mut conn, gtid_set).await?;
+                conn.query_drop("SET TRANSACTION ISOLATION LEVEL REPEATABLE READ")
+                    .await?;
+                conn.query_drop("START TRANSACTION WITH CONSISTENT SNAPSHOT, READ ONLY")
+                    .await?;
+                let table = Table::describe(
+                    &mut conn,
+                    &self.database,
+                    &self.table,
+                    self.key.as_deref(),
+                    &self.fields,
+                )
+                .await?;
+                self.snapshot = Some(Snapshot::new(table, self.batch_size));
+                self.snapshot_conn = Some(conn);
+                self.binlog = Some(binlog);
+            }
+        }
+        info!(
+            "opened mysql source `{}`.`{}` in {:?} mode",
+            self.database, self.table, self.mode
+        );
+        Ok(())
+    }
+
+    // read returns the next batch. A snapshot returns the next rows in key order
+    // until every row has been read; following the binlog waits for the next
+    // committed transactions. Both return None on shutdown.
+    async fn read(&mut self) -> Result<Option<Batch>, Error> {
+        if crate::shutting_down(&mut self.shutdown_rx) {
+            return Ok(None);
+        }
+        if let Some(snapshot) = self.snapshot.as_mut() {
+            let records = match self.snapshot_conn.as_mut() {
+                Some(conn) => snapshot.next_batch(conn).await?,
+                None => {
+                    snapshot
+                        .next_batch(&mut self.server.get_conn().await?)
+                        .await?
+                }
+            };
+            if !records.is_empty() || self.mode == Mode::Snapshot {
+                if records.is_empty() {
+                    return Ok(None);
+                }
+                return Ok(Some(self.batch(records)));
+            }
+
+            // the snapshot is over: its transaction ends, and the binlog picks up
+            // from the transactions it could see. The empty batch checkpoints that.
+            if let Some(mut conn) = self.snapshot_conn.take() {
+                conn.query_drop("COMMIT").await?;
+            }
+            self.snapshot = None;
+            info!(
+                "snapshot of `{}`.`{}` finished, following the binlog",
+                self.database, self.table
+            );
+            let conn = self.server.get_conn().await?;
+            let binlog = self
+                .binlog
+                .as_mut()
+                .ok_or("mysql source read before it was opened")?;
+            binlog.start(conn).await?;
+            return Ok(Some(self.batch(vec![])));
+        }
+
+        let Some(binlog) = self.binlog.as_mut() else {
+            return Err("mysql source read before it was opened".into());
+        };
+        let records = tokio::select! {
+            records = binlog.next_batch(&self.server, self.batch_size) => records?,
+            _ = self.shutdown_rx.recv() => return Ok(None),
+        };
+        Ok(Some(self.batch(records)))
+    }
+
+    // ack has nothing to release: the server keeps its binlog for as long as
+    // binlog_expire_logs_seconds says, whatever its replicas have read
+    async fn ack(&mut self, _position: &Position) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        info!("closing mysql source `{}`.`{}`", self.database, self.table);
+        if let Some(binlog) = self.binlog.as_mut() {
+            binlog.close().await?;
+        }
+        self.snapshot_conn = None;
+        self.server.clone().disconnect().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{RetryConfig, Secret};
+    use tokio::sync::broadcast;
+
+    fn mysql_source(
+        server_id: Option<u32>,
+        settings: &[(&str, &str)],
+    ) -> Result<MysqlSource, Error> {
+        let mysql = MysqlConfig {
+            host: "127.0.0.1".to_string(),
+            port: 3306,
+            user: "fust".to_string(),
+            password: Secret::new("secret"),
+            server_id,
+            pool_max_size: None,
+            connect_timeout: 5,
+            retry: RetryConfig::default(),
+        };
+        let settings: HashMap<String, String> = settings
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let (_, shutdown_rx) = broadcast::channel(1);
+        MysqlSource::from_mysql(&mysql, &settings, vec![], shutdown_rx)
+    }
+
+    #[tokio::test]
+    async fn reads_settings() {
+        let source = mysql_source(
+            Some(4201),
+            &[
+                ("database", "shop"),
+                ("table", "orders"),
+                ("key", "tenant, id"),
+                ("mode", "snapshot_cdc"),
+            ],
+        )
+        .unwrap();
+        assert_eq!(source.mode, Mode::SnapshotCdc);
+        assert_eq!(
+            source.key,
+            Some(vec!["tenant".to_string(), "id".to_string()])
+        );
+        assert_eq!(source.batch_size, DEFAULT_BATCH_SIZE);
+        assert_eq!(source.position(), None);
+
+        let err = |server_id, settings: &[(&str, &str)]| {
+            mysql_source(server_id, settings).err().unwrap().to_string()
+        };
+        assert_eq!(
+            err(
+                None,
+                &[("database", "shop"), ("table", "orders"), ("mode", "cdc")]
+            ),
+            "reading the binlog takes a `server_id` on the mysql connector"
+        );
+        assert_eq!(
+            err(Some(1), &[("table", "orders")]),
+            "mysql source is missing `database`"
+        );
+        assert!(err(
+            None,
+            &[("database", "shop"), ("table", "orders"), ("mode", "poll")]
+        )
+        .starts_with("invalid mode `poll`"));
+    }
+
+    #[tokio::test]
+    async fn refuses_checkpoints_of_another_mode() {
+        let mut source = mysql_source(None, &[("database", "shop"), ("table", "orders")]).unwrap();
+        let err = source
+            .open(Some(Position::Gtid {
+                gtid_set: "3e11fa47-71ca-11e1-9e33-c80aa9429562:1-2".to_string(),
+            }))
+            .await
+            .unwrap_err();
+        assert!(err.to_string().starts_with("cannot resume `shop`.`orders`"));
+    }
+}
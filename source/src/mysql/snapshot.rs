@@ -0,0 +1,574 @@
+use checkpoint::Position;
+use chrono::{NaiveDate, NaiveDateTime};
+use config::{Field, FieldType};
+use mysql_async::{prelude::Queryable, Conn, Params, Value as MysqlValue};
+use record::{Error, Metadata, Op, Record, Row, Value};
+
+const COLUMNS_QUERY: &str = "SELECT COLUMN_NAME, DATA_TYPE, COLUMN_TYPE, IS_NULLABLE = 'YES' \
+     FROM information_schema.columns \
+     WHERE TABLE_SCHEMA = ? AND TABLE_NAME = ? \
+     ORDER BY ORDINAL_POSITION";
+
+const PRIMARY_KEY_QUERY: &str = "SELECT COLUMN_NAME \
+     FROM information_schema.key_column_usage \
+     WHERE TABLE_SCHEMA = ? AND TABLE_NAME = ? AND CONSTRAINT_NAME = 'PRIMARY' \
+     ORDER BY ORDINAL_POSITION";
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    // data_type is the bare type, such as `int`
+    pub data_type: String,
+    // column_type is the full type, such as `int unsigned` or `enum('a','b')`
+    pub column_type: String,
+    pub nullable: bool,
+}
+
+impl Column {
+    fn unsigned(&self) -> bool {
+        self.column_type.contains("unsigned")
+    }
+
+    // labels are the values an enum or set column may take, in declared order
+    fn labels(&self) -> Vec<String> {
+        let Some(list) = self
+            .column_type
+            .split_once('(')
+            .and_then(|(_, rest)| rest.strip_suffix(')'))
+        else {
+            return vec![];
+        };
+        let mut labels = vec![];
+        let mut chars = list.chars().peekable();
+        while let Some(c) = chars.next() {
+            if c != '\'' {
+                continue;
+            }
+            let mut label = String::new();
+            while let Some(c) = chars.next() {
+                match c {
+                    '\'' if chars.peek() == Some(&'\'') => {
+                        chars.next();
+                        label.push('\'');
+                    }
+                    '\'' => break,
+                    '\\' => label.extend(chars.next()),
+                    c => label.push(c),
+                }
+            }
+            labels.push(label);
+        }
+        labels
+    }
+}
+
+// Table is what a source knows of the table it reads: its columns in table
+// order, the fields it returns and the key of its rows
+#[derive(Debug, Clone)]
+pub struct Table {
+    pub database: String,
+    pub name: String,
+    pub columns: Vec<Column>,
+    pub fields: Vec<Field>,
+    pub key: Vec<String>,
+}
+
+impl Table {
+    // describe looks the table up in the catalog. Its rows are read as `fields`,
+    // or as every column when no fields are configured, and `key` overrides the
+    // primary key, which is required for tables that do not have one.
+    pub async fn describe(
+        conn: &mut Conn,
+        database: &str,
+        name: &str,
+        key: Option<&[String]>,
+        fields: &[Field],
+    ) -> Result<Self, Error> {
+        let columns: Vec<Column> = conn
+            .exec_map(
+                COLUMNS_QUERY,
+                (database, name),
+                |(name, data_type, column_type, nullable): (String, String, String, bool)| Column {
+                    name,
+                    data_type: data_type.to_lowercase(),
+                    column_type: column_type.to_lowercase(),
+                    nullable,
+                },
+            )
+            .await?;
+        if columns.is_empty() {
+            return Err(format!("table `{}`.`{}` does not exist", database, name).into());
+        }
+        let key: Vec<String> = match key {
+            Some(key) => key.to_vec(),
+            None => conn.exec(PRIMARY_KEY_QUERY, (database, name)).await?,
+        };
+        if key.is_empty() {
+            return Err(format!(
+                "`{}`.`{}` has no primary key, set `key` to the columns that identify its rows",
+                database, name
+            )
+            .into());
+        }
+
+        let fields: Vec<Field> = if fields.is_empty() {
+            columns
+                .iter()
+                .map(|c| Field {
+                    name: c.name.clone(),
+                    field_type: field_type(c),
+                    nullable: c.nullable,
+                })
+                .collect()
+        } else {
+            fields.to_vec()
+        };
+        for column in key.iter().chain(fields.iter().map(|f| &f.name)) {
+            if !columns.iter().any(|c| c.name == *column) {
+                return Err(format!(
+                    "column `{}` does not exist in `{}`.`{}`",
+                    column, database, name
+                )
+                .into());
+            }
+        }
+
+        Ok(Table {
+            database: database.to_string(),
+            name: name.to_string(),
+            columns,
+            fields,
+            key,
+        })
+    }
+
+    pub fn column(&self, name: &str) -> Option<&Column> {
+        self.columns.iter().find(|c| c.name == name)
+    }
+
+    pub fn record(&self, op: Op, before: Option<Row>, after: Option<Row>) -> Record {
+        Record {
+            op,
+            key: self.key.clone(),
+            before,
+            after,
+            metadata: Metadata {
+                connector: "mysql".to_string(),
+                database: Some(self.database.clone()),
+                table: Some(self.name.clone()),
+                ..Metadata::default()
+            },
+            event_time: None,
+        }
+    }
+}
+
+// Snapshot reads a whole table in key order, one bounded batch at a time, each
+// batch starting strictly after the last key of the previous one
+#[derive(Debug)]
+pub struct Snapshot {
+    table: Table,
+    batch_size: usize,
+    last_key: Option<Vec<String>>,
+    done: bool,
+}
+
+impl Snapshot {
+    pub fn new(table: Table, batch_size: usize) -> Self {
+        Snapshot {
+            table,
+            batch_size,
+            last_key: None,
+            done: false,
+        }
+    }
+
+    // resume continues a snapshot from a checkpoint
+    pub fn resume(&mut self, position: &Position) -> Result<(), Error> {
+        match position {
+            Position::Keyset { key } if key.len() == self.table.key.len() => {
+                self.last_key = Some(key.clone())
+            }
+            Position::Finished => self.done = true,
+            other => {
+                return Err(format!(
+                    "cannot resume the snapshot of `{}`.`{}` from {:?}",
+                    self.table.database, self.table.name, other
+                )
+                .into())
+            }
+        }
+        Ok(())
+    }
+
+    // position is the key of the last row returned, or Finished once every row has
+    // been
+    pub fn position(&self) -> Option<Position> {
+        if self.done {
+            return Some(Position::Finished);
+        }
+        self.last_key.clone().map(|key| Position::Keyset { key })
+    }
+
+    // query builds the statement for the next batch. The key columns are selected
+    // in their text form ahead of the fields, so the last row of a batch tells
+    // where the next one starts; the last key is bound as parameters.
+    pub fn query(&self) -> String {
+        let key: Vec<String> = self.table.key.iter().map(|k| quote_ident(k)).collect();
+        let columns: Vec<String> = key
+            .iter()
+            .map(|k| format!("CAST({} AS CHAR)", k))
+            .chain(self.table.fields.iter().map(|f| quote_ident(&f.name)))
+            .collect();
+        let mut query = format!(
+            "SELECT {} FROM {}.{}",
+            columns.join(", "),
+            quote_ident(&self.table.database),
+            quote_ident(&self.table.name)
+        );
+        if self.last_key.is_some() {
+            let params = vec!["?"; key.len()];
+            query.push_str(&format!(
+                " WHERE ({}) > ({})",
+                key.join(", "),
+                params.join(", ")
+            ));
+        }
+        query.push_str(&format!(
+            " ORDER BY {} LIMIT {}",
+            key.join(", "),
+            self.batch_size
+        ));
+        query
+    }
+
+    // next_batch reads the next rows, returning an empty batch once the whole table
+    // has been read
+    pub async fn next_batch(&mut self, conn: &mut Conn) -> Result<Vec<Record>, Error> {
+        if self.done {
+            return Ok(vec![]);
+        }
+        let params = match &self.last_key {
+            Some(key) => Params::Positional(key.iter().map(|k| k.as_str().into()).collect()),
+            None => Params::Empty,
+        };
+        let rows: Vec<mysql_async::Row> = conn.exec(self.query(), params).await?;
+        let key_len = self.table.key.len();
+
+        let mut records = Vec::with_capacity(rows.len());
+        for row in rows {
+            let mut values = row.unwrap().into_iter();
+            let last_key = values
+                .by_ref()
+                .take(key_len)
+                .map(|k| match k {
+                    MysqlValue::Bytes(bytes) => String::from_utf8(bytes).ok(),
+                    _ => None,
+                })
+                .collect::<Option<Vec<String>>>()
+                .ok_or_else(|| {
+                    format!(
+                        "`{}`.`{}` has a NULL key value",
+                        self.table.database, self.table.name
+                    )
+                })?;
+            let mut after = Row::new();
+            for (field, value) in self.table.fields.iter().zip(values) {
+                let column = self.table.column(&field.name).expect("fields are columns");
+                after.insert(field.name.clone(), mysql_value(column, field, value)?);
+            }
+            records.push(self.table.record(Op::Snapshot, None, Some(after)));
+            self.last_key = Some(last_key);
+        }
+        if records.len() < self.batch_size {
+            self.done = true;
+        }
+        Ok(records)
+    }
+}
+
+// field_type maps a column to the field type that holds its values. A
+// `tinyint(1)` is MySQL's boolean, and unsigned integers that outgrow their
+// signed counterparts are widened.
+pub fn field_type(column: &Column) -> FieldType {
+    match column.data_type.as_str() {
+        "tinyint" if column.column_type.starts_with("tinyint(1)") => FieldType::Boolean,
+        "tinyint" | "smallint" | "mediumint" => FieldType::Int,
+        "int" | "integer" if column.unsigned() => FieldType::BigInt,
+        "int" | "integer" => FieldType::Int,
+        "bigint" if column.unsigned() => FieldType::Decimal,
+        "bigint" => FieldType::BigInt,
+        "decimal" | "numeric" => FieldType::Decimal,
+        "float" | "double" | "real" => FieldType::Number,
+        "date" => FieldType::Date,
+        "datetime" | "timestamp" => FieldType::Timestamp,
+        "json" => FieldType::Json,
+        "binary" | "varbinary" | "tinyblob" | "blob" | "mediumblob" | "longblob" | "bit" => {
+            FieldType::Bytes
+        }
+        _ => FieldType::String,
+    }
+}
+
+// mysql_value types a value read from MySQL by its field. Numbers and dates keep
+// their binary form where the field holds them, and everything else goes through
+// its text form. TIMESTAMP columns are read in UTC, and zero dates, which have no
+// calendar date, read as NULL.
+pub fn mysql_value(column: &Column, field: &Field, value: MysqlValue) -> Result<Value, String> {
+    let typed = match (value, field.field_type) {
+        (MysqlValue::NULL, _) => return Value::from_field(field, None),
+        (MysqlValue::Int(i), FieldType::Boolean) => Value::Bool(i != 0),
+        (MysqlValue::Int(i), FieldType::Int | FieldType::BigInt) => Value::Int(i),
+        (MysqlValue::Int(i), FieldType::Number) => Value::Float(i as f64),
+        (MysqlValue::UInt(u), FieldType::Boolean) => Value::Bool(u != 0),
+        (MysqlValue::UInt(u), FieldType::Int | FieldType::BigInt) if u <= i64::MAX as u64 => {
+            Value::Int(u as i64)
+        }
+        (MysqlValue::Float(f), FieldType::Number) => Value::Float(f as f64),
+        (MysqlValue::Double(d), FieldType::Number) => Value::Float(d),
+        (MysqlValue::Bytes(bytes), FieldType::Bytes) => Value::Bytes(bytes),
+        (MysqlValue::Date(y, m, d, h, i, s, us), FieldType::Date | FieldType::Timestamp) => {
+            let Some(date) = NaiveDate::from_ymd_opt(y as i32, m as u32, d as u32) else {
+                return Value::from_field(field, None);
+            };
+            match field.field_type {
+                FieldType::Date => Value::Date(date),
+                _ => timestamp(
+                    column,
+                    date.and_hms_micro_opt(h as u32, i as u32, s as u32, us)
+                        .ok_or_else(|| format!("field `{}`: invalid time", field.name))?,
+                ),
+            }
+        }
+        (value, _) => match Value::from_field(field, Some(&text(&value)))? {
+            Value::Timestamp(naive) => timestamp(column, naive),
+            value => value,
+        },
+    };
+    Ok(typed)
+}
+
+fn timestamp(column: &Column, naive: NaiveDateTime) -> Value {
+    match column.data_type.as_str() {
+        "timestamp" => Value::TimestampTz(naive.and_utc()),
+        _ => Value::Timestamp(naive),
+    }
+}
+
+// text is the text form of a value, as MySQL prints it
+fn text(value: &MysqlValue) -> String {
+    match value {
+        MysqlValue::NULL => String::new(),
+        MysqlValue::Bytes(bytes) => String::from_utf8_lossy(bytes).into_owned(),
+        MysqlValue::Int(i) => i.to_string(),
+        MysqlValue::UInt(u) => u.to_string(),
+        MysqlValue::Float(f) => f.to_string(),
+        MysqlValue::Double(d) => d.to_string(),
+        MysqlValue::Date(y, m, d, h, i, s, us) => {
+            let mut text = format!("{:04}-{:02}-{:02} {:02}:{:02}:{:02}", y, m, d, h, i, s);
+            if *us > 0 {
+                text.push_str(&format!(".{:06}", us));
+            }
+            text
+        }
+        MysqlValue::Time(negative, days, h, i, s, us) => {
+            let mut text = format!(
+                "{}{:02}:{:02}:{:02}",
+                if *negative { "-" } else { "" },
+                days * 24 + *h as u32,
+                i,
+                s
+            );
+            if *us > 0 {
+                text.push_str(&format!(".{:06}", us));
+            }
+            text
+        }
+    }
+}
+
+// unsigned reads an integer the binlog decoded as signed, because it did not say
+// the column is unsigned, back as the unsigned value it holds
+pub fn unsigned(column: &Column, value: i64) -> MysqlValue {
+    if !column.unsigned() || value >= 0 {
+        return MysqlValue::Int(value);
+    }
+    let bits = match column.data_type.as_str() {
+        "tinyint" => 8,
+        "smallint" => 16,
+        "mediumint" => 24,
+        "int" | "integer" => 32,
+        _ => 64,
+    };
+    let value = value as u64;
+    MysqlValue::UInt(if bits == 64 {
+        value
+    } else {
+        value & ((1 << bits) - 1)
+    })
+}
+
+// enum_label is the label an enum column holds by its 1-based index, where 0 is
+// the empty string MySQL stores for invalid values
+pub fn enum_label(column: &Column, index: i64) -> MysqlValue {
+    let label = usize::try_from(index - 1)
+        .ok()
+        .and_then(|i| column.labels().into_iter().nth(i))
+        .unwrap_or_default();
+    MysqlValue::Bytes(label.into_bytes())
+}
+
+// set_labels are the labels a set column holds, by the bits of its value
+pub fn set_labels(column: &Column, bits: &[u8]) -> MysqlValue {
+    let labels: Vec<String> = column
+        .labels()
+        .into_iter()
+        .enumerate()
+        .filter(|(i, _)| {
+            bits.get(i / 8)
+                .is_some_and(|byte| byte & (1 << (i % 8)) != 0)
+        })
+        .map(|(_, label)| label)
+        .collect();
+    MysqlValue::Bytes(labels.join(",").into_bytes())
+}
+
+// quote_ident quotes an identifier so it can be used verbatim in a statement
+pub fn quote_ident(ident: &str) -> String {
+    format!("`{}`", ident.replace('`', "``"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn column(name: &str, column_type: &str) -> Column {
+        Column {
+            name: name.to_string(),
+            data_type: column_type
+                .split(['(', ' '])
+                .next()
+                .unwrap_or_default()
+                .to_string(),
+            column_type: column_type.to_string(),
+            nullable: true,
+        }
+    }
+
+    fn table(key: &[&str]) -> Table {
+        let columns = vec![
+            column("tenant", "varchar(32)"),
+            column("id", "int"),
+            column("total", "decimal(10,2)"),
+        ];
+        Table {
+            database: "shop".to_string(),
+            name: "orders".to_string(),
+            fields: columns
+                .iter()
+                .map(|c| Field {
+                    name: c.name.clone(),
+                    field_type: field_type(c),
+                    nullable: c.nullable,
+                })
+                .collect(),
+            columns,
+            key: key.iter().map(|k| k.to_string()).collect(),
+        }
+    }
+
+    #[test]
+    fn query_pages_by_key() {
+        let mut snapshot = Snapshot::new(table(&["tenant", "id"]), 100);
+        assert_eq!(
+            snapshot.query(),
+            "SELECT CAST(`tenant` AS CHAR), CAST(`id` AS CHAR), `tenant`, `id`, `total` \
+             FROM `shop`.`orders` ORDER BY `tenant`, `id` LIMIT 100"
+        );
+        snapshot
+            .resume(&Position::Keyset {
+                key: vec!["acme".to_string(), "7".to_string()],
+            })
+            .unwrap();
+        assert!(snapshot
+            .query()
+            .contains("WHERE (`tenant`, `id`) > (?, ?) ORDER BY"));
+        assert!(snapshot
+            .resume(&Position::Keyset {
+                key: vec!["7".to_string()]
+            })
+            .is_err());
+    }
+
+    #[test]
+    fn maps_column_types() {
+        let types = |column_type: &str| field_type(&column("c", column_type));
+        assert_eq!(types("tinyint(1)"), FieldType::Boolean);
+        assert_eq!(types("tinyint(4)"), FieldType::Int);
+        assert_eq!(types("int unsigned"), FieldType::BigInt);
+        assert_eq!(types("bigint unsigned"), FieldType::Decimal);
+        assert_eq!(types("datetime(6)"), FieldType::Timestamp);
+        assert_eq!(types("varbinary(16)"), FieldType::Bytes);
+        assert_eq!(types("enum('a','b')"), FieldType::String);
+    }
+
+    #[test]
+    fn types_values() {
+        let typed = |column_type: &str, value: MysqlValue| {
+            let column = column("c", column_type);
+            let field = Field {
+                name: "c".to_string(),
+                field_type: field_type(&column),
+                nullable: true,
+            };
+            mysql_value(&column, &field, value).unwrap()
+        };
+        assert_eq!(typed("tinyint(1)", MysqlValue::Int(1)), Value::Bool(true));
+        assert_eq!(
+            typed("bigint unsigned", MysqlValue::UInt(u64::MAX)),
+            Value::Decimal(u64::MAX.to_string())
+        );
+        assert_eq!(
+            typed("decimal(10,2)", MysqlValue::Bytes(b"12.50".to_vec())),
+            Value::Decimal("12.50".to_string())
+        );
+        assert_eq!(
+            typed("timestamp", MysqlValue::Date(2024, 3, 1, 10, 15, 0, 0)),
+            Value::TimestampTz(
+                NaiveDate::from_ymd_opt(2024, 3, 1)
+                    .unwrap()
+                    .and_hms_opt(10, 15, 0)
+                    .unwrap()
+                    .and_utc()
+            )
+        );
+        assert_eq!(
+            typed("datetime", MysqlValue::Date(0, 0, 0, 0, 0, 0, 0)),
+            Value::Null
+        );
+        assert_eq!(
+            typed("time", MysqlValue::Time(true, 1, 2, 3, 4, 0)),
+            Value::String("-26:03:04".to_string())
+        );
+    }
+
+    #[test]
+    fn reads_binlog_integers_and_labels() {
+        let status = column("status", "enum('new','it''s paid','shipped')");
+        assert_eq!(
+            enum_label(&status, 2),
+            MysqlValue::Bytes(b"it's paid".to_vec())
+        );
+        assert_eq!(enum_label(&status, 0), MysqlValue::Bytes(vec![]));
+        let tags = column("tags", "set('a','b','c')");
+        assert_eq!(
+            set_labels(&tags, &[0b101]),
+            MysqlValue::Bytes(b"a,c".to_vec())
+        );
+        assert_eq!(
+            unsigned(&column("n", "int unsigned"), -1),
+            MysqlValue::UInt(u32::MAX as u64)
+        );
+        assert_eq!(unsigned(&column("n", "int"), -1), MysqlValue::Int(-1));
+    }
+}
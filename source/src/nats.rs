@@ -0,0 +1,376 @@
+use std::{collections::HashMap, collections::VecDeque, pin::Pin, time::Duration};
+
+use async_nats::jetstream::{
+    self,
+    consumer::{pull, AckPolicy, DeliverPolicy},
+};
+use async_trait::async_trait;
+use checkpoint::Position;
+use chrono::{DateTime, Utc};
+use config::{ConnectorConfig, Field, NatsConfig, SourceConfig};
+use record::{Batch, Error, Metadata, Record};
+use tokio::sync::broadcast::Receiver;
+use tokio_stream::{Stream, StreamExt, StreamMap};
+use tracing::{info, warn};
+
+use crate::{
+    codec::{Codec, Decoder},
+    Source,
+};
+
+const DEFAULT_BATCH_SIZE: usize = 100;
+// LINGER is how long a read waits for more messages once it has one
+const LINGER: Duration = Duration::from_millis(50);
+// ACK_WAIT is how long the server waits for an ack before it redelivers a message
+const ACK_WAIT: Duration = Duration::from_secs(60);
+
+// Incoming is a message waiting to be decoded
+struct Incoming {
+    subject: String,
+    payload: Vec<u8>,
+    published: Option<DateTime<Utc>>,
+    // message is the JetStream message to acknowledge once the batch is delivered
+    message: Option<jetstream::Message>,
+}
+
+type Inbox = Pin<Box<dyn Stream<Item = Result<Incoming, Error>> + Send>>;
+
+// JetStream names the stream and the durable consumer a source reads through
+#[derive(Debug, Clone, PartialEq)]
+struct JetStream {
+    stream: String,
+    durable: String,
+}
+
+// NatsSource reads messages from the subjects in `topic` (comma separated,
+// defaulting to the connector's topic) and decodes them with `codec`.
+//
+// Core NATS delivers only what is published while the source is subscribed. With
+// `jetstream = "true"` the source reads `stream` through a durable pull consumer,
+// `durable`, and acknowledges messages only once the sinks have written them, so
+// the server redelivers anything that was read but not delivered.
+pub struct NatsSource {
+    url: String,
+    subjects: Vec<String>,
+    jetstream: Option<JetStream>,
+    decoder: Decoder,
+    batch_size: usize,
+    skip_errors: bool,
+    shutdown_rx: Receiver<()>,
+    client: Option<async_nats::Client>,
+    inbox: Option<Inbox>,
+    // unacked holds JetStream messages by stream sequence until they are delivered
+    unacked: VecDeque<(u64, jetstream::Message)>,
+}
+
+impl NatsSource {
+    pub fn new(config: &SourceConfig, shutdown_rx: Receiver<()>) -> Result<Self, Error> {
+        match &config.connector {
+            ConnectorConfig::Nats(nats) => {
+                Self::from_nats(nats, &config.config, config.fields.clone(), shutdown_rx)
+            }
+            other => Err(format!("a {} connector cannot back a nats source", other.kind()).into()),
+        }
+    }
+
+    pub fn from_nats(
+        nats: &NatsConfig,
+        settings: &HashMap<String, String>,
+        fields: Vec<Field>,
+        shutdown_rx: Receiver<()>,
+    ) -> Result<Self, Error> {
+        let subjects = list(settings.get("topic").unwrap_or(&nats.topic));
+        if subjects.is_empty() {
+            return Err("nats source has no subject to read".into());
+        }
+        let jetstream = match settings.get("jetstream").map(String::as_str) {
+            None | Some("false") => None,
+            Some("true") => {
+                let stream = settings
+                    .get("stream")
+                    .cloned()
+                    .ok_or("a jetstream source needs a `stream`")?;
+                let durable = settings
+                    .get("durable")
+                    .cloned()
+                    .unwrap_or_else(|| durable_name(&stream, &subjects));
+                Some(JetStream { stream, durable })
+            }
+            Some(other) => {
+                return Err(
+                    format!("invalid jetstream `{}`, expected `true` or `false`", other).into(),
+                )
+            }
+        };
+        let batch_size = match settings.get("batch_size") {
+            Some(size) => match size.parse::<usize>() {
+                Ok(size) if size > 0 => size,
+                _ => return Err(format!("invalid batch_size `{}`", size).into()),
+            },
+            None => DEFAULT_BATCH_SIZE,
+        };
+        let skip_errors = match settings.get("on_error").map(String::as_str) {
+            None | Some("fail") => false,
+            Some("skip") => true,
+            Some(other) => {
+                return Err(
+                    format!("invalid on_error `{}`, expected `fail` or `skip`", other).into(),
+                )
+            }
+        };
+        let codec = Codec::parse(settings.get("codec").map(String::as_str).unwrap_or("json"))?;
+        let key = settings.get("key").map(|key| list(key)).unwrap_or_default();
+
+        Ok(NatsSource {
+            url: nats.url.clone(),
+            subjects,
+            jetstream,
+            decoder: Decoder::new(codec, key, fields),
+            batch_size,
+            skip_errors,
+            shutdown_rx,
+            client: None,
+            inbox: None,
+            unacked: VecDeque::new(),
+        })
+    }
+
+    fn decode(&mut self, incoming: Incoming) -> Result<Option<Record>, Error> {
+        let mut metadata = Metadata {
+            connector: "nats".to_string(),
+            ..Metadata::default()
+        };
+        metadata
+            .attributes
+            .insert("subject".to_string(), incoming.subject.clone());
+        if let Some(message) = incoming.message {
+            let sequence = message.info()?.stream_sequence;
+            metadata
+                .attributes
+                .insert("stream".to_string(), message.info()?.stream.to_string());
+            metadata
+                .attributes
+                .insert("sequence".to_string(), sequence.to_string());
+            self.unacked.push_back((sequence, message));
+        }
+        match self
+            .decoder
+            .decode(&incoming.payload, metadata, incoming.published)
+        {
+            Ok(record) => Ok(Some(record)),
+            Err(e) if self.skip_errors => {
+                warn!("skipped a message on `{}`: {}", incoming.subject, e);
+                Ok(None)
+            }
+            Err(e) => Err(format!("message on `{}`: {}", incoming.subject, e).into()),
+        }
+    }
+}
+
+async fn subscribe(client: &async_nats::Client, subjects: &[String]) -> Result<Inbox, Error> {
+    let mut subscriptions = StreamMap::new();
+    for (i, subject) in subjects.iter().enumerate() {
+        subscriptions.insert(i, client.subscribe(subject.clone()).await?);
+    }
+    Ok(Box::pin(subscriptions.map(|(_, message)| {
+        Ok(Incoming {
+            subject: message.subject.to_string(),
+            payload: message.payload.to_vec(),
+            published: None,
+            message: None,
+        })
+    })))
+}
+
+async fn consume(
+    client: &async_nats::Client,
+    subjects: &[String],
+    settings: &JetStream,
+    checkpoint: Option<Position>,
+) -> Result<Inbox, Error> {
+    let context = jetstream::new(client.clone());
+    let stream = context.get_stream(&settings.stream).await?;
+    // a new consumer starts after the checkpoint; an existing one knows where it is
+    let deliver_policy = match checkpoint {
+        Some(Position::StreamSequence { sequence }) => DeliverPolicy::ByStartSequence {
+            start_sequence: sequence + 1,
+        },
+        _ => DeliverPolicy::All,
+    };
+    let mut config = pull::Config {
+        durable_name: Some(settings.durable.clone()),
+        ack_policy: AckPolicy::Explicit,
+        ack_wait: ACK_WAIT,
+        deliver_policy,
+        ..Default::default()
+    };
+    match subjects {
+        [subject] => config.filter_subject = subject.clone(),
+        subjects => config.filter_subjects = subjects.to_vec(),
+    }
+    let consumer = stream
+        .get_or_create_consumer(&settings.durable, config)
+        .await?;
+    let messages = consumer.messages().await?;
+    Ok(Box::pin(messages.map(|message| {
+        let message = message?;
+        let published = message.info().ok().and_then(|info| {
+            DateTime::from_timestamp(info.published.unix_timestamp(), info.published.nanosecond())
+        });
+        Ok(Incoming {
+            subject: message.subject.to_string(),
+            payload: message.payload.to_vec(),
+            published,
+            message: Some(message),
+        })
+    })))
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+// durable_name derives a consumer name from the stream and subjects it reads, as
+// consumer names cannot contain `.`, `*` or `>`
+fn durable_name(stream: &str, subjects: &[String]) -> String {
+    let name: String = format!("fust_{}_{}", stream, subjects.join("_"))
+        .chars()
+        .map(|c| match c {
+            '*' => 'x',
+            '>' => 'X',
+            c if c.is_ascii_alphanumeric() || c == '-' || c == '_' => c,
+            _ => '_',
+        })
+        .collect();
+    name
+}
+
+#[async_trait]
+impl Source for NatsSource {
+    async fn open(&mut self, checkpoint: Option<Position>) -> Result<(), Error> {
+        if let Some(position) = &checkpoint {
+            if !matches!(position, Position::StreamSequence { .. }) {
+                return Err(format!("cannot resume a nats source from {:?}", position).into());
+            }
+        }
+        info!("connecting nats source to {}", self.url);
+        let client = async_nats::connect(&self.url).await?;
+        let inbox = match &self.jetstream {
+            Some(settings) => consume(&client, &self.subjects, settings, checkpoint).await?,
+            None => subscribe(&client, &self.subjects).await?,
+        };
+        self.inbox = Some(inbox);
+        self.client = Some(client);
+        Ok(())
+    }
+
+    // read waits for the next message, then takes whatever else has already
+    // arrived, up to `batch_size` messages
+    async fn read(&mut self) -> Result<Option<Batch>, Error> {
+        let Some(inbox) = self.inbox.as_mut() else {
+            return Err("nats source read before it was opened".into());
+        };
+        let first = tokio::select! {
+            incoming = inbox.next() => incoming,
+            _ = self.shutdown_rx.recv() => return Ok(None),
+        };
+        let Some(first) = first else {
+            return Ok(None);
+        };
+        let mut incoming = vec![first?];
+        while incoming.len() < self.batch_size {
+            match tokio::time::timeout(LINGER, inbox.next()).await {
+                Ok(Some(next)) => incoming.push(next?),
+                _ => break,
+            }
+        }
+
+        let mut records = Vec::with_capacity(incoming.len());
+        for incoming in incoming {
+            records.extend(self.decode(incoming)?);
+        }
+        let position = self
+            .unacked
+            .back()
+            .map(|(sequence, _)| Position::StreamSequence {
+                sequence: *sequence,
+            });
+        Ok(Some(Batch { records, position }))
+    }
+
+    // ack acknowledges every JetStream message up to the position
+    async fn ack(&mut self, position: &Position) -> Result<(), Error> {
+        let Position::StreamSequence { sequence } = position else {
+            return Ok(());
+        };
+        while let Some((next, _)) = self.unacked.front() {
+            if next > sequence {
+                break;
+            }
+            let (_, message) = self.unacked.pop_front().expect("front exists");
+            message.ack().await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        self.inbox = None;
+        if let Some(client) = self.client.take() {
+            client.flush().await?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(pairs: &[(&str, &str)]) -> HashMap<String, String> {
+        pairs
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect()
+    }
+
+    fn source(pairs: &[(&str, &str)]) -> Result<NatsSource, Error> {
+        let nats = NatsConfig {
+            url: "nats://localhost:4222".to_string(),
+            topic: "events".to_string(),
+            retry: Default::default(),
+        };
+        let (_tx, rx) = tokio::sync::broadcast::channel(1);
+        NatsSource::from_nats(&nats, &settings(pairs), vec![], rx)
+    }
+
+    #[test]
+    fn reads_settings() {
+        let core = source(&[]).unwrap();
+        assert_eq!(core.subjects, vec!["events"]);
+        assert_eq!(core.jetstream, None);
+
+        let jetstream = source(&[
+            ("topic", "orders.*, refunds.>"),
+            ("jetstream", "true"),
+            ("stream", "ORDERS"),
+        ])
+        .unwrap();
+        assert_eq!(jetstream.subjects, vec!["orders.*", "refunds.>"]);
+        assert_eq!(
+            jetstream.jetstream,
+            Some(JetStream {
+                stream: "ORDERS".to_string(),
+                durable: "fust_ORDERS_orders_x_refunds_X".to_string(),
+            })
+        );
+
+        assert!(source(&[("jetstream", "true")]).is_err());
+        assert!(source(&[("codec", "avro")]).is_err());
+        assert!(source(&[("batch_size", "0")]).is_err());
+        assert!(source(&[("topic", " ")]).is_err());
+    }
+}
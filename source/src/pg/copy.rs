@@ -0,0 +1,338 @@
+use std::{fmt, pin::Pin};
+
+use chrono::{Duration, NaiveDate, NaiveDateTime};
+use config::Field;
+use deadpool_postgres::Object;
+use record::{Error, Row, Value};
+use tokio_postgres::{
+    binary_copy::BinaryCopyOutRow,
+    types::{FromSql, Type},
+};
+use tokio_stream::Stream;
+
+use super::snapshot::field_type;
+
+// NUMERIC_* are the sign words of the binary numeric format
+const NUMERIC_NEGATIVE: u16 = 0x4000;
+const NUMERIC_NAN: u16 = 0xC000;
+const NUMERIC_INFINITY: u16 = 0xD000;
+const NUMERIC_NEGATIVE_INFINITY: u16 = 0xF000;
+
+// CopyRow is a copied row, decoded, along with the text form of its key
+pub type CopyRow = (Row, Vec<String>);
+
+// CopyOut is a running `COPY ... TO STDOUT (FORMAT binary)`, its rows decoded as
+// they are read. It holds on to its connection, which cannot be used for anything
+// else until the copy is over.
+pub struct CopyOut {
+    _client: Option<Object>,
+    pub rows: Pin<Box<dyn Stream<Item = Result<CopyRow, Error>> + Send + Sync>>,
+}
+
+impl CopyOut {
+    pub fn new(
+        client: Object,
+        rows: impl Stream<Item = Result<CopyRow, Error>> + Send + Sync + 'static,
+    ) -> Self {
+        CopyOut {
+            _client: Some(client),
+            rows: Box::pin(rows),
+        }
+    }
+
+    #[cfg(test)]
+    pub fn from_rows(
+        rows: impl Stream<Item = Result<CopyRow, Error>> + Send + Sync + 'static,
+    ) -> Self {
+        CopyOut {
+            _client: None,
+            rows: Box::pin(rows),
+        }
+    }
+}
+
+impl fmt::Debug for CopyOut {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("CopyOut")
+    }
+}
+
+// binary_type is the type a column is copied as when fust decodes its binary
+// form, or None when it is copied as text. Columns typed as something else than
+// their own type are copied as text, so the field decides how they are read.
+pub fn binary_type(field: &Field, pg_type: &str) -> Option<Type> {
+    if field.field_type != field_type(pg_type) {
+        return None;
+    }
+    let binary = match pg_type {
+        "smallint" => Type::INT2,
+        "integer" => Type::INT4,
+        "bigint" => Type::INT8,
+        "real" => Type::FLOAT4,
+        "double precision" => Type::FLOAT8,
+        "numeric" => Type::NUMERIC,
+        "boolean" => Type::BOOL,
+        "date" => Type::DATE,
+        "timestamp without time zone" => Type::TIMESTAMP,
+        "timestamp with time zone" => Type::TIMESTAMPTZ,
+        "uuid" => Type::UUID,
+        "json" => Type::JSON,
+        "jsonb" => Type::JSONB,
+        "bytea" => Type::BYTEA,
+        "text" => Type::TEXT,
+        "character varying" => Type::VARCHAR,
+        "character" => Type::BPCHAR,
+        "name" => Type::NAME,
+        _ => return None,
+    };
+    Some(binary)
+}
+
+// decode reads a copied row of `relation`: its fields, each with whether it is
+// copied in binary, followed by the text form of its `key_len` key columns
+pub fn decode(
+    row: &BinaryCopyOutRow,
+    fields: &[(Field, bool)],
+    key_len: usize,
+    relation: &str,
+) -> Result<CopyRow, Error> {
+    let mut after = Row::new();
+    for (i, (field, binary)) in fields.iter().enumerate() {
+        after.insert(field.name.clone(), value(row, i, field, *binary)?);
+    }
+    let key = (0..key_len)
+        .map(|i| row.try_get::<Option<String>>(fields.len() + i))
+        .collect::<Result<Option<Vec<String>>, _>>()?
+        .ok_or_else(|| format!("{} has a NULL key value", relation))?;
+    Ok((after, key))
+}
+
+// value reads column `i` of a copied row as typed by `field`
+pub fn value(
+    row: &BinaryCopyOutRow,
+    i: usize,
+    field: &Field,
+    binary: bool,
+) -> Result<Value, Error> {
+    if !binary {
+        let text: Option<&str> = row.try_get(i)?;
+        return Ok(Value::from_field(field, text)?);
+    }
+    let Cell(value) = row
+        .try_get(i)
+        .map_err(|e| format!("field `{}`: {}", field.name, e))?;
+    if value.is_null() && !field.nullable {
+        return Err(format!("field `{}` is not nullable", field.name).into());
+    }
+    Ok(value)
+}
+
+// Cell decodes one value from its binary form
+struct Cell(Value);
+
+impl<'a> FromSql<'a> for Cell {
+    fn from_sql(ty: &Type, raw: &'a [u8]) -> Result<Self, Error> {
+        let value = match *ty {
+            Type::INT2 => Value::Int(i16::from_be_bytes(fixed(raw)?).into()),
+            Type::INT4 => Value::Int(i32::from_be_bytes(fixed(raw)?).into()),
+            Type::INT8 => Value::Int(i64::from_be_bytes(fixed(raw)?)),
+            // widening a real adds digits it never had, so it goes through its
+            // shortest text, which is also what the text form carries
+            Type::FLOAT4 => Value::Float(f32::from_be_bytes(fixed(raw)?).to_string().parse()?),
+            Type::FLOAT8 => Value::Float(f64::from_be_bytes(fixed(raw)?)),
+            Type::NUMERIC => Value::Decimal(numeric(raw)?),
+            Type::BOOL => Value::Bool(u8::from_be_bytes(fixed(raw)?) != 0),
+            Type::DATE => {
+                let days = i32::from_be_bytes(fixed(raw)?);
+                Value::Date(
+                    epoch()
+                        .date()
+                        .checked_add_signed(Duration::days(days.into()))
+                        .ok_or("date out of range")?,
+                )
+            }
+            Type::TIMESTAMP => Value::Timestamp(timestamp(raw)?),
+            Type::TIMESTAMPTZ => Value::TimestampTz(timestamp(raw)?.and_utc()),
+            Type::UUID => Value::Uuid(uuid(&fixed::<16>(raw)?)),
+            Type::JSON => Value::Json(serde_json::from_slice(raw)?),
+            Type::JSONB => match raw.split_first() {
+                Some((1, json)) => Value::Json(serde_json::from_slice(json)?),
+                _ => return Err("unsupported jsonb version".into()),
+            },
+            Type::BYTEA => Value::Bytes(raw.to_vec()),
+            _ => Value::String(std::str::from_utf8(raw)?.to_string()),
+        };
+        Ok(Cell(value))
+    }
+
+    fn from_sql_null(_: &Type) -> Result<Self, Error> {
+        Ok(Cell(Value::Null))
+    }
+
+    fn accepts(_: &Type) -> bool {
+        true
+    }
+}
+
+fn fixed<const N: usize>(raw: &[u8]) -> Result<[u8; N], Error> {
+    raw.try_into()
+        .map_err(|_| format!("expected {} bytes, got {}", N, raw.len()).into())
+}
+
+// epoch is where Postgres counts dates and timestamps from
+fn epoch() -> NaiveDateTime {
+    NaiveDate::from_ymd_opt(2000, 1, 1)
+        .and_then(|date| date.and_hms_opt(0, 0, 0))
+        .expect("the Postgres epoch is a valid time")
+}
+
+fn timestamp(raw: &[u8]) -> Result<NaiveDateTime, Error> {
+    match i64::from_be_bytes(fixed(raw)?) {
+        i64::MAX | i64::MIN => Err("infinite timestamps are not supported".into()),
+        micros => epoch()
+            .checked_add_signed(Duration::microseconds(micros))
+            .ok_or_else(|| "timestamp out of range".into()),
+    }
+}
+
+fn uuid(bytes: &[u8; 16]) -> String {
+    let hex: String = bytes.iter().map(|b| format!("{:02x}", b)).collect();
+    format!(
+        "{}-{}-{}-{}-{}",
+        &hex[0..8],
+        &hex[8..12],
+        &hex[12..16],
+        &hex[16..20],
+        &hex[20..32]
+    )
+}
+
+// numeric prints a binary numeric exactly: base 10000 digits, the weight of the
+// first one, a sign and the number of decimal digits to show
+fn numeric(raw: &[u8]) -> Result<String, Error> {
+    let word = |i: usize| -> Result<u16, Error> {
+        raw.get(i * 2..i * 2 + 2)
+            .map(|b| u16::from_be_bytes([b[0], b[1]]))
+            .ok_or_else(|| "truncated numeric".into())
+    };
+    let ndigits = word(0)? as usize;
+    let weight = word(1)? as i16 as isize;
+    let sign = word(2)?;
+    let scale = word(3)? as usize;
+    match sign {
+        NUMERIC_NAN => return Ok("NaN".to_string()),
+        NUMERIC_INFINITY => return Ok("Infinity".to_string()),
+        NUMERIC_NEGATIVE_INFINITY => return Ok("-Infinity".to_string()),
+        _ => {}
+    }
+    let digits = (0..ndigits)
+        .map(|i| word(4 + i))
+        .collect::<Result<Vec<u16>, Error>>()?;
+    let digit = |i: isize| -> u16 {
+        usize::try_from(i)
+            .ok()
+            .and_then(|i| digits.get(i).copied())
+            .unwrap_or(0)
+    };
+
+    let mut text = String::new();
+    if sign == NUMERIC_NEGATIVE {
+        text.push('-');
+    }
+    if weight < 0 {
+        text.push('0');
+    } else {
+        text.push_str(&digit(0).to_string());
+        for i in 1..=weight {
+            text.push_str(&format!("{:04}", digit(i)));
+        }
+    }
+    if scale > 0 {
+        let mut fraction = String::new();
+        let mut i = weight + 1;
+        while fraction.len() < scale {
+            fraction.push_str(&format!("{:04}", digit(i)));
+            i += 1;
+        }
+        fraction.truncate(scale);
+        text.push('.');
+        text.push_str(&fraction);
+    }
+    Ok(text)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::DateTime;
+    use config::FieldType;
+
+    fn numeric_bytes(weight: i16, sign: u16, scale: u16, digits: &[u16]) -> Vec<u8> {
+        let mut raw = vec![];
+        for word in [digits.len() as u16, weight as u16, sign, scale]
+            .into_iter()
+            .chain(digits.iter().copied())
+        {
+            raw.extend(word.to_be_bytes());
+        }
+        raw
+    }
+
+    #[test]
+    fn decodes_numerics() {
+        let cases = [
+            (numeric_bytes(1, 0, 3, &[1, 2345, 6780]), "12345.678"),
+            (numeric_bytes(-1, 0, 4, &[12]), "0.0012"),
+            (numeric_bytes(0, NUMERIC_NEGATIVE, 0, &[5]), "-5"),
+            (numeric_bytes(2, 0, 2, &[7]), "700000000.00"),
+            (numeric_bytes(0, 0, 0, &[]), "0"),
+            (numeric_bytes(0, NUMERIC_NAN, 0, &[]), "NaN"),
+        ];
+        for (raw, text) in cases {
+            assert_eq!(numeric(&raw).unwrap(), text);
+        }
+        assert!(numeric(&[0, 1]).is_err());
+    }
+
+    #[test]
+    fn decodes_binary_values() {
+        let cell = |ty: Type, raw: &[u8]| Cell::from_sql(&ty, raw).unwrap().0;
+        assert_eq!(cell(Type::INT4, &42i32.to_be_bytes()), Value::Int(42));
+        // a real reads the same copied in binary as streamed in text
+        assert_eq!(
+            cell(Type::FLOAT4, &0.1f32.to_be_bytes()),
+            Value::parse(FieldType::Number, "0.1").unwrap()
+        );
+        assert_eq!(
+            cell(Type::DATE, &31i32.to_be_bytes()),
+            Value::Date(NaiveDate::from_ymd_opt(2000, 2, 1).unwrap())
+        );
+        assert_eq!(
+            cell(Type::TIMESTAMPTZ, &1_500_000i64.to_be_bytes()),
+            Value::TimestampTz(DateTime::from_timestamp(946_684_801, 500_000_000).unwrap())
+        );
+        assert_eq!(
+            cell(Type::JSONB, b"\x01{\"a\":1}"),
+            Value::Json(serde_json::json!({"a": 1}))
+        );
+        assert_eq!(
+            cell(Type::UUID, &[0xab; 16]),
+            Value::Uuid("abababab-abab-abab-abab-abababababab".to_string())
+        );
+        assert!(Cell::from_sql(&Type::TIMESTAMP, &i64::MAX.to_be_bytes()).is_err());
+        assert!(Cell::from_sql(&Type::INT8, &[1, 2]).is_err());
+
+        let field = |field_type| Field {
+            name: "total".to_string(),
+            field_type,
+            nullable: true,
+        };
+        assert_eq!(
+            binary_type(&field(FieldType::Decimal), "numeric"),
+            Some(Type::NUMERIC)
+        );
+        // a column read as another type is copied as text
+        assert_eq!(binary_type(&field(FieldType::String), "numeric"), None);
+        assert_eq!(binary_type(&field(FieldType::String), "integer[]"), None);
+    }
+}
@@ -0,0 +1,431 @@
+mod copy;
+mod parallel;
+mod pgoutput;
+mod replication;
+mod snapshot;
+
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use checkpoint::Position;
+use config::{ConnectorConfig, Field, RdsConfig, SourceConfig};
+use deadpool_postgres::Pool;
+use record::{Batch, Error, Record};
+use tokio::sync::broadcast::Receiver;
+
+use tracing::info;
+use util::pg::Pools;
+
+pub use parallel::ParallelSnapshot;
+pub use pgoutput::{Lsn, Message};
+pub use replication::{Replication, Visibility};
+pub use snapshot::{field_type, quote_ident, Column, Snapshot};
+
+use crate::Source;
+
+const DEFAULT_SCHEMA: &str = "public";
+const DEFAULT_BATCH_SIZE: i64 = 1000;
+// POLL_INTERVAL is how long change data capture waits before looking for new
+// changes after finding none
+const POLL_INTERVAL: Duration = Duration::from_millis(200);
+
+// Mode is how a PgSource reads its table
+#[derive(Debug, Clone, PartialEq)]
+pub enum Mode {
+    // Snapshot reads every row once, in primary key order, or in `parallelism`
+    // key ranges read concurrently
+    Snapshot,
+    // Copy reads every row once like Snapshot, streaming the table through a
+    // single binary COPY instead of paging through it
+    Copy,
+    // Cdc streams every committed change through a logical replication slot
+    Cdc { slot: String, publication: String },
+    // SnapshotCdc creates the replication slot, snapshots the table as of a point
+    // after it, then streams the changes the snapshot does not hold
+    SnapshotCdc { slot: String, publication: String },
+}
+
+pub struct PgSource {
+    pool: Pool,
+    shutdown_rx: Receiver<()>,
+    database: String,
+    schema: String,
+    table: String,
+    key: Option<Vec<String>>,
+    fields: Vec<Field>,
+    batch_size: i64,
+    parallelism: usize,
+    mode: Mode,
+    snapshot: Option<Snapshot>,
+    parallel: Option<ParallelSnapshot>,
+    replication: Option<Replication>,
+}
+
+impl PgSource {
+    pub fn new(
+        config: &SourceConfig,
+        shutdown_rx: Receiver<()>,
+        pools: &Pools,
+    ) -> Result<Self, Error> {
+        match &config.connector {
+            ConnectorConfig::Postgres(rds) => Self::from_rds(
+                &config.connector_name,
+                rds,
+                &config.config,
+                config.fields.clone(),
+                shutdown_rx,
+                pools,
+            ),
+            other => {
+                Err(format!("a {} connector cannot back a postgres source", other.kind()).into())
+            }
+        }
+    }
+
+    // from_rds builds a source for one table from the connector it reads through,
+    // taking its connections from the connector's pool in `pools`, and the
+    // source's own settings: `database`, `schema`, `table`, `key` and
+    // `batch_size`, plus `mode = "cdc"` with optional `slot` and `publication`
+    // names to stream changes rather than snapshot the table, `mode = "snapshot_cdc"`
+    // to snapshot it and then stream its changes, or `mode = "copy"` to snapshot
+    // it through a binary COPY. `parallelism` splits a snapshot into
+    // that many key ranges read over as many connections.
+    pub fn from_rds(
+        connector: &str,
+        rds: &RdsConfig,
+        settings: &HashMap<String, String>,
+        fields: Vec<Field>,
+        shutdown_rx: Receiver<()>,
+        pools: &Pools,
+    ) -> Result<Self, Error> {
+        let setting = |key: &str| -> Result<String, Error> {
+            settings
+                .get(key)
+                .cloned()
+                .ok_or_else(|| format!("postgres source is missing `{}`", key).into())
+        };
+        let batch_size = match settings.get("batch_size") {
+            Some(size) => match size.parse::<i64>() {
+                Ok(size) if size > 0 => size,
+                _ => return Err(format!("invalid batch_size `{}`", size).into()),
+            },
+            None => DEFAULT_BATCH_SIZE,
+        };
+        let parallelism = match settings.get("parallelism") {
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) if n > 0 => n,
+                _ => return Err(format!("invalid parallelism `{}`", n).into()),
+            },
+            None => 1,
+        };
+        let key = settings.get("key").map(|key| {
+            key.split(',')
+                .map(|column| column.trim().to_string())
+                .collect()
+        });
+
+        let database = setting("database")?;
+        let table = setting("table")?;
+        let slot = settings
+            .get("slot")
+            .cloned()
+            .unwrap_or_else(|| replication::slot_name(&table));
+        let publication = settings
+            .get("publication")
+            .cloned()
+            .unwrap_or_else(|| replication::slot_name(&table));
+        let mode = match settings.get("mode").map(String::as_str) {
+            None | Some("snapshot") => Mode::Snapshot,
+            Some("copy") => Mode::Copy,
+            Some("cdc") => Mode::Cdc { slot, publication },
+            Some("snapshot_cdc") => Mode::SnapshotCdc { slot, publication },
+            Some(other) => {
+                return Err(format!(
+                    "invalid mode `{}`, expected `snapshot`, `copy`, `cdc` or `snapshot_cdc`",
+                    other
+                )
+                .into())
+            }
+        };
+        if batch_size > i32::MAX as i64 {
+            return Err(format!("invalid batch_size `{}`", batch_size).into());
+        }
+        if parallelism > 1 && !matches!(mode, Mode::Snapshot | Mode::SnapshotCdc { .. }) {
+            return Err("parallelism only applies to `snapshot` and `snapshot_cdc` modes".into());
+        }
+
+        // a parallel snapshot holds a connection per worker, plus the one exporting
+        // the snapshot they share
+        let pool = pools.get(connector, rds, &database, parallelism + 1)?;
+
+        Ok(PgSource {
+            pool,
+            shutdown_rx,
+            database,
+            schema: settings
+                .get("schema")
+                .cloned()
+                .unwrap_or_else(|| DEFAULT_SCHEMA.to_string()),
+            table,
+            key,
+            fields,
+            batch_size,
+            parallelism,
+            mode,
+            snapshot: None,
+            parallel: None,
+            replication: None,
+        })
+    }
+
+    // get_conn takes a pooled connection. It does not retry: the pipeline makes
+    // the call that failed again, under the connector's retry policy.
+    pub async fn get_conn(&self) -> Result<deadpool_postgres::Object, Error> {
+        self.pool
+            .get()
+            .await
+            .map_err(|e| format!("failed to connect to database `{}`: {}", self.database, e).into())
+    }
+
+    // position is how far the batches read so far reach
+    fn position(&self) -> Option<Position> {
+        match &self.mode {
+            Mode::Snapshot | Mode::Copy => match &self.parallel {
+                Some(parallel) => Some(parallel.position()),
+                None => self.snapshot.as_ref().and_then(Snapshot::position),
+            },
+            Mode::Cdc { .. } => self
+                .replication
+                .as_ref()
+                .map(|r| r.position())
+                .filter(|lsn| *lsn > Lsn::default())
+                .map(|lsn| Position::Lsn { lsn: lsn.0 }),
+            // nothing is checkpointed until the stream no longer depends on the
+            // snapshot: a handoff that is interrupted starts over
+            Mode::SnapshotCdc { .. } => match (&self.parallel, &self.replication) {
+                (None, Some(replication)) if replication.handed_off() => Some(Position::Lsn {
+                    lsn: replication.position().0,
+                }),
+                _ => None,
+            },
+        }
+    }
+
+    fn batch(&self, mut records: Vec<Record>) -> Batch {
+        for record in &mut records {
+            record.metadata.database = Some(self.database.clone());
+        }
+        Batch {
+            records,
+            position: self.position(),
+        }
+    }
+}
+
+/// Implement the Source trait for PgSource
+#[async_trait]
+impl Source for PgSource {
+    async fn open(&mut self, checkpoint: Option<Position>) -> Result<(), Error> {
+        let resumable = match &checkpoint {
+            None => true,
+            Some(position) => matches!(
+                (&self.mode, position),
+                (
+                    Mode::Snapshot | Mode::Copy,
+                    Position::Keyset { .. } | Position::Finished
+                ) | (Mode::Snapshot, Position::Chunks { .. })
+                    | (
+                        Mode::Cdc { .. } | Mode::SnapshotCdc { .. },
+                        Position::Lsn { .. }
+                    )
+            ),
+        };
+        if !resumable {
+            return Err(format!(
+                "cannot resume {}.{} from {:?}",
+                self.schema, self.table, checkpoint
+            )
+            .into());
+        }
+
+        let conn = self.get_conn().await?;
+        match &self.mode {
+            Mode::Snapshot | Mode::Copy => {
+                let mut snapshot = Snapshot::plan(
+                    &conn,
+                    &self.schema,
+                    &self.table,
+                    self.key.as_deref(),
+                    &self.fields,
+                    self.batch_size,
+                )
+                .await?;
+                match &checkpoint {
+                    Some(position @ Position::Chunks { .. }) => {
+                        drop(conn);
+                        self.parallel = Some(
+                            ParallelSnapshot::start(
+                                &self.pool,
+                                &snapshot,
+                                self.parallelism,
+                                Some(position),
+                                false,
+                            )
+                            .await?,
+                        );
+                    }
+                    Some(position) => snapshot.resume(position)?,
+                    None if self.parallelism > 1 => {
+                        drop(conn);
+                        self.parallel = Some(
+                            ParallelSnapshot::start(
+                                &self.pool,
+                                &snapshot,
+                                self.parallelism,
+                                None,
+                                false,
+                            )
+                            .await?,
+                        );
+                    }
+                    None => {}
+                }
+                self.snapshot = Some(snapshot);
+            }
+            Mode::Cdc { slot, publication } => {
+                let mut replication = Replication::new(
+                    slot.clone(),
+                    publication.clone(),
+                    self.schema.clone(),
+                    self.table.clone(),
+                    self.fields.clone(),
+                    self.batch_size as i32,
+                )
+                .with_key(self.key.clone());
+                replication.setup(&conn).await?;
+                if let Some(Position::Lsn { lsn }) = checkpoint {
+                    replication.resume(&conn, Lsn(lsn)).await?;
+                }
+                self.replication = Some(replication);
+            }
+            Mode::SnapshotCdc { slot, publication } => {
+                let mut replication = Replication::new(
+                    slot.clone(),
+                    publication.clone(),
+                    self.schema.clone(),
+                    self.table.clone(),
+                    self.fields.clone(),
+                    self.batch_size as i32,
+                )
+                .with_key(self.key.clone());
+                // the slot has to exist before the snapshot is taken, so that every
+                // change the snapshot misses is still in it
+                replication.setup(&conn).await?;
+                match checkpoint {
+                    Some(Position::Lsn { lsn }) => replication.resume(&conn, Lsn(lsn)).await?,
+                    _ => {
+                        let snapshot = Snapshot::plan(
+                            &conn,
+                            &self.schema,
+                            &self.table,
+                            self.key.as_deref(),
+                            &self.fields,
+                            self.batch_size,
+                        )
+                        .await?;
+                        drop(conn);
+                        let parallel = ParallelSnapshot::start(
+                            &self.pool,
+                            &snapshot,
+                            self.parallelism,
+                            None,
+                            true,
+                        )
+                        .await?;
+                        if let Some(visibility) = parallel.visibility() {
+                            replication.hand_off(visibility.clone());
+                        }
+                        self.parallel = Some(parallel);
+                    }
+                }
+                self.replication = Some(replication);
+            }
+        }
+        Ok(())
+    }
+
+    // read returns the next batch. A snapshot returns the next rows in key order
+    // until every row has been read; change data capture waits for the next
+    // committed changes. Every mode returns None on shutdown.
+    async fn read(&mut self) -> Result<Option<Batch>, Error> {
+        if crate::shutting_down(&mut self.shutdown_rx) {
+            return Ok(None);
+        }
+        if let Some(parallel) = self.parallel.as_mut() {
+            match parallel.next_batch().await? {
+                Some(records) => return Ok(Some(self.batch(records))),
+                None if self.replication.is_some() => {
+                    info!(
+                        "snapshot of {}.{} finished, streaming its changes",
+                        self.schema, self.table
+                    );
+                    self.parallel = None;
+                }
+                None => return Ok(None),
+            }
+        }
+        if let (Mode::Copy, Some(snapshot)) = (&self.mode, self.snapshot.as_mut()) {
+            let records = snapshot.next_copy_batch(&self.pool).await?;
+            if records.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some(self.batch(records)));
+        }
+
+        let conn = self.get_conn().await?;
+        if let Some(snapshot) = self.snapshot.as_mut() {
+            let records = snapshot.next_batch(&conn).await?;
+            if records.is_empty() {
+                return Ok(None);
+            }
+            return Ok(Some(self.batch(records)));
+        }
+
+        let Some(replication) = self.replication.as_mut() else {
+            return Err("postgres source read before it was opened".into());
+        };
+        loop {
+            let handing_off = !replication.handed_off();
+            let records = replication.next_batch(&conn).await?;
+            // the first empty batch past a handoff is what checkpoints it
+            if !records.is_empty() || (handing_off && replication.handed_off()) {
+                return Ok(Some(self.batch(records)));
+            }
+            tokio::select! {
+                _ = tokio::time::sleep(POLL_INTERVAL) => {}
+                _ = self.shutdown_rx.recv() => return Ok(None),
+            }
+        }
+    }
+
+    // ack moves the replication slot past delivered changes; snapshots have nothing
+    // to release
+    async fn ack(&mut self, position: &Position) -> Result<(), Error> {
+        if let (Some(_), Position::Lsn { lsn }) = (&self.replication, position) {
+            let conn = self.get_conn().await?;
+            let replication = self.replication.as_mut().expect("replication is set up");
+            replication.ack(&conn, Lsn(*lsn)).await?;
+        }
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        info!("closing postgres source {}.{}", self.schema, self.table);
+        // the pool stays open for the connector's other sources and sinks
+        self.parallel = None;
+        if let Some(mut replication) = self.replication.take() {
+            replication.stop(&*self.get_conn().await?).await?;
+        }
+        Ok(())
+    }
+}
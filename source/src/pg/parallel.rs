@@ -0,0 +1,426 @@
+use std::{
+    collections::{BTreeSet, VecDeque},
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use checkpoint::{ChunkProgress, Position};
+use deadpool_postgres::{Object, Pool};
+use record::{Error, Record};
+use tokio::{
+    sync::{mpsc, oneshot},
+    task::JoinHandle,
+};
+use tracing::{info, warn};
+
+use super::{Lsn, Snapshot, Visibility};
+
+// Chunk is a batch read from one chunk of a parallel snapshot
+struct Chunk {
+    index: usize,
+    records: Vec<Record>,
+    done: bool,
+}
+
+// ParallelSnapshot reads a table as key ranges, or chunks, cut by `bounds`.
+// Workers read the chunks concurrently, each over its own pooled connection, and
+// every connection imports the same exported snapshot so together they see the
+// table as of a single point in time. The exporting transaction stays open until
+// the snapshot is over, so a worker whose connection fails imports the snapshot
+// again on a new one and reads its chunk on from the last row it returned.
+//
+// Batches of different chunks interleave, so the position only records which
+// chunks have been read in full: a snapshot that is resumed reads the others
+// again from their start.
+pub struct ParallelSnapshot {
+    bounds: Vec<Vec<String>>,
+    finished: BTreeSet<usize>,
+    chunks: mpsc::Receiver<Result<Chunk, Error>>,
+    workers: Vec<JoinHandle<()>>,
+    // visibility is which transactions the snapshot sees, when asked for
+    visibility: Option<Visibility>,
+    // release ends the exporting transaction when it is sent or dropped
+    release: Option<oneshot::Sender<()>>,
+}
+
+impl ParallelSnapshot {
+    // start splits the table into `parallelism` chunks, or reuses the chunks of a
+    // checkpoint, and starts a worker for each chunk left to read, up to
+    // `parallelism` of them. With `visibility` set it also records which
+    // transactions the snapshot sees, to hand off to a replication slot.
+    pub async fn start(
+        pool: &Pool,
+        snapshot: &Snapshot,
+        parallelism: usize,
+        checkpoint: Option<&Position>,
+        visibility: bool,
+    ) -> Result<Self, Error> {
+        let exporter = pool.get().await?;
+        exporter
+            .batch_execute("BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY")
+            .await?;
+        let id: String = exporter
+            .query_one("SELECT pg_export_snapshot()", &[])
+            .await?
+            .get(0);
+        let visibility = if visibility {
+            let row = exporter
+                .query_one(
+                    "SELECT pg_current_snapshot()::text, pg_current_wal_lsn()::text",
+                    &[],
+                )
+                .await?;
+            let lsn: Lsn = row.get::<_, String>(1).parse()?;
+            Some(Visibility::parse(row.get(0), lsn)?)
+        } else {
+            None
+        };
+
+        let (bounds, finished) = match checkpoint {
+            Some(Position::Chunks { chunks }) => (
+                chunks.bounds.clone(),
+                chunks.finished.iter().copied().collect(),
+            ),
+            _ => (
+                snapshot.split(&exporter, parallelism).await?,
+                BTreeSet::new(),
+            ),
+        };
+        let queue = Queue::new(&bounds, &finished);
+        let pending = queue.len();
+        info!(
+            "reading {} of {} chunks with {} workers in snapshot {}",
+            pending,
+            bounds.len() + 1,
+            parallelism.min(pending),
+            id
+        );
+
+        let exported = Exported {
+            pool: pool.clone(),
+            id,
+        };
+        let mut conns = vec![];
+        for _ in 0..parallelism.min(pending) {
+            conns.push(exported.import().await?);
+        }
+        let (release, released) = oneshot::channel();
+        tokio::spawn(async move {
+            let _ = released.await;
+            let _ = exporter.batch_execute("COMMIT").await;
+        });
+
+        let (tx, chunks) = mpsc::channel(parallelism.max(1));
+        let worker = Arc::new(Worker {
+            reader: exported,
+            table: snapshot.chunk(None, None),
+            bounds: bounds.clone(),
+            queue: Mutex::new(queue),
+            tx,
+        });
+        let workers = conns
+            .into_iter()
+            .map(|conn| tokio::spawn(work(conn, worker.clone())))
+            .collect();
+
+        Ok(ParallelSnapshot {
+            bounds,
+            finished,
+            chunks,
+            workers,
+            visibility,
+            release: Some(release),
+        })
+    }
+
+    pub fn visibility(&self) -> Option<&Visibility> {
+        self.visibility.as_ref()
+    }
+
+    // position is the chunks and which of them have been read in full
+    pub fn position(&self) -> Position {
+        if self.finished.len() > self.bounds.len() {
+            return Position::Finished;
+        }
+        Position::Chunks {
+            chunks: ChunkProgress {
+                bounds: self.bounds.clone(),
+                finished: self.finished.iter().copied().collect(),
+            },
+        }
+    }
+
+    // next_batch returns the next rows any worker has read, or None once every
+    // chunk has been read. An error is that of a worker that goes on reading
+    // once it has a connection again.
+    pub async fn next_batch(&mut self) -> Result<Option<Vec<Record>>, Error> {
+        match self.chunks.recv().await {
+            Some(chunk) => {
+                let chunk = chunk?;
+                if chunk.done {
+                    self.finished.insert(chunk.index);
+                }
+                Ok(Some(chunk.records))
+            }
+            None if self.finished.len() > self.bounds.len() => {
+                self.release.take();
+                Ok(None)
+            }
+            None => Err("a snapshot worker stopped before its chunks were read".into()),
+        }
+    }
+}
+
+impl Drop for ParallelSnapshot {
+    fn drop(&mut self) {
+        for worker in &self.workers {
+            worker.abort();
+        }
+    }
+}
+
+// Queue hands out the chunks left to read, each with the key it starts after
+struct Queue(VecDeque<(usize, Option<Vec<String>>)>);
+
+impl Queue {
+    fn new(bounds: &[Vec<String>], finished: &BTreeSet<usize>) -> Self {
+        Queue(
+            (0..=bounds.len())
+                .filter(|chunk| !finished.contains(chunk))
+                .map(|chunk| (chunk, chunk.checked_sub(1).map(|i| bounds[i].clone())))
+                .collect(),
+        )
+    }
+
+    fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    fn next(&mut self) -> Option<(usize, Option<Vec<String>>)> {
+        self.0.pop_front()
+    }
+
+    // requeue puts back a chunk that was read up to `lower`, to be read on first
+    fn requeue(&mut self, chunk: usize, lower: Option<Vec<String>>) {
+        self.0.push_front((chunk, lower));
+    }
+}
+
+// Reader reads chunks over connections that imported the snapshot
+#[async_trait]
+trait Reader: Send + Sync + 'static {
+    type Conn: Send + Sync;
+    async fn import(&self) -> Result<Self::Conn, Error>;
+    async fn read(&self, conn: &Self::Conn, chunk: &mut Snapshot) -> Result<Vec<Record>, Error>;
+    // release ends the connection's transaction, which only read
+    async fn release(&self, conn: Self::Conn);
+}
+
+// Exported is a snapshot exported as `id`, read over connections of `pool`
+struct Exported {
+    pool: Pool,
+    id: String,
+}
+
+#[async_trait]
+impl Reader for Exported {
+    type Conn = Object;
+
+    async fn import(&self) -> Result<Object, Error> {
+        let client = self.pool.get().await?;
+        client
+            .batch_execute(&format!(
+                "BEGIN ISOLATION LEVEL REPEATABLE READ READ ONLY; SET TRANSACTION SNAPSHOT '{}'",
+                self.id
+            ))
+            .await?;
+        Ok(client)
+    }
+
+    async fn read(&self, conn: &Object, chunk: &mut Snapshot) -> Result<Vec<Record>, Error> {
+        chunk.next_batch(conn).await
+    }
+
+    async fn release(&self, conn: Object) {
+        let _ = conn.batch_execute("ROLLBACK").await;
+    }
+}
+
+// Worker is what the workers of a snapshot share
+struct Worker<R: Reader> {
+    reader: R,
+    table: Snapshot,
+    bounds: Vec<Vec<String>>,
+    queue: Mutex<Queue>,
+    tx: mpsc::Sender<Result<Chunk, Error>>,
+}
+
+// work reads chunks until there are none left. When a read fails it reports
+// the error, puts the chunk back from the last row it returned, and imports the
+// snapshot again on a new connection to read on.
+async fn work<R: Reader>(mut conn: R::Conn, worker: Arc<Worker<R>>) {
+    loop {
+        let failed = match read_chunks(&conn, &worker).await {
+            Ok(()) => break,
+            Err(e) => e,
+        };
+        warn!(
+            "a snapshot worker failed, reading its chunk again: {}",
+            failed
+        );
+        worker.reader.release(conn).await;
+        if worker.tx.send(Err(failed)).await.is_err() {
+            return;
+        }
+        conn = loop {
+            match worker.reader.import().await {
+                Ok(conn) => break conn,
+                Err(e) => {
+                    if worker.tx.send(Err(e)).await.is_err() {
+                        return;
+                    }
+                }
+            }
+        };
+    }
+    worker.reader.release(conn).await;
+}
+
+// read_chunks reads chunks off the queue until it is empty or the snapshot is
+// dropped
+async fn read_chunks<R: Reader>(conn: &R::Conn, worker: &Worker<R>) -> Result<(), Error> {
+    loop {
+        let next = worker.queue.lock().expect("chunk queue lock").next();
+        let Some((index, lower)) = next else {
+            return Ok(());
+        };
+        let upper = worker.bounds.get(index).cloned();
+        let mut snapshot = worker.table.chunk(lower, upper);
+        loop {
+            let records = match worker.reader.read(conn, &mut snapshot).await {
+                Ok(records) => records,
+                Err(e) => {
+                    let lower = snapshot.last_key().cloned();
+                    worker
+                        .queue
+                        .lock()
+                        .expect("chunk queue lock")
+                        .requeue(index, lower);
+                    return Err(e);
+                }
+            };
+            let done = snapshot.is_done();
+            let chunk = Chunk {
+                index,
+                records,
+                done,
+            };
+            if worker.tx.send(Ok(chunk)).await.is_err() {
+                return Ok(());
+            }
+            if done {
+                break;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg::snapshot::tests::{column, snapshot};
+
+    // Flaky reads a chunk one key at a time up to key 3, failing the reads it
+    // is told to
+    struct Flaky {
+        fail: usize,
+        reads: Mutex<Vec<Option<Vec<String>>>>,
+        imports: Mutex<usize>,
+    }
+
+    #[async_trait]
+    impl Reader for Flaky {
+        type Conn = ();
+
+        async fn import(&self) -> Result<(), Error> {
+            *self.imports.lock().unwrap() += 1;
+            Ok(())
+        }
+
+        async fn read(&self, _: &(), chunk: &mut Snapshot) -> Result<Vec<Record>, Error> {
+            let mut reads = self.reads.lock().unwrap();
+            reads.push(chunk.last_key().cloned());
+            if reads.len() == self.fail {
+                return Err("connection reset".into());
+            }
+            let key = chunk
+                .last_key()
+                .map_or(1, |key| key[0].parse::<u32>().unwrap() + 1);
+            chunk.advance(vec![key.to_string()], key == 3);
+            Ok(vec![])
+        }
+
+        async fn release(&self, _: ()) {}
+    }
+
+    #[tokio::test]
+    async fn reads_a_failed_chunk_on_from_its_last_key() {
+        let (tx, mut chunks) = mpsc::channel(8);
+        let worker = Arc::new(Worker {
+            reader: Flaky {
+                fail: 2,
+                reads: Mutex::new(vec![]),
+                imports: Mutex::new(0),
+            },
+            table: snapshot(vec![column("id", "integer")]),
+            bounds: vec![],
+            queue: Mutex::new(Queue::new(&[], &BTreeSet::new())),
+            tx,
+        });
+        work((), worker.clone()).await;
+
+        let mut results = vec![];
+        while let Ok(chunk) = chunks.try_recv() {
+            results.push(chunk.map(|chunk| chunk.done).map_err(|e| e.to_string()));
+        }
+        assert_eq!(
+            results,
+            vec![
+                Ok(false),
+                Err("connection reset".to_string()),
+                Ok(false),
+                Ok(true)
+            ]
+        );
+        let key = |key: &str| Some(vec![key.to_string()]);
+        assert_eq!(
+            *worker.reader.reads.lock().unwrap(),
+            vec![None, key("1"), key("1"), key("2")]
+        );
+        assert_eq!(*worker.reader.imports.lock().unwrap(), 1);
+    }
+
+    #[test]
+    fn finishes_once_every_chunk_is_read() {
+        let (_tx, chunks) = mpsc::channel(1);
+        let mut parallel = ParallelSnapshot {
+            bounds: vec![vec!["100".to_string()], vec!["200".to_string()]],
+            finished: BTreeSet::from([2, 0]),
+            chunks,
+            workers: vec![],
+            visibility: None,
+            release: None,
+        };
+        assert_eq!(
+            parallel.position(),
+            Position::Chunks {
+                chunks: ChunkProgress {
+                    bounds: vec![vec!["100".to_string()], vec!["200".to_string()]],
+                    finished: vec![0, 2],
+                },
+            }
+        );
+        parallel.finished.insert(1);
+        assert_eq!(parallel.position(), Position::Finished);
+    }
+}
@@ -0,0 +1,475 @@
+use std::fmt;
+
+use config::FieldType;
+
+// Lsn is a position in the Postgres write-ahead log
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Default)]
+pub struct Lsn(pub u64);
+
+impl fmt::Display for Lsn {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{:X}/{:X}", self.0 >> 32, self.0 & 0xFFFF_FFFF)
+    }
+}
+
+impl std::str::FromStr for Lsn {
+    type Err = String;
+
+    fn from_str(text: &str) -> Result<Self, Self::Err> {
+        let invalid = || format!("invalid LSN `{}`", text);
+        let (high, low) = text.split_once('/').ok_or_else(invalid)?;
+        let high = u64::from_str_radix(high, 16).map_err(|_| invalid())?;
+        let low = u64::from_str_radix(low, 16).map_err(|_| invalid())?;
+        Ok(Lsn(high << 32 | low))
+    }
+}
+
+// RelationColumn describes one column of a relation message
+#[derive(Debug, Clone, PartialEq)]
+pub struct RelationColumn {
+    pub key: bool,
+    pub name: String,
+    pub type_oid: u32,
+    pub type_modifier: i32,
+}
+
+// Relation describes a table before any change to it is sent
+#[derive(Debug, Clone, PartialEq)]
+pub struct Relation {
+    pub oid: u32,
+    pub namespace: String,
+    pub name: String,
+    pub replica_identity: u8,
+    pub columns: Vec<RelationColumn>,
+}
+
+// TupleValue is one column of a row image
+#[derive(Debug, Clone, PartialEq)]
+pub enum TupleValue {
+    Null,
+    // UnchangedToast is a large value the update did not touch, so it is not sent
+    UnchangedToast,
+    Text(String),
+}
+
+// OldTuple is the row image an update or delete carries for the old row:
+// only the replica identity columns, or the whole row with `REPLICA IDENTITY FULL`
+#[derive(Debug, Clone, PartialEq)]
+pub enum OldTuple {
+    Key(Vec<TupleValue>),
+    Full(Vec<TupleValue>),
+}
+
+// Message is one decoded message of the pgoutput protocol, version 1
+#[derive(Debug, Clone, PartialEq)]
+pub enum Message {
+    Begin {
+        final_lsn: Lsn,
+        timestamp: i64,
+        xid: u32,
+    },
+    Commit {
+        commit_lsn: Lsn,
+        end_lsn: Lsn,
+        timestamp: i64,
+    },
+    Origin {
+        lsn: Lsn,
+        name: String,
+    },
+    Relation(Relation),
+    Type {
+        oid: u32,
+        namespace: String,
+        name: String,
+    },
+    Insert {
+        relation: u32,
+        new: Vec<TupleValue>,
+    },
+    Update {
+        relation: u32,
+        old: Option<OldTuple>,
+        new: Vec<TupleValue>,
+    },
+    Delete {
+        relation: u32,
+        old: OldTuple,
+    },
+    Truncate {
+        relations: Vec<u32>,
+        options: u8,
+    },
+}
+
+impl Message {
+    // decode parses one message as returned by `pg_logical_slot_peek_binary_changes`
+    pub fn decode(data: &[u8]) -> Result<Message, String> {
+        let mut reader = Reader { data, position: 0 };
+        let message = match reader.u8()? {
+            b'B' => Message::Begin {
+                final_lsn: Lsn(reader.u64()?),
+                timestamp: reader.i64()?,
+                xid: reader.u32()?,
+            },
+            b'C' => {
+                reader.u8()?; // flags, currently unused
+                Message::Commit {
+                    commit_lsn: Lsn(reader.u64()?),
+                    end_lsn: Lsn(reader.u64()?),
+                    timestamp: reader.i64()?,
+                }
+            }
+            b'O' => Message::Origin {
+                lsn: Lsn(reader.u64()?),
+                name: reader.string()?,
+            },
+            b'R' => {
+                let oid = reader.u32()?;
+                let namespace = reader.string()?;
+                let name = reader.string()?;
+                let replica_identity = reader.u8()?;
+                let count = reader.u16()?;
+                let mut columns = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    columns.push(RelationColumn {
+                        key: reader.u8()? & 1 == 1,
+                        name: reader.string()?,
+                        type_oid: reader.u32()?,
+                        type_modifier: reader.i32()?,
+                    });
+                }
+                Message::Relation(Relation {
+                    oid,
+                    namespace,
+                    name,
+                    replica_identity,
+                    columns,
+                })
+            }
+            b'Y' => Message::Type {
+                oid: reader.u32()?,
+                namespace: reader.string()?,
+                name: reader.string()?,
+            },
+            b'I' => {
+                let relation = reader.u32()?;
+                reader.expect(b'N')?;
+                Message::Insert {
+                    relation,
+                    new: reader.tuple()?,
+                }
+            }
+            b'U' => {
+                let relation = reader.u32()?;
+                let (old, new) = match reader.u8()? {
+                    b'K' => {
+                        let old = OldTuple::Key(reader.tuple()?);
+                        reader.expect(b'N')?;
+                        (Some(old), reader.tuple()?)
+                    }
+                    b'O' => {
+                        let old = OldTuple::Full(reader.tuple()?);
+                        reader.expect(b'N')?;
+                        (Some(old), reader.tuple()?)
+                    }
+                    b'N' => (None, reader.tuple()?),
+                    other => {
+                        return Err(format!("unexpected update tuple tag `{}`", other as char))
+                    }
+                };
+                Message::Update { relation, old, new }
+            }
+            b'D' => {
+                let relation = reader.u32()?;
+                let old = match reader.u8()? {
+                    b'K' => OldTuple::Key(reader.tuple()?),
+                    b'O' => OldTuple::Full(reader.tuple()?),
+                    other => {
+                        return Err(format!("unexpected delete tuple tag `{}`", other as char))
+                    }
+                };
+                Message::Delete { relation, old }
+            }
+            b'T' => {
+                let count = reader.u32()?;
+                let options = reader.u8()?;
+                let mut relations = Vec::with_capacity(count as usize);
+                for _ in 0..count {
+                    relations.push(reader.u32()?);
+                }
+                Message::Truncate { relations, options }
+            }
+            other => return Err(format!("unknown pgoutput message `{}`", other as char)),
+        };
+        Ok(message)
+    }
+}
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self.position + len;
+        if end > self.data.len() {
+            return Err(format!(
+                "pgoutput message truncated at byte {} of {}",
+                self.position,
+                self.data.len()
+            ));
+        }
+        let bytes = &self.data[self.position..end];
+        self.position = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, String> {
+        Ok(u16::from_be_bytes(self.take(2)?.try_into().unwrap()))
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(i32::from_be_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn u64(&mut self) -> Result<u64, String> {
+        Ok(u64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn i64(&mut self) -> Result<i64, String> {
+        Ok(i64::from_be_bytes(self.take(8)?.try_into().unwrap()))
+    }
+
+    fn expect(&mut self, tag: u8) -> Result<(), String> {
+        match self.u8()? {
+            found if found == tag => Ok(()),
+            found => Err(format!(
+                "expected pgoutput tag `{}`, found `{}`",
+                tag as char, found as char
+            )),
+        }
+    }
+
+    // string reads a NUL-terminated string
+    fn string(&mut self) -> Result<String, String> {
+        let rest = &self.data[self.position..];
+        let len = rest
+            .iter()
+            .position(|b| *b == 0)
+            .ok_or("unterminated string in pgoutput message")?;
+        let text = String::from_utf8_lossy(&rest[..len]).into_owned();
+        self.position += len + 1;
+        Ok(text)
+    }
+
+    fn tuple(&mut self) -> Result<Vec<TupleValue>, String> {
+        let count = self.u16()?;
+        let mut values = Vec::with_capacity(count as usize);
+        for _ in 0..count {
+            let value = match self.u8()? {
+                b'n' => TupleValue::Null,
+                b'u' => TupleValue::UnchangedToast,
+                b't' => {
+                    let len = self.u32()? as usize;
+                    TupleValue::Text(String::from_utf8_lossy(self.take(len)?).into_owned())
+                }
+                other => return Err(format!("unexpected tuple value kind `{}`", other as char)),
+            };
+            values.push(value);
+        }
+        Ok(values)
+    }
+}
+
+// oid_field_type maps the type of a relation column to a field type
+pub fn oid_field_type(oid: u32) -> FieldType {
+    match oid {
+        16 => FieldType::Boolean,
+        17 => FieldType::Bytes,
+        20 => FieldType::BigInt,
+        21 | 23 => FieldType::Int,
+        114 | 3802 => FieldType::Json,
+        700 | 701 => FieldType::Number,
+        1082 => FieldType::Date,
+        1114 | 1184 => FieldType::Timestamp,
+        1700 => FieldType::Decimal,
+        2950 => FieldType::Uuid,
+        _ => FieldType::String,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn string(buffer: &mut Vec<u8>, text: &str) {
+        buffer.extend_from_slice(text.as_bytes());
+        buffer.push(0);
+    }
+
+    fn tuple(buffer: &mut Vec<u8>, values: &[Option<&str>]) {
+        buffer.extend_from_slice(&(values.len() as u16).to_be_bytes());
+        for value in values {
+            match value {
+                Some(text) => {
+                    buffer.push(b't');
+                    buffer.extend_from_slice(&(text.len() as u32).to_be_bytes());
+                    buffer.extend_from_slice(text.as_bytes());
+                }
+                None => buffer.push(b'n'),
+            }
+        }
+    }
+
+    fn text(value: &str) -> TupleValue {
+        TupleValue::Text(value.to_string())
+    }
+
+    #[test]
+    fn lsn_round_trips() {
+        let lsn: Lsn = "16/B374D848".parse().unwrap();
+        assert_eq!(lsn, Lsn(0x16_B374_D848));
+        assert_eq!(lsn.to_string(), "16/B374D848");
+        assert!("16B374D848".parse::<Lsn>().is_err());
+    }
+
+    #[test]
+    fn decodes_transaction_boundaries() {
+        let mut begin = vec![b'B'];
+        begin.extend_from_slice(&0x0100_0000_0028u64.to_be_bytes());
+        begin.extend_from_slice(&42i64.to_be_bytes());
+        begin.extend_from_slice(&731u32.to_be_bytes());
+        assert_eq!(
+            Message::decode(&begin),
+            Ok(Message::Begin {
+                final_lsn: Lsn(0x0100_0000_0028),
+                timestamp: 42,
+                xid: 731,
+            })
+        );
+
+        let mut commit = vec![b'C', 0];
+        commit.extend_from_slice(&0x28u64.to_be_bytes());
+        commit.extend_from_slice(&0x58u64.to_be_bytes());
+        commit.extend_from_slice(&42i64.to_be_bytes());
+        assert_eq!(
+            Message::decode(&commit),
+            Ok(Message::Commit {
+                commit_lsn: Lsn(0x28),
+                end_lsn: Lsn(0x58),
+                timestamp: 42,
+            })
+        );
+        assert!(Message::decode(&commit[..10]).is_err());
+    }
+
+    #[test]
+    fn decodes_relations() {
+        let mut relation = vec![b'R'];
+        relation.extend_from_slice(&16384u32.to_be_bytes());
+        string(&mut relation, "public");
+        string(&mut relation, "orders");
+        relation.push(b'd');
+        relation.extend_from_slice(&2u16.to_be_bytes());
+        relation.push(1);
+        string(&mut relation, "id");
+        relation.extend_from_slice(&23u32.to_be_bytes());
+        relation.extend_from_slice(&(-1i32).to_be_bytes());
+        relation.push(0);
+        string(&mut relation, "total");
+        relation.extend_from_slice(&1700u32.to_be_bytes());
+        relation.extend_from_slice(&655366i32.to_be_bytes());
+
+        let Ok(Message::Relation(relation)) = Message::decode(&relation) else {
+            panic!("expected a relation message");
+        };
+        assert_eq!(relation.oid, 16384);
+        assert_eq!(relation.namespace, "public");
+        assert_eq!(relation.name, "orders");
+        assert_eq!(
+            relation.columns[0],
+            RelationColumn {
+                key: true,
+                name: "id".to_string(),
+                type_oid: 23,
+                type_modifier: -1,
+            }
+        );
+        assert!(!relation.columns[1].key);
+    }
+
+    #[test]
+    fn decodes_row_changes() {
+        let mut insert = vec![b'I'];
+        insert.extend_from_slice(&16384u32.to_be_bytes());
+        insert.push(b'N');
+        tuple(&mut insert, &[Some("1"), None]);
+        assert_eq!(
+            Message::decode(&insert),
+            Ok(Message::Insert {
+                relation: 16384,
+                new: vec![text("1"), TupleValue::Null],
+            })
+        );
+
+        let mut update = vec![b'U'];
+        update.extend_from_slice(&16384u32.to_be_bytes());
+        update.push(b'O');
+        tuple(&mut update, &[Some("1"), Some("9.50")]);
+        update.push(b'N');
+        tuple(&mut update, &[Some("1"), Some("12.00")]);
+        assert_eq!(
+            Message::decode(&update),
+            Ok(Message::Update {
+                relation: 16384,
+                old: Some(OldTuple::Full(vec![text("1"), text("9.50")])),
+                new: vec![text("1"), text("12.00")],
+            })
+        );
+
+        let mut delete = vec![b'D'];
+        delete.extend_from_slice(&16384u32.to_be_bytes());
+        delete.push(b'K');
+        tuple(&mut delete, &[Some("1"), None]);
+        assert_eq!(
+            Message::decode(&delete),
+            Ok(Message::Delete {
+                relation: 16384,
+                old: OldTuple::Key(vec![text("1"), TupleValue::Null]),
+            })
+        );
+
+        let mut unchanged = vec![b'U'];
+        unchanged.extend_from_slice(&16384u32.to_be_bytes());
+        unchanged.push(b'N');
+        unchanged.extend_from_slice(&1u16.to_be_bytes());
+        unchanged.push(b'u');
+        assert_eq!(
+            Message::decode(&unchanged),
+            Ok(Message::Update {
+                relation: 16384,
+                old: None,
+                new: vec![TupleValue::UnchangedToast],
+            })
+        );
+    }
+
+    #[test]
+    fn rejects_unknown_messages() {
+        assert_eq!(
+            Message::decode(b"Z"),
+            Err("unknown pgoutput message `Z`".to_string())
+        );
+        assert!(Message::decode(b"").is_err());
+    }
+}
@@ -0,0 +1,794 @@
+use std::collections::{BTreeMap, HashMap};
+
+use chrono::{DateTime, Utc};
+use config::Field;
+use record::{Error, Metadata, Op, Record, Row, Value};
+use tokio_postgres::Client;
+use tracing::{info, warn};
+
+use super::pgoutput::{oid_field_type, Lsn, Message, OldTuple, Relation, TupleValue};
+use super::snapshot::{quote_ident, PRIMARY_KEY_QUERY};
+
+// microseconds between the Unix epoch and the Postgres epoch, 2000-01-01
+const POSTGRES_EPOCH_MICROS: i64 = 946_684_800_000_000;
+
+// READ_QUERY consumes the next changes from the cursor slot. Postgres stops at the
+// end of the transaction that reaches the limit, so transactions come whole.
+const READ_QUERY: &str = "SELECT lsn::text, data \
+     FROM pg_logical_slot_get_binary_changes($1, NULL, $2, \
+     'proto_version', '1', 'publication_names', $3)";
+
+// COPY_QUERY starts a cursor slot where the slot was last acknowledged
+const COPY_QUERY: &str = "SELECT pg_copy_logical_replication_slot($1, $2)";
+
+const DROP_QUERY: &str = "SELECT pg_drop_replication_slot(slot_name) \
+     FROM pg_replication_slots WHERE slot_name = $1";
+
+// ADVANCE_QUERY moves the slot forward, and never backwards, which Postgres rejects
+const ADVANCE_QUERY: &str = "SELECT pg_replication_slot_advance(slot_name, $2::text::pg_lsn) \
+     FROM pg_replication_slots \
+     WHERE slot_name = $1 AND confirmed_flush_lsn < $2::text::pg_lsn";
+
+// Replication streams the changes to one table out of a logical replication slot,
+// decoded from the `pgoutput` plugin. The slot only moves past changes once they
+// are acknowledged, so changes that were read but never delivered are read again
+// after a restart. Reading goes through a copy of the slot, its cursor, which is
+// consumed as changes are read so every read starts where the last one ended.
+// The cursor is copied again from the slot on open and after a failed read, and
+// transactions that were already read are skipped.
+#[derive(Debug)]
+pub struct Replication {
+    slot: String,
+    // cursor is the slot changes are read from, once it has been copied
+    cursor: String,
+    cursor_started: bool,
+    publication: String,
+    schema: String,
+    table: String,
+    fields: Vec<Field>,
+    // key names the row's identifying columns when the relation does not say,
+    // because replica identity full flags every column as part of the key
+    key: Option<Vec<String>>,
+    batch_size: i32,
+    relations: HashMap<u32, Relation>,
+    transaction: Option<Transaction>,
+    // read is the end of the last transaction decoded, delivered the end of the
+    // last batch returned, and acked how far the slot has been moved
+    read: Lsn,
+    delivered: Lsn,
+    acked: Lsn,
+    // handoff is the snapshot of the table taken after the slot was created, until
+    // the stream is past every transaction it may have seen
+    handoff: Option<Visibility>,
+}
+
+// Transaction is the transaction being decoded
+#[derive(Debug)]
+struct Transaction {
+    xid: u32,
+    commit_lsn: Lsn,
+    commit_time: Option<DateTime<Utc>>,
+    // skip is set for transactions an earlier batch already returned
+    skip: bool,
+    // snapshotted is set for transactions the handoff snapshot already holds
+    snapshotted: bool,
+}
+
+// Visibility tells which transactions a snapshot sees, as printed by
+// `pg_current_snapshot()`, with `lsn` a WAL position read once it was taken: a
+// transaction that commits after `lsn` is never visible to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct Visibility {
+    xmax: u64,
+    xip: Vec<u64>,
+    lsn: Lsn,
+}
+
+impl Visibility {
+    pub fn parse(snapshot: &str, lsn: Lsn) -> Result<Self, Error> {
+        let invalid = || format!("invalid snapshot `{}`", snapshot);
+        let mut parts = snapshot.split(':');
+        let (Some(_xmin), Some(xmax), Some(xip), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            return Err(invalid().into());
+        };
+        Ok(Visibility {
+            xmax: xmax.parse().map_err(|_| invalid())?,
+            xip: xip
+                .split(',')
+                .filter(|xid| !xid.is_empty())
+                .map(|xid| xid.parse().map_err(|_| invalid()))
+                .collect::<Result<_, _>>()?,
+            lsn,
+        })
+    }
+
+    // sees tells whether a transaction committed at `commit_lsn` was visible to the
+    // snapshot. The WAL carries 32 bit xids, which compare modulo 2^32 like Postgres
+    // compares them.
+    pub fn sees(&self, xid: u32, commit_lsn: Lsn) -> bool {
+        commit_lsn < self.lsn
+            && (xid.wrapping_sub(self.xmax as u32) as i32) < 0
+            && !self.xip.iter().any(|running| *running as u32 == xid)
+    }
+}
+
+impl Replication {
+    pub fn new(
+        slot: String,
+        publication: String,
+        schema: String,
+        table: String,
+        fields: Vec<Field>,
+        batch_size: i32,
+    ) -> Self {
+        Replication {
+            cursor: cursor_name(&slot),
+            cursor_started: false,
+            slot,
+            publication,
+            schema,
+            table,
+            fields,
+            key: None,
+            batch_size,
+            relations: HashMap::new(),
+            transaction: None,
+            read: Lsn::default(),
+            delivered: Lsn::default(),
+            acked: Lsn::default(),
+            handoff: None,
+        }
+    }
+
+    // with_key sets the key columns, overriding the table's primary key
+    pub fn with_key(mut self, key: Option<Vec<String>>) -> Self {
+        self.key = key;
+        self
+    }
+
+    // setup creates the publication and the replication slot, reusing them if a
+    // previous run already did
+    pub async fn setup(&mut self, client: &Client) -> Result<(), Error> {
+        if self.key.is_none() {
+            let relation = format!("{}.{}", quote_ident(&self.schema), quote_ident(&self.table));
+            let key: Vec<String> = client
+                .query(PRIMARY_KEY_QUERY, &[&relation])
+                .await?
+                .iter()
+                .map(|row| row.get(0))
+                .collect();
+            self.key = (!key.is_empty()).then_some(key);
+        }
+
+        let publication = client
+            .query_opt(
+                "SELECT 1 FROM pg_publication WHERE pubname = $1",
+                &[&self.publication],
+            )
+            .await?;
+        if publication.is_none() {
+            client
+                .batch_execute(&format!(
+                    "CREATE PUBLICATION {} FOR TABLE {}.{}",
+                    quote_ident(&self.publication),
+                    quote_ident(&self.schema),
+                    quote_ident(&self.table)
+                ))
+                .await?;
+        }
+
+        let slot = client
+            .query_opt(
+                "SELECT plugin FROM pg_replication_slots WHERE slot_name = $1",
+                &[&self.slot],
+            )
+            .await?;
+        match slot {
+            Some(row) => {
+                let plugin: Option<String> = row.get(0);
+                if plugin.as_deref() != Some("pgoutput") {
+                    return Err(format!(
+                        "replication slot `{}` does not use the pgoutput plugin",
+                        self.slot
+                    )
+                    .into());
+                }
+            }
+            None => {
+                let lsn: String = client
+                    .query_one(
+                        "SELECT lsn::text FROM pg_create_logical_replication_slot($1, 'pgoutput')",
+                        &[&self.slot],
+                    )
+                    .await?
+                    .get(0);
+                info!("created replication slot `{}` at {}", self.slot, lsn);
+            }
+        }
+        Ok(())
+    }
+
+    // position is the end of the last transaction returned
+    pub fn position(&self) -> Lsn {
+        self.read
+    }
+
+    // hand_off streams on from a snapshot of the table taken after the slot was
+    // created, skipping the transactions it already holds
+    pub fn hand_off(&mut self, snapshot: Visibility) {
+        self.handoff = Some(snapshot);
+    }
+
+    // handed_off tells whether the stream is past every transaction the handoff
+    // snapshot may hold, so its position no longer depends on the snapshot
+    pub fn handed_off(&self) -> bool {
+        self.handoff.is_none()
+    }
+
+    // resume continues after `lsn`, moving the slot there if it is behind
+    pub async fn resume(&mut self, client: &Client, lsn: Lsn) -> Result<(), Error> {
+        self.read = lsn;
+        self.delivered = lsn;
+        self.ack(client, lsn).await
+    }
+
+    // ack lets the slot release everything up to `lsn`
+    pub async fn ack(&mut self, client: &Client, lsn: Lsn) -> Result<(), Error> {
+        client
+            .query(ADVANCE_QUERY, &[&self.slot, &lsn.to_string()])
+            .await?;
+        self.acked = self.acked.max(lsn);
+        Ok(())
+    }
+
+    // next_batch returns the changes committed after the last batch, made of whole
+    // transactions. The batch is empty when nothing new has been committed.
+    pub async fn next_batch(&mut self, client: &Client) -> Result<Vec<Record>, Error> {
+        loop {
+            if !self.cursor_started {
+                client.query(DROP_QUERY, &[&self.cursor]).await?;
+                client
+                    .query(COPY_QUERY, &[&self.slot, &self.cursor])
+                    .await?;
+                self.cursor_started = true;
+            }
+            let rows = match client
+                .query(
+                    READ_QUERY,
+                    &[&self.cursor, &self.batch_size, &self.publication],
+                )
+                .await
+            {
+                Ok(rows) => rows,
+                Err(e) => {
+                    // the cursor may have moved past changes that were not returned
+                    self.cursor_started = false;
+                    return Err(e.into());
+                }
+            };
+            let read = rows.len();
+            let records = self.decode_batch(rows.iter().map(|row| {
+                let lsn: Lsn = row.get::<_, String>(0).parse()?;
+                Ok((lsn, Message::decode(row.get(1))?))
+            }))?;
+            if !records.is_empty() {
+                return Ok(records);
+            }
+            if (read as i32) < self.batch_size {
+                // the stream has caught up, so it holds every transaction the handoff
+                // snapshot may have seen
+                self.handoff = None;
+                // only transactions to other tables: once every batch is acknowledged
+                // the slot can skip them without waiting for the next change
+                if self.acked >= self.delivered && self.read > self.acked {
+                    self.ack(client, self.read).await?;
+                }
+                return Ok(vec![]);
+            }
+        }
+    }
+
+    // decode_batch decodes the changes read from the cursor. When one cannot be
+    // decoded none of them are returned, so the decoder goes back to where it was
+    // and the cursor is copied again to read them anew.
+    fn decode_batch(
+        &mut self,
+        mut changes: impl Iterator<Item = Result<(Lsn, Message), Error>>,
+    ) -> Result<Vec<Record>, Error> {
+        let (read, handoff) = (self.read, self.handoff.clone());
+        let mut records = vec![];
+        let decoded = changes.try_for_each(|change| {
+            change.and_then(|(lsn, message)| self.decode(lsn, message, &mut records))
+        });
+        if let Err(e) = decoded {
+            self.read = read;
+            self.handoff = handoff;
+            self.transaction = None;
+            self.cursor_started = false;
+            return Err(e);
+        }
+        if !records.is_empty() {
+            self.delivered = self.read;
+        }
+        Ok(records)
+    }
+
+    // stop drops the cursor, which the next run copies anew
+    pub async fn stop(&mut self, client: &Client) -> Result<(), Error> {
+        client.query(DROP_QUERY, &[&self.cursor]).await?;
+        self.cursor_started = false;
+        Ok(())
+    }
+
+    fn decode(
+        &mut self,
+        lsn: Lsn,
+        message: Message,
+        records: &mut Vec<Record>,
+    ) -> Result<(), Error> {
+        match message {
+            Message::Begin {
+                final_lsn,
+                timestamp,
+                xid,
+            } => {
+                if self.handoff.as_ref().is_some_and(|h| final_lsn >= h.lsn) {
+                    self.handoff = None;
+                }
+                self.transaction = Some(Transaction {
+                    xid,
+                    commit_lsn: final_lsn,
+                    commit_time: DateTime::from_timestamp_micros(timestamp + POSTGRES_EPOCH_MICROS),
+                    skip: final_lsn < self.read,
+                    snapshotted: self
+                        .handoff
+                        .as_ref()
+                        .is_some_and(|h| h.sees(xid, final_lsn)),
+                });
+            }
+            Message::Commit { end_lsn, .. } => {
+                let Some(transaction) = self.transaction.take() else {
+                    return Err("commit without a transaction".into());
+                };
+                // a transaction that never touched the table returns nothing, but its
+                // end still counts as read so the slot can move past it
+                if !transaction.skip {
+                    self.read = end_lsn;
+                }
+            }
+            Message::Relation(relation) => {
+                self.relations.insert(relation.oid, relation);
+            }
+            Message::Insert { relation, new } => {
+                if let Some(relation) = self.watched(relation)? {
+                    let after = self.image(relation, &new, false)?;
+                    records.push(self.change(relation, Op::Insert, lsn, None, Some(after)));
+                }
+            }
+            Message::Update { relation, old, new } => {
+                if let Some(relation) = self.watched(relation)? {
+                    let before = match &old {
+                        Some(old) => Some(self.old_image(relation, old)?),
+                        None => None,
+                    };
+                    let after = self.image(relation, &new, false)?;
+                    records.push(self.change(relation, Op::Update, lsn, before, Some(after)));
+                }
+            }
+            Message::Delete { relation, old } => {
+                if let Some(relation) = self.watched(relation)? {
+                    let before = self.old_image(relation, &old)?;
+                    records.push(self.change(relation, Op::Delete, lsn, Some(before), None));
+                }
+            }
+            Message::Truncate { relations, .. } => {
+                for relation in relations {
+                    if self.watched(relation)?.is_some() {
+                        warn!(
+                            "{}.{} was truncated, the rows it held are not replicated as deletes",
+                            self.schema, self.table
+                        );
+                    }
+                }
+            }
+            Message::Origin { .. } | Message::Type { .. } => {}
+        }
+        Ok(())
+    }
+
+    // watched returns the relation a change applies to, if it is the replicated table
+    // and the change has not been returned or snapshotted before
+    fn watched(&self, oid: u32) -> Result<Option<&Relation>, Error> {
+        if self
+            .transaction
+            .as_ref()
+            .is_some_and(|t| t.skip || t.snapshotted)
+        {
+            return Ok(None);
+        }
+        let relation = self
+            .relations
+            .get(&oid)
+            .ok_or_else(|| format!("change to relation {} before its description", oid))?;
+        Ok((relation.namespace == self.schema && relation.name == self.table).then_some(relation))
+    }
+
+    fn change(
+        &self,
+        relation: &Relation,
+        op: Op,
+        lsn: Lsn,
+        before: Option<Row>,
+        after: Option<Row>,
+    ) -> Record {
+        let transaction = self.transaction.as_ref();
+        let mut attributes = BTreeMap::from([("lsn".to_string(), lsn.to_string())]);
+        if let Some(transaction) = transaction {
+            attributes.insert("commit_lsn".to_string(), transaction.commit_lsn.to_string());
+        }
+        Record {
+            op,
+            key: match &self.key {
+                Some(key) if relation.replica_identity == b'f' => key.clone(),
+                _ => relation
+                    .columns
+                    .iter()
+                    .filter(|c| c.key)
+                    .map(|c| c.name.clone())
+                    .collect(),
+            },
+            before,
+            after,
+            metadata: Metadata {
+                connector: "postgres".to_string(),
+                schema: Some(self.schema.clone()),
+                table: Some(self.table.clone()),
+                transaction: transaction.map(|t| t.xid.to_string()),
+                attributes,
+                ..Metadata::default()
+            },
+            event_time: transaction.and_then(|t| t.commit_time),
+        }
+    }
+
+    fn old_image(&self, relation: &Relation, old: &OldTuple) -> Result<Row, Error> {
+        match old {
+            OldTuple::Key(values) => self.image(relation, values, true),
+            OldTuple::Full(values) => self.image(relation, values, false),
+        }
+    }
+
+    // image types a row image by the configured fields, or by the column types when
+    // no fields are configured. Unchanged TOAST values are left out, since the
+    // change does not carry them.
+    fn image(
+        &self,
+        relation: &Relation,
+        values: &[TupleValue],
+        key_only: bool,
+    ) -> Result<Row, Error> {
+        let mut image = Row::new();
+        for (column, value) in relation.columns.iter().zip(values) {
+            if key_only && !column.key {
+                continue;
+            }
+            let field = if self.fields.is_empty() {
+                Field {
+                    name: column.name.clone(),
+                    field_type: oid_field_type(column.type_oid),
+                    nullable: true,
+                }
+            } else {
+                match self.fields.iter().find(|f| f.name == column.name) {
+                    Some(field) => field.clone(),
+                    None => continue,
+                }
+            };
+            let value = match value {
+                TupleValue::Null => Value::from_field(&field, None)?,
+                TupleValue::Text(text) => Value::from_field(&field, Some(text))?,
+                TupleValue::UnchangedToast => continue,
+            };
+            image.insert(column.name.clone(), value);
+        }
+        Ok(image)
+    }
+}
+
+// slot_name turns a table name into a valid default slot or publication name
+pub fn slot_name(table: &str) -> String {
+    let name: String = table
+        .to_lowercase()
+        .chars()
+        .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+        .collect();
+    format!("fust_{}", name)
+}
+
+// cursor_name is the name of the slot a slot is read through, within the 63
+// characters slot names may have
+fn cursor_name(slot: &str) -> String {
+    let slot: String = slot.chars().take(56).collect();
+    format!("{}_cursor", slot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::pg::pgoutput::RelationColumn;
+
+    fn replication() -> Replication {
+        let mut replication = Replication::new(
+            "fust_orders".to_string(),
+            "fust_orders".to_string(),
+            "public".to_string(),
+            "orders".to_string(),
+            vec![],
+            100,
+        );
+        for (oid, name) in [(1, "orders"), (2, "customers")] {
+            replication.relations.insert(
+                oid,
+                Relation {
+                    oid,
+                    namespace: "public".to_string(),
+                    name: name.to_string(),
+                    replica_identity: b'd',
+                    columns: vec![
+                        RelationColumn {
+                            key: true,
+                            name: "id".to_string(),
+                            type_oid: 23,
+                            type_modifier: -1,
+                        },
+                        RelationColumn {
+                            key: false,
+                            name: "note".to_string(),
+                            type_oid: 25,
+                            type_modifier: -1,
+                        },
+                    ],
+                },
+            );
+        }
+        replication
+    }
+
+    fn text(value: &str) -> TupleValue {
+        TupleValue::Text(value.to_string())
+    }
+
+    fn decode_all(replication: &mut Replication, messages: Vec<Message>) -> Vec<Record> {
+        let mut records = vec![];
+        for (i, message) in messages.into_iter().enumerate() {
+            replication
+                .decode(Lsn(0x100 + i as u64), message, &mut records)
+                .unwrap();
+        }
+        records
+    }
+
+    fn begin(xid: u32) -> Message {
+        Message::Begin {
+            final_lsn: Lsn(0x200),
+            timestamp: 0,
+            xid,
+        }
+    }
+
+    fn commit() -> Message {
+        Message::Commit {
+            commit_lsn: Lsn(0x200),
+            end_lsn: Lsn(0x228),
+            timestamp: 0,
+        }
+    }
+
+    fn row(columns: &[(&str, Value)]) -> Row {
+        columns
+            .iter()
+            .map(|(name, value)| (name.to_string(), value.clone()))
+            .collect()
+    }
+
+    #[test]
+    fn emits_changes_with_images() {
+        let mut replication = replication();
+        let records = decode_all(
+            &mut replication,
+            vec![
+                begin(7),
+                Message::Update {
+                    relation: 1,
+                    old: Some(OldTuple::Key(vec![text("1"), TupleValue::Null])),
+                    new: vec![text("2"), TupleValue::UnchangedToast],
+                },
+                Message::Delete {
+                    relation: 1,
+                    old: OldTuple::Full(vec![text("3"), TupleValue::Null]),
+                },
+                commit(),
+            ],
+        );
+
+        assert_eq!(records.len(), 2);
+        let update = &records[0];
+        assert_eq!(update.op, Op::Update);
+        assert_eq!(update.key, vec!["id"]);
+        assert_eq!(update.before, Some(row(&[("id", Value::Int(1))])));
+        assert_eq!(update.after, Some(row(&[("id", Value::Int(2))])));
+        assert_eq!(update.metadata.transaction.as_deref(), Some("7"));
+        assert_eq!(update.metadata.attributes["lsn"], "0/101");
+        assert_eq!(update.metadata.attributes["commit_lsn"], "0/200");
+        assert_eq!(
+            update.event_time.map(|t| t.to_rfc3339()).as_deref(),
+            Some("2000-01-01T00:00:00+00:00")
+        );
+
+        let delete = &records[1];
+        assert_eq!(delete.op, Op::Delete);
+        assert_eq!(
+            delete.before,
+            Some(row(&[("id", Value::Int(3)), ("note", Value::Null)]))
+        );
+        assert_eq!(delete.after, None);
+        assert_eq!(replication.position(), Lsn(0x228));
+    }
+
+    #[test]
+    fn keys_full_identity_tables_by_primary_key() {
+        let mut replication = replication().with_key(Some(vec!["id".to_string()]));
+        let relation = replication.relations.get_mut(&1).unwrap();
+        relation.replica_identity = b'f';
+        for column in &mut relation.columns {
+            column.key = true;
+        }
+        let records = decode_all(
+            &mut replication,
+            vec![
+                begin(7),
+                Message::Insert {
+                    relation: 1,
+                    new: vec![text("1"), TupleValue::Null],
+                },
+                commit(),
+            ],
+        );
+        assert_eq!(records[0].key, vec!["id"]);
+    }
+
+    #[test]
+    fn skips_transactions_already_read() {
+        let mut replication = replication();
+        let insert = || Message::Insert {
+            relation: 1,
+            new: vec![text("1"), TupleValue::Null],
+        };
+        let records = decode_all(&mut replication, vec![begin(7), insert(), commit()]);
+        assert_eq!(records.len(), 1);
+
+        // the same transaction peeked again before it was acknowledged
+        let records = decode_all(&mut replication, vec![begin(7), insert(), commit()]);
+        assert!(records.is_empty());
+        assert_eq!(replication.position(), Lsn(0x228));
+    }
+
+    #[test]
+    fn skips_transactions_the_snapshot_holds() {
+        let mut replication = replication();
+        replication.hand_off(Visibility::parse("700:710:703,705", Lsn(0x1000)).unwrap());
+        let transaction = |replication: &mut Replication, xid: u32, lsn: u64| {
+            let messages = vec![
+                Message::Begin {
+                    final_lsn: Lsn(lsn),
+                    timestamp: 0,
+                    xid,
+                },
+                Message::Insert {
+                    relation: 1,
+                    new: vec![text(&xid.to_string()), TupleValue::Null],
+                },
+                Message::Commit {
+                    commit_lsn: Lsn(lsn),
+                    end_lsn: Lsn(lsn + 0x28),
+                    timestamp: 0,
+                },
+            ];
+            decode_all(replication, messages).len()
+        };
+        // committed before the snapshot was taken
+        assert_eq!(transaction(&mut replication, 702, 0x200), 0);
+        // still running, or not yet started, when it was taken
+        assert_eq!(transaction(&mut replication, 703, 0x300), 1);
+        assert_eq!(transaction(&mut replication, 712, 0x400), 1);
+        assert!(!replication.handed_off());
+        assert_eq!(replication.position(), Lsn(0x428));
+
+        // past the snapshot, nothing is skipped any more
+        assert_eq!(transaction(&mut replication, 701, 0x1000), 1);
+        assert!(replication.handed_off());
+        assert!(Visibility::parse("700:710", Lsn(0)).is_err());
+    }
+
+    #[test]
+    fn skips_other_tables() {
+        let mut replication = replication();
+        let records = decode_all(
+            &mut replication,
+            vec![
+                begin(8),
+                Message::Insert {
+                    relation: 2,
+                    new: vec![text("1"), text("x")],
+                },
+                commit(),
+            ],
+        );
+        assert!(records.is_empty());
+        // the slot can still move past the transaction
+        assert_eq!(replication.position(), Lsn(0x228));
+    }
+
+    #[test]
+    fn projects_configured_fields() {
+        let mut replication = replication();
+        replication.fields = vec![Field {
+            name: "note".to_string(),
+            field_type: config::FieldType::String,
+            nullable: true,
+        }];
+        let records = decode_all(
+            &mut replication,
+            vec![
+                begin(9),
+                Message::Insert {
+                    relation: 1,
+                    new: vec![text("1"), text("hello")],
+                },
+                commit(),
+            ],
+        );
+        assert_eq!(
+            records[0].after,
+            Some(row(&[("note", Value::String("hello".to_string()))]))
+        );
+    }
+
+    #[test]
+    fn restores_the_decoder_when_a_batch_fails() {
+        let mut replication = replication();
+        replication.cursor_started = true;
+        let insert = || Message::Insert {
+            relation: 1,
+            new: vec![text("1"), TupleValue::Null],
+        };
+        let changes = vec![
+            Ok((Lsn(0x100), begin(7))),
+            Ok((Lsn(0x101), insert())),
+            Ok((Lsn(0x102), commit())),
+            Err("unknown message".into()),
+        ];
+        assert!(replication.decode_batch(changes.into_iter()).is_err());
+        // the transaction decoded before the failure is read again from a new cursor
+        assert_eq!(replication.position(), Lsn::default());
+        assert!(!replication.cursor_started);
+
+        let changes = vec![
+            Ok((Lsn(0x100), begin(7))),
+            Ok((Lsn(0x101), insert())),
+            Ok((Lsn(0x102), commit())),
+        ];
+        let records = replication.decode_batch(changes.into_iter()).unwrap();
+        assert_eq!(records.len(), 1);
+        assert_eq!(replication.position(), Lsn(0x228));
+    }
+
+    #[test]
+    fn slot_names_are_sanitized() {
+        assert_eq!(slot_name("Order-Items"), "fust_order_items");
+        assert_eq!(cursor_name("fust_orders"), "fust_orders_cursor");
+        assert_eq!(cursor_name(&"s".repeat(63)).len(), 63);
+    }
+}
@@ -0,0 +1,677 @@
+use checkpoint::Position;
+use config::{Field, FieldType};
+use deadpool_postgres::Pool;
+use record::{Error, Metadata, Op, Record, Row, Value};
+use tokio_postgres::{
+    binary_copy::BinaryCopyOutStream,
+    types::{ToSql, Type},
+    Client,
+};
+use tokio_stream::StreamExt;
+
+use super::copy::{self, CopyOut};
+
+// Column is a table column as described by the Postgres catalog
+#[derive(Debug, Clone, PartialEq)]
+pub struct Column {
+    pub name: String,
+    pub pg_type: String,
+    pub nullable: bool,
+}
+
+// Snapshot reads a whole table in primary key order, one bounded batch at a time.
+// Each batch starts strictly after the last key of the previous one (keyset
+// pagination), so no batch has to skip over rows already read.
+#[derive(Debug)]
+pub struct Snapshot {
+    schema: String,
+    table: String,
+    relation: String,
+    fields: Vec<Field>,
+    // pg_types are the catalog types of `fields`
+    pg_types: Vec<String>,
+    key: Vec<Column>,
+    batch_size: i64,
+    last_key: Option<Vec<String>>,
+    // upper is the last key a chunk of a parallel snapshot reads up to
+    upper: Option<Vec<String>>,
+    done: bool,
+    // copy is the COPY a binary snapshot streams its rows from
+    copy: Option<CopyOut>,
+}
+
+const COLUMNS_QUERY: &str = "SELECT a.attname::text, a.atttypid::regtype::text, NOT a.attnotnull \
+     FROM pg_attribute a \
+     WHERE a.attrelid = $1::text::regclass AND a.attnum > 0 AND NOT a.attisdropped \
+     ORDER BY a.attnum";
+
+pub(super) const PRIMARY_KEY_QUERY: &str = "SELECT a.attname::text \
+     FROM pg_index i \
+     JOIN pg_attribute a ON a.attrelid = i.indrelid AND a.attnum = ANY(i.indkey) \
+     WHERE i.indrelid = $1::text::regclass AND i.indisprimary \
+     ORDER BY array_position(i.indkey::int2[], a.attnum)";
+
+impl Snapshot {
+    // plan looks the table up in the catalog and prepares a snapshot of `fields`,
+    // or of every column when no fields are configured. `key` overrides the
+    // primary key, and is required for tables that do not have one.
+    pub async fn plan(
+        client: &Client,
+        schema: &str,
+        table: &str,
+        key: Option<&[String]>,
+        fields: &[Field],
+        batch_size: i64,
+    ) -> Result<Self, Error> {
+        let relation = format!("{}.{}", quote_ident(schema), quote_ident(table));
+
+        let columns: Vec<Column> = client
+            .query(COLUMNS_QUERY, &[&relation])
+            .await?
+            .iter()
+            .map(|row| Column {
+                name: row.get(0),
+                pg_type: row.get(1),
+                nullable: row.get(2),
+            })
+            .collect();
+        let column = |name: &str| -> Result<Column, Error> {
+            columns
+                .iter()
+                .find(|c| c.name == name)
+                .cloned()
+                .ok_or_else(|| format!("column `{}` does not exist in {}", name, relation).into())
+        };
+
+        let key_names: Vec<String> = match key {
+            Some(key) => key.to_vec(),
+            None => client
+                .query(PRIMARY_KEY_QUERY, &[&relation])
+                .await?
+                .iter()
+                .map(|row| row.get(0))
+                .collect(),
+        };
+        if key_names.is_empty() {
+            return Err(format!(
+                "{} has no primary key, set `key` to the columns to page through it by",
+                relation
+            )
+            .into());
+        }
+        let key = key_names
+            .iter()
+            .map(|name| column(name))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        let fields: Vec<Field> = if fields.is_empty() {
+            columns
+                .iter()
+                .map(|c| Field {
+                    name: c.name.clone(),
+                    field_type: field_type(&c.pg_type),
+                    nullable: c.nullable,
+                })
+                .collect()
+        } else {
+            fields.to_vec()
+        };
+        let pg_types = fields
+            .iter()
+            .map(|field| column(&field.name).map(|c| c.pg_type))
+            .collect::<Result<Vec<_>, _>>()?;
+
+        Ok(Snapshot {
+            schema: schema.to_string(),
+            table: table.to_string(),
+            relation,
+            fields,
+            pg_types,
+            key,
+            batch_size,
+            last_key: None,
+            upper: None,
+            done: false,
+            copy: None,
+        })
+    }
+
+    // resume continues a snapshot from a checkpoint
+    pub fn resume(&mut self, position: &Position) -> Result<(), Error> {
+        match position {
+            Position::Keyset { key } if key.len() == self.key.len() => {
+                self.last_key = Some(key.clone())
+            }
+            Position::Finished => self.done = true,
+            other => {
+                return Err(format!(
+                    "cannot resume the snapshot of {} from {:?}",
+                    self.relation, other
+                )
+                .into())
+            }
+        }
+        Ok(())
+    }
+
+    // position is the key of the last row returned, or Finished once every row has
+    pub fn position(&self) -> Option<Position> {
+        if self.done {
+            return Some(Position::Finished);
+        }
+        self.last_key.clone().map(|key| Position::Keyset { key })
+    }
+
+    // query builds the statement for the next batch. Every column is read in its
+    // text form and typed by its field; the key columns are appended so the last
+    // row of a batch tells where the next one starts.
+    pub fn query(&self) -> String {
+        let columns: Vec<String> = self
+            .fields
+            .iter()
+            .map(|f| f.name.as_str())
+            .chain(self.key.iter().map(|c| c.name.as_str()))
+            .map(|name| format!("{}::text", quote_ident(name)))
+            .collect();
+        // the key is qualified, otherwise ORDER BY would pick the text output columns
+        let key: Vec<String> = self
+            .key
+            .iter()
+            .map(|c| format!("t.{}", quote_ident(&c.name)))
+            .collect();
+
+        let params = |first: usize| -> String {
+            self.key
+                .iter()
+                .enumerate()
+                .map(|(i, c)| format!("${}::text::{}", first + i, c.pg_type))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut bounds = vec![];
+        if self.last_key.is_some() {
+            bounds.push(format!("({}) > ({})", key.join(", "), params(1)));
+        }
+        if self.upper.is_some() {
+            let first = bounds.len() * self.key.len() + 1;
+            bounds.push(format!("({}) <= ({})", key.join(", "), params(first)));
+        }
+
+        let mut query = format!("SELECT {} FROM {} t", columns.join(", "), self.relation);
+        if !bounds.is_empty() {
+            query.push_str(&format!(" WHERE {}", bounds.join(" AND ")));
+        }
+        query.push_str(&format!(
+            " ORDER BY {} LIMIT {}",
+            key.join(", "),
+            self.batch_size
+        ));
+        query
+    }
+
+    // next_batch reads the next rows, returning an empty batch once the whole table
+    // has been read
+    pub async fn next_batch(&mut self, client: &Client) -> Result<Vec<Record>, Error> {
+        if self.done {
+            return Ok(vec![]);
+        }
+        let params: Vec<&(dyn ToSql + Sync)> = self
+            .last_key
+            .iter()
+            .chain(&self.upper)
+            .flatten()
+            .map(|k| k as &(dyn ToSql + Sync))
+            .collect();
+        let rows = client.query(&self.query(), &params).await?;
+
+        let mut records = Vec::with_capacity(rows.len());
+        for row in &rows {
+            let mut after = Row::new();
+            for (i, field) in self.fields.iter().enumerate() {
+                let text: Option<&str> = row.get(i);
+                after.insert(field.name.clone(), Value::from_field(field, text)?);
+            }
+            records.push(self.record(after));
+        }
+
+        if let Some(last) = rows.last() {
+            let offset = self.fields.len();
+            self.last_key = Some(
+                (0..self.key.len())
+                    .map(|i| last.get::<_, Option<String>>(offset + i))
+                    .collect::<Option<Vec<String>>>()
+                    .ok_or_else(|| format!("{} has a NULL key value", self.relation))?,
+            );
+        }
+        if (rows.len() as i64) < self.batch_size {
+            self.done = true;
+        }
+        Ok(records)
+    }
+
+    // split finds the keys that cut the table into `chunks` ranges of about the
+    // same number of rows. Each key is the last one of its range, so there is one
+    // key less than there are ranges, or fewer for small tables.
+    pub async fn split(&self, client: &Client, chunks: usize) -> Result<Vec<Vec<String>>, Error> {
+        if chunks < 2 {
+            return Ok(vec![]);
+        }
+        let count: i64 = client
+            .query_one(&format!("SELECT count(*) FROM {}", self.relation), &[])
+            .await?
+            .get(0);
+        let step = (count + chunks as i64 - 1) / chunks as i64;
+        if step == 0 {
+            return Ok(vec![]);
+        }
+        let rows = client
+            .query(&self.split_query(), &[&step, &(chunks as i64 - 1)])
+            .await?;
+        rows.iter()
+            .map(|row| {
+                (0..self.key.len())
+                    .map(|i| row.get::<_, Option<String>>(i))
+                    .collect::<Option<Vec<String>>>()
+                    .ok_or_else(|| format!("{} has a NULL key value", self.relation).into())
+            })
+            .collect()
+    }
+
+    // split_query numbers the rows in key order and keeps the key of every
+    // `$1`th row, the last row of each chunk
+    fn split_query(&self) -> String {
+        let key: Vec<String> = self
+            .key
+            .iter()
+            .map(|c| format!("t.{}", quote_ident(&c.name)))
+            .collect();
+        let columns: Vec<String> = self
+            .key
+            .iter()
+            .map(|c| format!("s.{}::text", quote_ident(&c.name)))
+            .collect();
+        format!(
+            "SELECT {} FROM (SELECT {}, row_number() OVER (ORDER BY {}) AS fust_row FROM {} t) s \
+             WHERE s.fust_row % $1::bigint = 0 AND s.fust_row < $1::bigint * ($2::bigint + 1) ORDER BY s.fust_row",
+            columns.join(", "),
+            key.join(", "),
+            key.join(", "),
+            self.relation
+        )
+    }
+
+    // chunk is a snapshot of the rows after `lower` up to and including `upper`,
+    // both open ended when None
+    pub fn chunk(&self, lower: Option<Vec<String>>, upper: Option<Vec<String>>) -> Snapshot {
+        Snapshot {
+            schema: self.schema.clone(),
+            table: self.table.clone(),
+            relation: self.relation.clone(),
+            fields: self.fields.clone(),
+            pg_types: self.pg_types.clone(),
+            key: self.key.clone(),
+            batch_size: self.batch_size,
+            last_key: lower,
+            upper,
+            done: false,
+            copy: None,
+        }
+    }
+
+    // is_done tells whether every row has been read
+    pub fn is_done(&self) -> bool {
+        self.done
+    }
+
+    // last_key is the key of the last row read, the lower bound of the rest
+    pub fn last_key(&self) -> Option<&Vec<String>> {
+        self.last_key.as_ref()
+    }
+
+    // advance records a batch read up to `key` without reading it
+    #[cfg(test)]
+    pub fn advance(&mut self, key: Vec<String>, done: bool) {
+        self.last_key = Some(key);
+        self.done = done;
+    }
+
+    // copy_query builds the COPY a binary snapshot reads the rest of the table
+    // with. Columns whose binary form fust decodes are copied as they are, the
+    // others in their text form; the key columns are appended as text, like in
+    // `query`. COPY takes no parameters, so the last key is inlined as literals.
+    pub fn copy_query(&self) -> String {
+        let columns: Vec<String> = self
+            .fields
+            .iter()
+            .zip(&self.pg_types)
+            .map(|(field, pg_type)| match copy::binary_type(field, pg_type) {
+                Some(_) => format!("t.{}", quote_ident(&field.name)),
+                None => format!("t.{}::text", quote_ident(&field.name)),
+            })
+            .chain(
+                self.key
+                    .iter()
+                    .map(|c| format!("t.{}::text", quote_ident(&c.name))),
+            )
+            .collect();
+        let key: Vec<String> = self
+            .key
+            .iter()
+            .map(|c| format!("t.{}", quote_ident(&c.name)))
+            .collect();
+
+        let values = |bound: &[String]| -> String {
+            self.key
+                .iter()
+                .zip(bound)
+                .map(|(c, value)| format!("{}::text::{}", quote_literal(value), c.pg_type))
+                .collect::<Vec<_>>()
+                .join(", ")
+        };
+        let mut bounds = vec![];
+        if let Some(last_key) = &self.last_key {
+            bounds.push(format!("({}) > ({})", key.join(", "), values(last_key)));
+        }
+        if let Some(upper) = &self.upper {
+            bounds.push(format!("({}) <= ({})", key.join(", "), values(upper)));
+        }
+
+        let mut query = format!("SELECT {} FROM {} t", columns.join(", "), self.relation);
+        if !bounds.is_empty() {
+            query.push_str(&format!(" WHERE {}", bounds.join(" AND ")));
+        }
+        query.push_str(&format!(" ORDER BY {}", key.join(", ")));
+        format!("COPY ({}) TO STDOUT (FORMAT binary)", query)
+    }
+
+    // copy_types are the types of the columns `copy_query` selects
+    fn copy_types(&self) -> Vec<Type> {
+        self.fields
+            .iter()
+            .zip(&self.pg_types)
+            .map(|(field, pg_type)| copy::binary_type(field, pg_type).unwrap_or(Type::TEXT))
+            .chain(self.key.iter().map(|_| Type::TEXT))
+            .collect()
+    }
+
+    // next_copy_batch reads the next rows of a binary snapshot. The whole rest of
+    // the table is streamed by a single COPY, which keeps one connection of `pool`
+    // until it is over; batches are cut from it as they are read.
+    pub async fn next_copy_batch(&mut self, pool: &Pool) -> Result<Vec<Record>, Error> {
+        if self.done {
+            return Ok(vec![]);
+        }
+        if self.copy.is_none() {
+            let client = pool.get().await?;
+            let stream = client.copy_out(&self.copy_query()).await?;
+            let fields: Vec<(Field, bool)> = self
+                .fields
+                .iter()
+                .zip(&self.pg_types)
+                .map(|(field, pg_type)| {
+                    (field.clone(), copy::binary_type(field, pg_type).is_some())
+                })
+                .collect();
+            let key_len = self.key.len();
+            let relation = self.relation.clone();
+            let rows = BinaryCopyOutStream::new(stream, &self.copy_types())
+                .map(move |row| copy::decode(&row?, &fields, key_len, &relation));
+            self.copy = Some(CopyOut::new(client, rows));
+        }
+        self.copy_batch().await
+    }
+
+    // copy_batch cuts the next batch from the running COPY. When a row cannot be
+    // read, the rows taken from the copy for the batch are lost with it, so the
+    // copy is dropped and the next batch starts a new one after the last key
+    // returned.
+    async fn copy_batch(&mut self) -> Result<Vec<Record>, Error> {
+        let copy = self.copy.as_mut().expect("copy is started");
+        let mut rows = Vec::with_capacity(self.batch_size as usize);
+        while (rows.len() as i64) < self.batch_size {
+            match copy.rows.next().await {
+                Some(Ok(row)) => rows.push(row),
+                Some(Err(e)) => {
+                    self.copy = None;
+                    return Err(e);
+                }
+                None => {
+                    self.done = true;
+                    self.copy = None;
+                    break;
+                }
+            }
+        }
+
+        let mut records = Vec::with_capacity(rows.len());
+        for (after, key) in rows {
+            records.push(self.record(after));
+            self.last_key = Some(key);
+        }
+        Ok(records)
+    }
+
+    fn record(&self, after: Row) -> Record {
+        Record {
+            op: Op::Snapshot,
+            key: self.key.iter().map(|c| c.name.clone()).collect(),
+            before: None,
+            after: Some(after),
+            metadata: Metadata {
+                connector: "postgres".to_string(),
+                schema: Some(self.schema.clone()),
+                table: Some(self.table.clone()),
+                ..Metadata::default()
+            },
+            event_time: None,
+        }
+    }
+}
+
+// field_type maps a Postgres type name, as printed by `regtype`, to a field type
+pub fn field_type(pg_type: &str) -> FieldType {
+    match pg_type {
+        "smallint" | "integer" => FieldType::Int,
+        "bigint" => FieldType::BigInt,
+        "real" | "double precision" => FieldType::Number,
+        "numeric" => FieldType::Decimal,
+        "boolean" => FieldType::Boolean,
+        "date" => FieldType::Date,
+        "timestamp without time zone" | "timestamp with time zone" => FieldType::Timestamp,
+        "uuid" => FieldType::Uuid,
+        "json" | "jsonb" => FieldType::Json,
+        "bytea" => FieldType::Bytes,
+        _ => FieldType::String,
+    }
+}
+
+// quote_ident quotes an identifier so it can be used verbatim in a statement
+pub fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+// quote_literal quotes a string constant, for statements that take no parameters
+fn quote_literal(value: &str) -> String {
+    format!("E'{}'", value.replace('\\', "\\\\").replace('\'', "\\'"))
+}
+
+#[cfg(test)]
+pub(super) mod tests {
+    use super::*;
+
+    pub(in crate::pg) fn column(name: &str, pg_type: &str) -> Column {
+        Column {
+            name: name.to_string(),
+            pg_type: pg_type.to_string(),
+            nullable: false,
+        }
+    }
+
+    pub(in crate::pg) fn snapshot(key: Vec<Column>) -> Snapshot {
+        Snapshot {
+            schema: "public".to_string(),
+            table: "orders".to_string(),
+            relation: "\"public\".\"orders\"".to_string(),
+            fields: vec![Field {
+                name: "total".to_string(),
+                field_type: FieldType::Decimal,
+                nullable: true,
+            }],
+            pg_types: vec!["numeric".to_string()],
+            key,
+            batch_size: 500,
+            last_key: None,
+            upper: None,
+            done: false,
+            copy: None,
+        }
+    }
+
+    #[test]
+    fn query_pages_by_key() {
+        let mut snapshot = snapshot(vec![column("id", "bigint")]);
+        assert_eq!(
+            snapshot.query(),
+            "SELECT \"total\"::text, \"id\"::text FROM \"public\".\"orders\" t ORDER BY t.\"id\" LIMIT 500"
+        );
+
+        snapshot.last_key = Some(vec!["42".to_string()]);
+        assert_eq!(
+            snapshot.query(),
+            "SELECT \"total\"::text, \"id\"::text FROM \"public\".\"orders\" t \
+             WHERE (t.\"id\") > ($1::text::bigint) ORDER BY t.\"id\" LIMIT 500"
+        );
+    }
+
+    #[test]
+    fn query_pages_by_composite_key() {
+        let mut snapshot = snapshot(vec![
+            column("tenant", "character varying"),
+            column("created", "timestamp with time zone"),
+        ]);
+        snapshot.last_key = Some(vec!["acme".to_string(), "2024-01-01".to_string()]);
+        assert_eq!(
+            snapshot.query(),
+            "SELECT \"total\"::text, \"tenant\"::text, \"created\"::text FROM \"public\".\"orders\" t \
+             WHERE (t.\"tenant\", t.\"created\") > ($1::text::character varying, $2::text::timestamp with time zone) \
+             ORDER BY t.\"tenant\", t.\"created\" LIMIT 500"
+        );
+    }
+
+    #[test]
+    fn chunks_page_within_their_range() {
+        let snapshot = snapshot(vec![column("id", "bigint")]);
+        assert_eq!(
+            snapshot.split_query(),
+            "SELECT s.\"id\"::text FROM (SELECT t.\"id\", row_number() OVER (ORDER BY t.\"id\") AS fust_row \
+             FROM \"public\".\"orders\" t) s WHERE s.fust_row % $1::bigint = 0 AND s.fust_row < $1::bigint * ($2::bigint + 1) \
+             ORDER BY s.fust_row"
+        );
+
+        let first = snapshot.chunk(None, Some(vec!["100".to_string()]));
+        assert!(first
+            .query()
+            .contains("WHERE (t.\"id\") <= ($1::text::bigint) ORDER BY"));
+        let middle = snapshot.chunk(Some(vec!["100".to_string()]), Some(vec!["200".to_string()]));
+        assert!(middle.query().contains(
+            "WHERE (t.\"id\") > ($1::text::bigint) AND (t.\"id\") <= ($2::text::bigint) ORDER BY"
+        ));
+        assert_eq!(
+            middle.position(),
+            Some(Position::Keyset {
+                key: vec!["100".to_string()]
+            })
+        );
+    }
+
+    #[test]
+    fn copy_query_streams_the_rest_of_the_table() {
+        let mut snapshot = snapshot(vec![column("tenant", "text"), column("id", "integer")]);
+        snapshot.fields.push(Field {
+            name: "tags".to_string(),
+            field_type: FieldType::String,
+            nullable: true,
+        });
+        snapshot.pg_types.push("text[]".to_string());
+        assert_eq!(
+            snapshot.copy_query(),
+            "COPY (SELECT t.\"total\", t.\"tags\"::text, t.\"tenant\"::text, t.\"id\"::text \
+             FROM \"public\".\"orders\" t ORDER BY t.\"tenant\", t.\"id\") TO STDOUT (FORMAT binary)"
+        );
+        assert_eq!(
+            snapshot.copy_types(),
+            vec![Type::NUMERIC, Type::TEXT, Type::TEXT, Type::TEXT]
+        );
+
+        snapshot.last_key = Some(vec!["o'neil".to_string(), "42".to_string()]);
+        assert!(snapshot.copy_query().contains(
+            "WHERE (t.\"tenant\", t.\"id\") > (E'o\\'neil'::text::text, E'42'::text::integer)"
+        ));
+    }
+
+    #[tokio::test]
+    async fn restarts_the_copy_after_a_failed_row() {
+        let mut snapshot = snapshot(vec![column("id", "bigint")]);
+        snapshot.batch_size = 2;
+        let row = |id: i64| -> Result<copy::CopyRow, Error> {
+            let mut after = Row::new();
+            after.insert("total", Value::Decimal(format!("{}.00", id)));
+            Ok((after, vec![id.to_string()]))
+        };
+        snapshot.copy = Some(CopyOut::from_rows(tokio_stream::iter(vec![
+            row(1),
+            row(2),
+            row(3),
+            Err("connection reset".into()),
+            row(4),
+        ])));
+
+        assert_eq!(snapshot.copy_batch().await.unwrap().len(), 2);
+        // row 3 was taken from the copy before it failed, so the next copy has to
+        // start after row 2 for it to be read again
+        assert!(snapshot.copy_batch().await.is_err());
+        assert!(snapshot.copy.is_none());
+        assert!(!snapshot.is_done());
+        assert_eq!(
+            snapshot.position(),
+            Some(Position::Keyset {
+                key: vec!["2".to_string()]
+            })
+        );
+        assert!(snapshot
+            .copy_query()
+            .contains("WHERE (t.\"id\") > (E'2'::text::bigint)"));
+    }
+
+    #[test]
+    fn resumes_from_checkpoints() {
+        let mut snapshot = snapshot(vec![column("id", "bigint")]);
+        assert_eq!(snapshot.position(), None);
+
+        let position = Position::Keyset {
+            key: vec!["42".to_string()],
+        };
+        snapshot.resume(&position).unwrap();
+        assert_eq!(snapshot.position(), Some(position));
+        assert!(snapshot
+            .query()
+            .contains("WHERE (t.\"id\") > ($1::text::bigint)"));
+
+        assert!(snapshot
+            .resume(&Position::Keyset {
+                key: vec!["acme".to_string(), "42".to_string()],
+            })
+            .is_err());
+        snapshot.resume(&Position::Finished).unwrap();
+        assert_eq!(snapshot.position(), Some(Position::Finished));
+    }
+
+    #[test]
+    fn maps_postgres_types() {
+        assert_eq!(field_type("integer"), FieldType::Int);
+        assert_eq!(field_type("timestamp with time zone"), FieldType::Timestamp);
+        assert_eq!(field_type("jsonb"), FieldType::Json);
+        assert_eq!(field_type("integer[]"), FieldType::String);
+        assert_eq!(quote_ident("we\"ird"), "\"we\"\"ird\"");
+    }
+}
@@ -0,0 +1,602 @@
+use std::{collections::HashMap, time::Duration};
+
+use async_trait::async_trait;
+use checkpoint::Position;
+use config::{ConnectorConfig, Field, FieldType, SourceConfig, SqliteConfig};
+use record::{Batch, Error, Metadata, Op, Record, Row, Value};
+use rusqlite::{params_from_iter, types::ValueRef, Connection};
+use tokio::sync::broadcast::Receiver;
+use tracing::info;
+use util::sqlite::Database;
+
+use crate::Source;
+
+const DEFAULT_BATCH_SIZE: usize = 1000;
+const DEFAULT_POLL_INTERVAL: Duration = Duration::from_millis(1000);
+
+// SqliteSource reads a table of a SQLite database file in the order rows were
+// written: by rowid, or by a `watermark` column, such as an `updated_at`
+// timestamp, with the rowid breaking ties. It snapshots the table page by page,
+// each page starting after the cursor of the last row read, and with
+// `mode = "poll"` keeps looking for rows past that cursor every
+// `poll_interval_ms`.
+//
+// Polling by rowid finds new rows; polling by a watermark also finds rows
+// updated since, as long as every write moves the watermark forward. Deletes
+// are never seen, nor are rows whose watermark is NULL. The table needs a rowid,
+// so it cannot be declared `WITHOUT ROWID`.
+pub struct SqliteSource {
+    sqlite: SqliteConfig,
+    database: Option<Database>,
+    shutdown_rx: Receiver<()>,
+    table: String,
+    key: Option<Vec<String>>,
+    fields: Vec<Field>,
+    batch_size: usize,
+    watermark: Option<String>,
+    // poll_interval is how long a polling source waits after finding no rows,
+    // and None for a source that stops after the snapshot
+    poll_interval: Option<Duration>,
+    // cursor is the rowid of the last row read, after its watermark if any
+    cursor: Option<Vec<String>>,
+    // snapshotting is whether the rows read are still the table as it was
+    // when the source opened
+    snapshotting: bool,
+    done: bool,
+}
+
+impl SqliteSource {
+    pub fn new(config: &SourceConfig, shutdown_rx: Receiver<()>) -> Result<Self, Error> {
+        match &config.connector {
+            ConnectorConfig::Sqlite(sqlite) => {
+                Self::from_sqlite(sqlite, &config.config, config.fields.clone(), shutdown_rx)
+            }
+            other => {
+                Err(format!("a {} connector cannot back a sqlite source", other.kind()).into())
+            }
+        }
+    }
+
+    // from_sqlite builds a source for one table from the connector's database
+    // file and the source's own settings: `table`, `key`, `batch_size`,
+    // `watermark`, plus `mode = "poll"` with an optional `poll_interval_ms` to
+    // keep reading rows written after the snapshot
+    pub fn from_sqlite(
+        sqlite: &SqliteConfig,
+        settings: &HashMap<String, String>,
+        fields: Vec<Field>,
+        shutdown_rx: Receiver<()>,
+    ) -> Result<Self, Error> {
+        let table = settings
+            .get("table")
+            .cloned()
+            .ok_or("sqlite source is missing `table`")?;
+        let batch_size = match settings.get("batch_size") {
+            Some(size) => match size.parse::<usize>() {
+                Ok(size) if size > 0 => size,
+                _ => return Err(format!("invalid batch_size `{}`", size).into()),
+            },
+            None => DEFAULT_BATCH_SIZE,
+        };
+        let poll_interval = match settings.get("poll_interval_ms") {
+            Some(ms) => match ms.parse::<u64>() {
+                Ok(ms) if ms > 0 => Duration::from_millis(ms),
+                _ => return Err(format!("invalid poll_interval_ms `{}`", ms).into()),
+            },
+            None => DEFAULT_POLL_INTERVAL,
+        };
+        let poll_interval = match settings.get("mode").map(String::as_str) {
+            None | Some("snapshot") => None,
+            Some("poll") => Some(poll_interval),
+            Some(other) => {
+                return Err(
+                    format!("invalid mode `{}`, expected `snapshot` or `poll`", other).into(),
+                )
+            }
+        };
+        let key = settings.get("key").map(|key| {
+            key.split(',')
+                .map(|column| column.trim().to_string())
+                .collect()
+        });
+
+        Ok(SqliteSource {
+            sqlite: sqlite.clone(),
+            database: None,
+            shutdown_rx,
+            table,
+            key,
+            fields,
+            batch_size,
+            watermark: settings.get("watermark").cloned(),
+            poll_interval,
+            cursor: None,
+            snapshotting: true,
+            done: false,
+        })
+    }
+
+    // query builds the statement for the next page. The rowid and the watermark
+    // are selected ahead of the fields, and the cursor is bound as parameters.
+    fn query(&self) -> String {
+        let cursor: Vec<String> = self
+            .watermark
+            .iter()
+            .map(|column| quote_ident(column))
+            .chain(["rowid".to_string()])
+            .collect();
+        let columns: Vec<String> = ["rowid".to_string()]
+            .into_iter()
+            .chain(self.watermark.iter().map(|column| quote_ident(column)))
+            .chain(self.fields.iter().map(|field| quote_ident(&field.name)))
+            .collect();
+        let params: Vec<String> = (1..=cursor.len()).map(|i| format!("?{}", i)).collect();
+
+        let mut bounds = vec![];
+        if let Some(watermark) = &self.watermark {
+            bounds.push(format!("{} IS NOT NULL", quote_ident(watermark)));
+        }
+        if self.cursor.is_some() {
+            bounds.push(format!("({}) > ({})", cursor.join(", "), params.join(", ")));
+        }
+        let mut query = format!(
+            "SELECT {} FROM {}",
+            columns.join(", "),
+            quote_ident(&self.table)
+        );
+        if !bounds.is_empty() {
+            query.push_str(&format!(" WHERE {}", bounds.join(" AND ")));
+        }
+        query.push_str(&format!(
+            " ORDER BY {} LIMIT {}",
+            cursor.join(", "),
+            self.batch_size
+        ));
+        query
+    }
+
+    // position is the cursor of the last row read, or Finished once a snapshot
+    // has read every row
+    fn position(&self) -> Option<Position> {
+        if self.done {
+            return Some(Position::Finished);
+        }
+        self.cursor.clone().map(|key| Position::Keyset { key })
+    }
+
+    // next_page reads the rows after the cursor, with their cursors
+    async fn next_page(&self) -> Result<Vec<(Vec<String>, Row)>, Error> {
+        let database = self
+            .database
+            .as_ref()
+            .ok_or("sqlite source read before it was opened")?;
+        let query = self.query();
+        let params = self.cursor.clone().unwrap_or_default();
+        let watermarked = self.watermark.is_some();
+        let fields = self.fields.clone();
+        database
+            .call(move |conn| {
+                let mut statement = conn.prepare_cached(&query)?;
+                let mut rows = statement.query(params_from_iter(params))?;
+                let mut page = vec![];
+                while let Some(row) = rows.next()? {
+                    let mut cursor = vec![];
+                    if watermarked {
+                        cursor.push(text(row.get_ref(1)?));
+                    }
+                    cursor.push(row.get::<_, i64>(0)?.to_string());
+                    let offset = 1 + watermarked as usize;
+                    let mut values = Row::new();
+                    for (i, field) in fields.iter().enumerate() {
+                        values.insert(field.name.clone(), value(field, row.get_ref(offset + i)?)?);
+                    }
+                    page.push((cursor, values));
+                }
+                Ok(page)
+            })
+            .await
+    }
+
+    // record is a row read at the cursor, which tells changes to it apart
+    fn record(&self, op: Op, after: Row) -> Record {
+        Record {
+            op,
+            key: self.key.clone().unwrap_or_default(),
+            before: None,
+            after: Some(after),
+            metadata: Metadata {
+                connector: "sqlite".to_string(),
+                database: Some(self.sqlite.path.clone()),
+                table: Some(self.table.clone()),
+                attributes: self
+                    .cursor
+                    .iter()
+                    .map(|cursor| ("cursor".to_string(), cursor.join("/")))
+                    .collect(),
+                ..Metadata::default()
+            },
+            event_time: None,
+        }
+    }
+}
+
+// describe looks a table up, returning its columns as fields and its primary
+// key, in key order
+fn describe(conn: &Connection, table: &str) -> Result<(Vec<Field>, Vec<String>), Error> {
+    let mut statement = conn.prepare(&format!("PRAGMA table_info({})", quote_ident(table)))?;
+    let mut fields = vec![];
+    let mut key = vec![];
+    let mut rows = statement.query([])?;
+    while let Some(row) = rows.next()? {
+        let name: String = row.get("name")?;
+        let declared: String = row.get("type")?;
+        let not_null: bool = row.get("notnull")?;
+        let pk: i64 = row.get("pk")?;
+        if pk > 0 {
+            key.push((pk, name.clone()));
+        }
+        fields.push(Field {
+            name,
+            field_type: field_type(&declared),
+            nullable: !not_null,
+        });
+    }
+    if fields.is_empty() {
+        return Err(format!("table `{}` does not exist", table).into());
+    }
+    key.sort();
+    Ok((fields, key.into_iter().map(|(_, name)| name).collect()))
+}
+
+// field_type maps a declared column type to a field type, by the same rules
+// SQLite uses to pick a column's affinity, after the names it has no affinity for
+pub fn field_type(declared: &str) -> FieldType {
+    let declared = declared.to_uppercase();
+    let has = |names: &[&str]| names.iter().any(|name| declared.contains(name));
+    if has(&["BOOL"]) {
+        FieldType::Boolean
+    } else if has(&["DATETIME", "TIMESTAMP"]) {
+        FieldType::Timestamp
+    } else if has(&["DATE"]) {
+        FieldType::Date
+    } else if has(&["JSON"]) {
+        FieldType::Json
+    } else if has(&["UUID"]) {
+        FieldType::Uuid
+    } else if has(&["INT"]) {
+        FieldType::BigInt
+    } else if has(&["CHAR", "CLOB", "TEXT"]) {
+        FieldType::String
+    } else if has(&["BLOB"]) {
+        FieldType::Bytes
+    } else if has(&["REAL", "FLOA", "DOUB"]) {
+        FieldType::Number
+    } else if has(&["DECIMAL", "NUMERIC"]) {
+        FieldType::Decimal
+    } else {
+        FieldType::String
+    }
+}
+
+// value types one column value by its field. SQLite keeps whatever a column is
+// given, so integers and reals are taken as they are where the field holds
+// numbers, and everything else goes through the value's text form.
+fn value(field: &Field, value: ValueRef) -> Result<Value, String> {
+    let typed = match (value, field.field_type) {
+        (ValueRef::Null, _) => return Value::from_field(field, None),
+        (ValueRef::Integer(i), FieldType::Boolean) => Value::Bool(i != 0),
+        (ValueRef::Integer(i), FieldType::Int | FieldType::BigInt) => Value::Int(i),
+        (ValueRef::Integer(i), FieldType::Number) => Value::Float(i as f64),
+        (ValueRef::Real(f), FieldType::Number) => Value::Float(f),
+        (ValueRef::Blob(bytes), FieldType::Bytes) => Value::Bytes(bytes.to_vec()),
+        (value, _) => return Value::from_field(field, Some(&text(value))),
+    };
+    Ok(typed)
+}
+
+// text is the text form of a value, as SQLite would cast it
+fn text(value: ValueRef) -> String {
+    match value {
+        ValueRef::Null => String::new(),
+        ValueRef::Integer(i) => i.to_string(),
+        ValueRef::Real(f) => f.to_string(),
+        ValueRef::Text(text) | ValueRef::Blob(text) => String::from_utf8_lossy(text).into_owned(),
+    }
+}
+
+fn quote_ident(ident: &str) -> String {
+    format!("\"{}\"", ident.replace('"', "\"\""))
+}
+
+#[async_trait]
+impl Source for SqliteSource {
+    async fn open(&mut self, checkpoint: Option<Position>) -> Result<(), Error> {
+        let cursor_len = 1 + self.watermark.is_some() as usize;
+        match &checkpoint {
+            None => {}
+            Some(Position::Keyset { key }) if key.len() == cursor_len => {
+                self.cursor = Some(key.clone())
+            }
+            Some(Position::Finished) if self.poll_interval.is_none() => self.done = true,
+            Some(other) => {
+                return Err(format!("cannot resume {} from {:?}", self.table, other).into())
+            }
+        }
+
+        let database = Database::open(&self.sqlite, false).await?;
+        let table = self.table.clone();
+        let (columns, primary_key) = database.call(move |conn| describe(conn, &table)).await?;
+        for name in self.key.iter().flatten().chain(&self.watermark) {
+            if !columns.iter().any(|column| column.name == *name) {
+                return Err(format!("column `{}` does not exist in {}", name, self.table).into());
+            }
+        }
+        for field in &self.fields {
+            if !columns.iter().any(|column| column.name == field.name) {
+                return Err(
+                    format!("column `{}` does not exist in {}", field.name, self.table).into(),
+                );
+            }
+        }
+        if self.fields.is_empty() {
+            self.fields = columns;
+        }
+        if self.key.is_none() {
+            self.key = Some(primary_key);
+        }
+        info!(
+            "opened sqlite source {} in {}",
+            self.table, self.sqlite.path
+        );
+        self.database = Some(database);
+        Ok(())
+    }
+
+    // read returns the next page of rows. A snapshot returns None once every row
+    // has been read; polling waits for rows past the cursor. Both return None on
+    // shutdown.
+    async fn read(&mut self) -> Result<Option<Batch>, Error> {
+        loop {
+            if self.done || crate::shutting_down(&mut self.shutdown_rx) {
+                return Ok(None);
+            }
+            let page = self.next_page().await?;
+            // rows read after a restart are taken as snapshot rows until the
+            // source catches up, as the checkpoint does not tell which they are
+            let op = match (self.snapshotting, &self.watermark) {
+                (true, _) => Op::Snapshot,
+                (false, None) => Op::Insert,
+                // a row past the watermark may be new or changed, which cannot be
+                // told apart, so it is an update: sinks upsert both alike
+                (false, Some(_)) => Op::Update,
+            };
+            if page.len() < self.batch_size {
+                if self.snapshotting {
+                    info!("snapshot of {} finished", self.table);
+                }
+                self.snapshotting = false;
+                self.done = self.poll_interval.is_none();
+            }
+            let mut records = Vec::with_capacity(page.len());
+            for (cursor, row) in page {
+                self.cursor = Some(cursor);
+                records.push(self.record(op, row));
+            }
+            if !records.is_empty() {
+                return Ok(Some(Batch {
+                    records,
+                    position: self.position(),
+                }));
+            }
+            let Some(poll_interval) = self.poll_interval else {
+                return Ok(None);
+            };
+            tokio::select! {
+                _ = tokio::time::sleep(poll_interval) => {}
+                _ = self.shutdown_rx.recv() => return Ok(None),
+            }
+        }
+    }
+
+    // ack has nothing to release: the cursor is all the source keeps
+    async fn ack(&mut self, _position: &Position) -> Result<(), Error> {
+        Ok(())
+    }
+
+    async fn close(&mut self) -> Result<(), Error> {
+        info!("closing sqlite source {}", self.table);
+        self.database = None;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tokio::sync::broadcast;
+
+    fn sqlite_source(path: &str, settings: &[(&str, &str)]) -> SqliteSource {
+        let sqlite = SqliteConfig {
+            path: path.to_string(),
+            busy_timeout: 1000,
+            retry: Default::default(),
+        };
+        let settings: HashMap<String, String> = settings
+            .iter()
+            .map(|(k, v)| (k.to_string(), v.to_string()))
+            .collect();
+        let (_, shutdown_rx) = broadcast::channel(1);
+        SqliteSource::from_sqlite(&sqlite, &settings, vec![], shutdown_rx).unwrap()
+    }
+
+    fn ids(batch: &Batch) -> Vec<i64> {
+        batch
+            .records
+            .iter()
+            .map(|record| match record.after.as_ref().unwrap().get("id") {
+                Some(Value::Int(id)) => *id,
+                other => panic!("unexpected id {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn query_pages_by_cursor() {
+        let mut source = sqlite_source("edge.db", &[("table", "events"), ("batch_size", "2")]);
+        source.fields = vec![Field {
+            name: "id".to_string(),
+            field_type: FieldType::BigInt,
+            nullable: false,
+        }];
+        assert_eq!(
+            source.query(),
+            "SELECT rowid, \"id\" FROM \"events\" ORDER BY rowid LIMIT 2"
+        );
+        source.cursor = Some(vec!["7".to_string()]);
+        assert_eq!(
+            source.query(),
+            "SELECT rowid, \"id\" FROM \"events\" WHERE (rowid) > (?1) ORDER BY rowid LIMIT 2"
+        );
+
+        source.watermark = Some("updated_at".to_string());
+        source.cursor = Some(vec!["2024-01-31 12:00:00".to_string(), "7".to_string()]);
+        assert_eq!(
+            source.query(),
+            "SELECT rowid, \"updated_at\", \"id\" FROM \"events\" \
+             WHERE \"updated_at\" IS NOT NULL AND (\"updated_at\", rowid) > (?1, ?2) \
+             ORDER BY \"updated_at\", rowid LIMIT 2"
+        );
+    }
+
+    #[test]
+    fn maps_declared_types() {
+        assert_eq!(field_type("INTEGER"), FieldType::BigInt);
+        assert_eq!(field_type("varchar(20)"), FieldType::String);
+        assert_eq!(field_type("DATETIME"), FieldType::Timestamp);
+        assert_eq!(field_type("BOOLEAN"), FieldType::Boolean);
+        assert_eq!(field_type("DECIMAL(10,2)"), FieldType::Decimal);
+        assert_eq!(field_type(""), FieldType::String);
+
+        let flag = Field {
+            name: "flag".to_string(),
+            field_type: FieldType::Boolean,
+            nullable: false,
+        };
+        assert_eq!(value(&flag, ValueRef::Integer(1)), Ok(Value::Bool(true)));
+        assert!(value(&flag, ValueRef::Null).is_err());
+    }
+
+    #[tokio::test]
+    async fn snapshots_then_polls_for_new_rows() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("edge.db");
+        let path = path.to_str().unwrap();
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE events (id INTEGER PRIMARY KEY, name TEXT NOT NULL, seen BOOLEAN);
+             INSERT INTO events (id, name, seen) VALUES (1, 'boot', 1), (2, 'ping', NULL), (3, 'pong', 0);",
+        )
+        .unwrap();
+
+        let mut source = sqlite_source(
+            path,
+            &[
+                ("table", "events"),
+                ("batch_size", "2"),
+                ("mode", "poll"),
+                ("poll_interval_ms", "10"),
+            ],
+        );
+        source.open(None).await.unwrap();
+        let batch = source.read().await.unwrap().unwrap();
+        assert_eq!(ids(&batch), vec![1, 2]);
+        assert_eq!(batch.records[0].op, Op::Snapshot);
+        assert_eq!(batch.records[0].key, vec!["id"]);
+        assert_eq!(
+            batch.records[0].after.as_ref().unwrap().get("seen"),
+            Some(&Value::Bool(true))
+        );
+        let batch = source.read().await.unwrap().unwrap();
+        assert_eq!(ids(&batch), vec![3]);
+        assert_eq!(
+            batch.position,
+            Some(Position::Keyset {
+                key: vec!["3".to_string()]
+            })
+        );
+
+        conn.execute("INSERT INTO events (id, name) VALUES (4, 'late')", [])
+            .unwrap();
+        let batch = source.read().await.unwrap().unwrap();
+        assert_eq!(ids(&batch), vec![4]);
+        assert_eq!(batch.records[0].op, Op::Insert);
+        assert_eq!(batch.records[0].metadata.attributes["cursor"], "4");
+
+        // a snapshot resumed from its checkpoint reads the rest and finishes
+        let mut source = sqlite_source(path, &[("table", "events")]);
+        source
+            .open(Some(Position::Keyset {
+                key: vec!["2".to_string()],
+            }))
+            .await
+            .unwrap();
+        let batch = source.read().await.unwrap().unwrap();
+        assert_eq!(ids(&batch), vec![3, 4]);
+        assert_eq!(batch.position, Some(Position::Finished));
+        assert!(source.read().await.unwrap().is_none());
+
+        // a snapshot stops between pages once shutdown is signalled
+        let mut source = sqlite_source(path, &[("table", "events"), ("batch_size", "2")]);
+        let (shutdown, shutdown_rx) = broadcast::channel(1);
+        source.shutdown_rx = shutdown_rx;
+        source.open(None).await.unwrap();
+        assert_eq!(ids(&source.read().await.unwrap().unwrap()), vec![1, 2]);
+        shutdown.send(()).unwrap();
+        assert!(source.read().await.unwrap().is_none());
+    }
+
+    #[tokio::test]
+    async fn polls_by_watermark() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("edge.db");
+        let path = path.to_str().unwrap();
+        let conn = Connection::open(path).unwrap();
+        conn.execute_batch(
+            "CREATE TABLE readings (id INTEGER PRIMARY KEY, value REAL, updated_at INTEGER);
+             INSERT INTO readings VALUES (1, 0.5, 100), (2, 1.5, 100), (3, 2.5, NULL);",
+        )
+        .unwrap();
+
+        let mut source = sqlite_source(
+            path,
+            &[
+                ("table", "readings"),
+                ("watermark", "updated_at"),
+                ("mode", "poll"),
+                ("poll_interval_ms", "10"),
+            ],
+        );
+        source.open(None).await.unwrap();
+        let batch = source.read().await.unwrap().unwrap();
+        assert_eq!(ids(&batch), vec![1, 2]);
+        assert_eq!(
+            batch.position,
+            Some(Position::Keyset {
+                key: vec!["100".to_string(), "2".to_string()]
+            })
+        );
+
+        conn.execute_batch(
+            "UPDATE readings SET value = 0.75, updated_at = 101 WHERE id = 1;
+             UPDATE readings SET updated_at = 102 WHERE id = 3;",
+        )
+        .unwrap();
+        let batch = source.read().await.unwrap().unwrap();
+        assert_eq!(ids(&batch), vec![1, 3]);
+        assert_eq!(batch.records[0].op, Op::Update);
+        assert_eq!(
+            batch.records[0].after.as_ref().unwrap().get("value"),
+            Some(&Value::Float(0.75))
+        );
+    }
+}
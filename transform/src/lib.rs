@@ -3,4 +3,5 @@ pub mod processor;
 pub mod transform;
 
 pub use pipeline::Pipeline;
-pub use processor::Processor;
\ No newline at end of file
+pub use processor::{AsyncProcessor, OnError, Processor, Step};
+pub use transform::Transform;
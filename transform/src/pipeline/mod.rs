@@ -1,7 +1,626 @@
-pub mod pipeline;
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
 
+use checkpoint::{CheckpointStore, Position, Tracker};
+use config::RetryConfig;
+use record::{Error, Record};
+use sink::Sink;
+use source::Source;
+use tokio::{
+    sync::{mpsc, Mutex},
+    task::JoinSet,
+};
+use tracing::info;
+use util::{CircuitBreaker, Health};
+
+use crate::{processor::Step, transform::Transform};
+
+// DEFAULT_CAPACITY is how many batches may wait between two stages before the
+// upstream stage has to wait for the downstream one
+const DEFAULT_CAPACITY: usize = 16;
+
+// Commit checkpoints one source once its batches have been written by every sink
+struct Commit {
+    tracker: Mutex<Tracker>,
+    positions: mpsc::UnboundedSender<Position>,
+}
+
+// Delivery is a batch on its way from a source to the sinks
+struct Delivery {
+    records: Arc<Vec<Record>>,
+    // sequence is the batch's number in its source's tracker, if it moves the source
+    sequence: Option<u64>,
+    // remaining counts the sinks that have not written the batch yet
+    remaining: Arc<AtomicUsize>,
+    commit: Arc<Commit>,
+}
+
+impl Delivery {
+    // written is called by each sink after it wrote the batch; the last one commits
+    // the source's checkpoint and hands the position back to the source to ack
+    async fn written(&self) -> Result<(), Error> {
+        if self.remaining.fetch_sub(1, Ordering::AcqRel) != 1 {
+            return Ok(());
+        }
+        let Some(sequence) = self.sequence else {
+            return Ok(());
+        };
+        let committed = self.commit.tracker.lock().await.ack(sequence).await?;
+        if let Some(position) = committed {
+            // the source is gone only when the pipeline is already failing
+            let _ = self.commit.positions.send(position);
+        }
+        Ok(())
+    }
+}
+
+// Guard runs the calls a stage makes through its connector. With a retry policy
+// failed calls go through the stage's circuit breaker, which retries them or
+// pauses the stage; without one a stage fails at its first error.
+struct Guard(Option<CircuitBreaker>);
+
+impl Guard {
+    fn new(name: String, retry: Option<RetryConfig>, health: &Health) -> Self {
+        Guard(retry.map(|retry| CircuitBreaker::new(&name, &retry, health.clone())))
+    }
+
+    // check passes on what a call returned, or None once it is time to make the
+    // call again
+    async fn check<T>(&mut self, result: Result<T, Error>) -> Result<Option<T>, Error> {
+        match (result, &mut self.0) {
+            (Ok(value), breaker) => {
+                if let Some(breaker) = breaker {
+                    breaker.succeeded();
+                }
+                Ok(Some(value))
+            }
+            (Err(e), Some(breaker)) => breaker.failed(e).await.map(|()| None),
+            (Err(e), None) => Err(e),
+        }
+    }
+}
+
+// Pipeline moves records from its sources, through its processors, into every
+// one of its sinks. Stages run concurrently and are connected by bounded channels,
+// so a slow sink slows the sources down instead of buffering without limit. A
+// source's checkpoint only moves once every sink has written the batches before it.
 pub struct Pipeline {
     name: String,
-    // source: Box<dyn Source>,
-    // processors: Vec<Box<dyn Processor>>,
-}
\ No newline at end of file
+    sources: Vec<(String, Box<dyn Source>, Option<RetryConfig>)>,
+    transform: Transform,
+    sinks: Vec<(String, Box<dyn Sink>, Option<RetryConfig>)>,
+    store: Arc<dyn CheckpointStore>,
+    capacity: usize,
+    health: Health,
+}
+
+impl Pipeline {
+    pub fn new(name: impl Into<String>, store: Arc<dyn CheckpointStore>) -> Pipeline {
+        Pipeline {
+            name: name.into(),
+            sources: vec![],
+            transform: Transform::new(),
+            sinks: vec![],
+            store,
+            capacity: DEFAULT_CAPACITY,
+            health: Health::new(),
+        }
+    }
+
+    // with_health reports how the sources and sinks are doing to `health`, as
+    // `<pipeline>.sources.<name>` and `<pipeline>.sinks.<name>`
+    pub fn with_health(mut self, health: Health) -> Pipeline {
+        self.health = health;
+        self
+    }
+
+    // with_capacity sets how many batches may queue in front of each stage
+    pub fn with_capacity(mut self, capacity: usize) -> Pipeline {
+        self.capacity = capacity.max(1);
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub fn add_source(&mut self, name: impl Into<String>, source: Box<dyn Source>) {
+        self.sources.push((name.into(), source, None));
+    }
+
+    // add_source_with_retry adds a source whose failed calls are retried under
+    // `retry` rather than failing the pipeline straight away
+    pub fn add_source_with_retry(
+        &mut self,
+        name: impl Into<String>,
+        source: Box<dyn Source>,
+        retry: RetryConfig,
+    ) {
+        self.sources.push((name.into(), source, Some(retry)));
+    }
+
+    pub fn add_processor(&mut self, step: Step) {
+        self.transform.add_processor(step);
+    }
+
+    pub fn add_sink(&mut self, name: impl Into<String>, sink: Box<dyn Sink>) {
+        self.sinks.push((name.into(), sink, None));
+    }
+
+    // add_sink_with_retry adds a sink whose failed calls are retried under `retry`
+    pub fn add_sink_with_retry(
+        &mut self,
+        name: impl Into<String>,
+        sink: Box<dyn Sink>,
+        retry: RetryConfig,
+    ) {
+        self.sinks.push((name.into(), sink, Some(retry)));
+    }
+
+    // run executes the pipeline until every source is exhausted or shut down and
+    // everything read has been written, or until a stage fails
+    pub async fn run(self) -> Result<(), Error> {
+        if self.sources.is_empty() || self.sinks.is_empty() {
+            return Err(format!(
+                "pipeline `{}` needs at least one source and one sink",
+                self.name
+            )
+            .into());
+        }
+        info!(
+            "starting pipeline `{}` with {} source(s) and {} sink(s)",
+            self.name,
+            self.sources.len(),
+            self.sinks.len()
+        );
+
+        let mut tasks = JoinSet::new();
+        let sink_count = self.sinks.len();
+        let (batches_tx, batches_rx) = mpsc::channel(self.capacity);
+
+        let mut sink_txs = vec![];
+        for (name, sink, retry) in self.sinks {
+            let (tx, rx) = mpsc::channel(self.capacity);
+            sink_txs.push(tx);
+            let guard = Guard::new(format!("{}.sinks.{}", self.name, name), retry, &self.health);
+            tasks.spawn(run_sink(name, sink, guard, rx));
+        }
+        tasks.spawn(run_transform(self.transform, batches_rx, sink_txs));
+        for (name, source, retry) in self.sources {
+            let tracker = Tracker::new(self.store.clone(), format!("{}.{}", self.name, name));
+            let guard = Guard::new(
+                format!("{}.sources.{}", self.name, name),
+                retry,
+                &self.health,
+            );
+            tasks.spawn(run_source(
+                name,
+                source,
+                guard,
+                tracker,
+                sink_count,
+                batches_tx.clone(),
+            ));
+        }
+        drop(batches_tx);
+
+        while let Some(joined) = tasks.join_next().await {
+            let result = joined.map_err(Error::from).and_then(|result| result);
+            if let Err(e) = result {
+                tasks.abort_all();
+                return Err(format!("pipeline `{}`: {}", self.name, e).into());
+            }
+        }
+        info!("pipeline `{}` finished", self.name);
+        Ok(())
+    }
+}
+
+async fn run_source(
+    name: String,
+    mut source: Box<dyn Source>,
+    mut guard: Guard,
+    tracker: Tracker,
+    sink_count: usize,
+    batches: mpsc::Sender<Delivery>,
+) -> Result<(), Error> {
+    let failed = |e: Error| format!("source `{}`: {}", name, e);
+    let checkpoint = tracker.resume().await?;
+    // a call the guard lets through again is made again
+    while guard
+        .check(source.open(checkpoint.clone()).await)
+        .await
+        .map_err(failed)?
+        .is_none()
+    {}
+
+    let (positions_tx, mut positions) = mpsc::unbounded_channel();
+    let commit = Arc::new(Commit {
+        tracker: Mutex::new(tracker),
+        positions: positions_tx,
+    });
+    loop {
+        // acknowledge what the sinks finished writing since the last read
+        let mut committed = None;
+        while let Ok(position) = positions.try_recv() {
+            committed = Some(position);
+        }
+        if let Some(position) = committed {
+            while guard
+                .check(source.ack(&position).await)
+                .await
+                .map_err(failed)?
+                .is_none()
+            {}
+        }
+
+        let batch = loop {
+            if let Some(batch) = guard.check(source.read().await).await.map_err(failed)? {
+                break batch;
+            }
+        };
+        let Some(batch) = batch else {
+            break;
+        };
+        let sequence = match batch.position {
+            Some(position) => Some(commit.tracker.lock().await.track(position)),
+            None => None,
+        };
+        let delivery = Delivery {
+            records: Arc::new(batch.records),
+            sequence,
+            remaining: Arc::new(AtomicUsize::new(sink_count)),
+            commit: commit.clone(),
+        };
+        if batches.send(delivery).await.is_err() {
+            return Err(format!("source `{}`: the pipeline stopped", name).into());
+        }
+    }
+
+    // wait for the sinks to finish what was read, then acknowledge it
+    drop(batches);
+    drop(commit);
+    let mut committed = None;
+    while let Some(position) = positions.recv().await {
+        committed = Some(position);
+    }
+    if let Some(position) = committed {
+        while guard
+            .check(source.ack(&position).await)
+            .await
+            .map_err(failed)?
+            .is_none()
+        {}
+    }
+    source.close().await?;
+    info!("source `{}` finished", name);
+    Ok(())
+}
+
+async fn run_transform(
+    mut transform: Transform,
+    mut batches: mpsc::Receiver<Delivery>,
+    sinks: Vec<mpsc::Sender<Delivery>>,
+) -> Result<(), Error> {
+    while let Some(delivery) = batches.recv().await {
+        let records = if transform.is_empty() {
+            delivery.records
+        } else {
+            let records = Arc::try_unwrap(delivery.records).unwrap_or_else(|r| (*r).clone());
+            Arc::new(transform.execute(records).await?)
+        };
+        for sink in &sinks {
+            let delivery = Delivery {
+                records: records.clone(),
+                sequence: delivery.sequence,
+                remaining: delivery.remaining.clone(),
+                commit: delivery.commit.clone(),
+            };
+            if sink.send(delivery).await.is_err() {
+                return Err("a sink stopped".into());
+            }
+        }
+    }
+    Ok(())
+}
+
+async fn run_sink(
+    name: String,
+    mut sink: Box<dyn Sink>,
+    mut guard: Guard,
+    mut batches: mpsc::Receiver<Delivery>,
+) -> Result<(), Error> {
+    let failed = |e: Error| format!("sink `{}`: {}", name, e);
+    while guard
+        .check(sink.open().await)
+        .await
+        .map_err(failed)?
+        .is_none()
+    {}
+    while let Some(delivery) = batches.recv().await {
+        while !delivery.records.is_empty()
+            && guard
+                .check(sink.write(&delivery.records).await)
+                .await
+                .map_err(failed)?
+                .is_none()
+        {}
+        delivery.written().await?;
+    }
+    sink.close().await?;
+    info!("sink `{}` finished", name);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use async_trait::async_trait;
+    use checkpoint::FileStore;
+    use record::{Batch, Metadata, Op, Row, Value};
+    use std::sync::Mutex as StdMutex;
+
+    use crate::processor::Processor;
+
+    // MemorySource replays batches of ids, each ending at the LSN of its last id
+    struct MemorySource {
+        batches: Vec<Vec<i64>>,
+        opened: Arc<StdMutex<Option<Position>>>,
+        acked: Arc<StdMutex<Vec<Position>>>,
+    }
+
+    #[async_trait]
+    impl Source for MemorySource {
+        async fn open(&mut self, checkpoint: Option<Position>) -> Result<(), Error> {
+            if let Some(Position::Lsn { lsn }) = &checkpoint {
+                for batch in &mut self.batches {
+                    batch.retain(|id| *id as u64 > *lsn);
+                }
+                self.batches.retain(|batch| !batch.is_empty());
+            }
+            *self.opened.lock().unwrap() = checkpoint;
+            self.batches.reverse();
+            Ok(())
+        }
+
+        async fn read(&mut self) -> Result<Option<Batch>, Error> {
+            Ok(self.batches.pop().map(|ids| Batch {
+                position: ids.last().map(|id| Position::Lsn { lsn: *id as u64 }),
+                records: ids.into_iter().map(record).collect(),
+            }))
+        }
+
+        async fn ack(&mut self, position: &Position) -> Result<(), Error> {
+            self.acked.lock().unwrap().push(position.clone());
+            Ok(())
+        }
+
+        async fn close(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    // MemorySink collects what it is given, or fails once it has `fail_after` records
+    struct MemorySink {
+        written: Arc<StdMutex<Vec<i64>>>,
+        fail_after: Option<usize>,
+    }
+
+    #[async_trait]
+    impl Sink for MemorySink {
+        async fn open(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+
+        async fn write(&mut self, records: &[Record]) -> Result<(), Error> {
+            let mut written = self.written.lock().unwrap();
+            if self.fail_after.is_some_and(|n| written.len() >= n) {
+                return Err("disk full".into());
+            }
+            for record in records {
+                match record.row().and_then(|r| r.get("id")) {
+                    Some(Value::Int(id)) => written.push(*id),
+                    other => panic!("unexpected id {:?}", other),
+                }
+            }
+            Ok(())
+        }
+
+        async fn close(&mut self) -> Result<(), Error> {
+            Ok(())
+        }
+    }
+
+    // Flaky fails its first `failures` writes, then writes to `sink`
+    struct Flaky {
+        failures: usize,
+        sink: Box<dyn Sink>,
+    }
+
+    #[async_trait]
+    impl Sink for Flaky {
+        async fn open(&mut self) -> Result<(), Error> {
+            self.sink.open().await
+        }
+
+        async fn write(&mut self, records: &[Record]) -> Result<(), Error> {
+            if self.failures > 0 {
+                self.failures -= 1;
+                return Err("connection reset".into());
+            }
+            self.sink.write(records).await
+        }
+
+        async fn close(&mut self) -> Result<(), Error> {
+            self.sink.close().await
+        }
+    }
+
+    // Odd drops records with even ids
+    struct Odd;
+
+    impl Processor for Odd {
+        fn process(&mut self, record: Record) -> Result<Vec<Record>, Error> {
+            match record.row().and_then(|r| r.get("id")) {
+                Some(Value::Int(id)) if id % 2 == 0 => Ok(vec![]),
+                _ => Ok(vec![record]),
+            }
+        }
+    }
+
+    fn record(id: i64) -> Record {
+        Record {
+            op: Op::Insert,
+            key: vec!["id".to_string()],
+            before: None,
+            after: Some(Row::from_iter([("id".to_string(), Value::Int(id))])),
+            metadata: Metadata::default(),
+            event_time: None,
+        }
+    }
+
+    struct Fixture {
+        store: Arc<dyn CheckpointStore>,
+        opened: Arc<StdMutex<Option<Position>>>,
+        acked: Arc<StdMutex<Vec<Position>>>,
+        written: Vec<Arc<StdMutex<Vec<i64>>>>,
+    }
+
+    impl Fixture {
+        async fn new(directory: &tempfile::TempDir) -> Self {
+            let store = FileStore::open(directory.path().join("checkpoints.json"))
+                .await
+                .unwrap();
+            Fixture {
+                store: Arc::new(store),
+                opened: Arc::default(),
+                acked: Arc::default(),
+                written: vec![],
+            }
+        }
+
+        fn source(&self, batches: Vec<Vec<i64>>) -> Box<dyn Source> {
+            Box::new(MemorySource {
+                batches,
+                opened: self.opened.clone(),
+                acked: self.acked.clone(),
+            })
+        }
+
+        fn sink(&mut self, fail_after: Option<usize>) -> Box<dyn Sink> {
+            let written = Arc::new(StdMutex::new(vec![]));
+            self.written.push(written.clone());
+            Box::new(MemorySink {
+                written,
+                fail_after,
+            })
+        }
+
+        fn written(&self, sink: usize) -> Vec<i64> {
+            let mut written = self.written[sink].lock().unwrap().clone();
+            written.sort();
+            written
+        }
+    }
+
+    #[tokio::test]
+    async fn delivers_to_every_sink_and_commits() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut fixture = Fixture::new(&directory).await;
+        let mut pipeline = Pipeline::new("orders", fixture.store.clone()).with_capacity(1);
+        pipeline.add_source("a", fixture.source(vec![vec![1, 2], vec![3, 4], vec![5]]));
+        pipeline.add_source("b", fixture.source(vec![vec![11, 12, 13]]));
+        pipeline.add_processor(Step::new("odd", Box::new(Odd)));
+        pipeline.add_sink("first", fixture.sink(None));
+        pipeline.add_sink("second", fixture.sink(None));
+        pipeline.run().await.unwrap();
+
+        assert_eq!(fixture.written(0), vec![1, 3, 5, 11, 13]);
+        assert_eq!(fixture.written(1), vec![1, 3, 5, 11, 13]);
+        assert_eq!(
+            fixture.store.load("orders.a").await.unwrap(),
+            Some(Position::Lsn { lsn: 5 })
+        );
+        assert_eq!(
+            fixture.store.load("orders.b").await.unwrap(),
+            Some(Position::Lsn { lsn: 13 })
+        );
+        let acked = fixture.acked.lock().unwrap();
+        assert!(acked.contains(&Position::Lsn { lsn: 5 }));
+        assert!(acked.contains(&Position::Lsn { lsn: 13 }));
+    }
+
+    #[tokio::test]
+    async fn sink_failures_stop_the_pipeline_before_committing() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut fixture = Fixture::new(&directory).await;
+        let mut pipeline = Pipeline::new("orders", fixture.store.clone());
+        pipeline.add_source("a", fixture.source(vec![vec![1, 2], vec![3, 4], vec![5]]));
+        pipeline.add_sink("healthy", fixture.sink(None));
+        pipeline.add_sink("full", fixture.sink(Some(0)));
+        let error = pipeline.run().await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "pipeline `orders`: sink `full`: disk full"
+        );
+
+        // nothing reached every sink, so nothing is committed and a rerun starts over
+        assert_eq!(fixture.store.load("orders.a").await.unwrap(), None);
+
+        let mut pipeline = Pipeline::new("orders", fixture.store.clone());
+        pipeline.add_source("a", fixture.source(vec![vec![1, 2], vec![3, 4], vec![5]]));
+        pipeline.add_sink("replay", fixture.sink(None));
+        pipeline.run().await.unwrap();
+        assert_eq!(*fixture.opened.lock().unwrap(), None);
+        assert_eq!(fixture.written(2), vec![1, 2, 3, 4, 5]);
+    }
+
+    #[tokio::test]
+    async fn retries_failed_calls_and_reports_health() {
+        let directory = tempfile::tempdir().unwrap();
+        let mut fixture = Fixture::new(&directory).await;
+        let retry = RetryConfig {
+            initial_backoff_ms: 1,
+            max_backoff_ms: 1,
+            max_attempts: 2,
+            open_ms: 1,
+            ..RetryConfig::default()
+        };
+        let health = Health::new();
+        let mut pipeline =
+            Pipeline::new("orders", fixture.store.clone()).with_health(health.clone());
+        pipeline.add_source("a", fixture.source(vec![vec![1, 2], vec![3]]));
+        let flaky = Box::new(Flaky {
+            failures: 3,
+            sink: fixture.sink(None),
+        });
+        pipeline.add_sink_with_retry("flaky", flaky, retry.clone());
+        pipeline.run().await.unwrap();
+
+        // the write gave up after two attempts, failed again half open and then
+        // went through
+        assert_eq!(fixture.written(0), vec![1, 2, 3]);
+        assert_eq!(
+            health.components()["orders.sinks.flaky"].status,
+            util::Status::Healthy
+        );
+
+        let mut pipeline =
+            Pipeline::new("orders", fixture.store.clone()).with_health(health.clone());
+        pipeline.add_source("b", fixture.source(vec![vec![1, 2], vec![3]]));
+        let retry = RetryConfig {
+            on_unhealthy: config::OnUnhealthy::Fail,
+            ..retry
+        };
+        pipeline.add_sink_with_retry("full", fixture.sink(Some(0)), retry);
+        let error = pipeline.run().await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "pipeline `orders`: sink `full`: disk full"
+        );
+        assert!(!health.is_healthy());
+        assert_eq!(fixture.store.load("orders.b").await.unwrap(), None);
+    }
+}
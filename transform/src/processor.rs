@@ -0,0 +1,340 @@
+use async_trait::async_trait;
+use config::ProcessorConfig;
+use record::{Error, Op, Record, Row};
+use tracing::warn;
+
+// Processor turns one record into zero, one or many records
+pub trait Processor: Send {
+    fn process(&mut self, record: Record) -> Result<Vec<Record>, Error>;
+}
+
+// AsyncProcessor is a processor that waits on something, such as a lookup in
+// another system, while it works on a record
+#[async_trait]
+pub trait AsyncProcessor: Send {
+    async fn process(&mut self, record: Record) -> Result<Vec<Record>, Error>;
+}
+
+// OnError is what a step does with a record its processor fails on
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnError {
+    // Fail stops the pipeline
+    Fail,
+    // Skip logs the error and drops the record
+    Skip,
+}
+
+enum Kind {
+    Sync(Box<dyn Processor>),
+    Async(Box<dyn AsyncProcessor>),
+}
+
+// Step is one named processor in a transform, with its error handling
+pub struct Step {
+    name: String,
+    kind: Kind,
+    on_error: OnError,
+}
+
+impl Step {
+    pub fn new(name: impl Into<String>, processor: Box<dyn Processor>) -> Self {
+        Step {
+            name: name.into(),
+            kind: Kind::Sync(processor),
+            on_error: OnError::Fail,
+        }
+    }
+
+    pub fn new_async(name: impl Into<String>, processor: Box<dyn AsyncProcessor>) -> Self {
+        Step {
+            name: name.into(),
+            kind: Kind::Async(processor),
+            on_error: OnError::Fail,
+        }
+    }
+
+    pub fn on_error(mut self, on_error: OnError) -> Self {
+        self.on_error = on_error;
+        self
+    }
+
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    pub async fn process(&mut self, record: Record) -> Result<Vec<Record>, Error> {
+        let result = match &mut self.kind {
+            Kind::Sync(processor) => processor.process(record),
+            Kind::Async(processor) => processor.process(record).await,
+        };
+        match (result, self.on_error) {
+            (Ok(records), _) => Ok(records),
+            (Err(e), OnError::Fail) => Err(format!("processor `{}`: {}", self.name, e).into()),
+            (Err(e), OnError::Skip) => {
+                warn!("processor `{}` skipped a record: {}", self.name, e);
+                Ok(vec![])
+            }
+        }
+    }
+}
+
+// build creates a step from its configuration. Every processor accepts
+// `on_error = "fail" | "skip"`; the rest of the settings depend on its type:
+//
+//   drop_fields    fields = "a,b"        removes columns
+//   rename_fields  fields = "a:x,b:y"    renames columns
+//   filter         ops = "insert,update" keeps only records with these operations
+pub fn build(name: &str, config: &ProcessorConfig) -> Result<Step, Error> {
+    let setting = |key: &str| -> Result<&String, Error> {
+        config.config.get(key).ok_or_else(|| {
+            format!(
+                "{} processor `{}` is missing `{}`",
+                config.processor_type, name, key
+            )
+            .into()
+        })
+    };
+    let processor: Box<dyn Processor> = match config.processor_type.as_str() {
+        "drop_fields" => Box::new(DropFields {
+            fields: list(setting("fields")?),
+        }),
+        "rename_fields" => {
+            let mut renames = vec![];
+            for pair in list(setting("fields")?) {
+                let (from, to) = match pair.split_once(':') {
+                    Some((from, to)) if !from.trim().is_empty() && !to.trim().is_empty() => {
+                        (from.trim().to_string(), to.trim().to_string())
+                    }
+                    _ => {
+                        return Err(format!("invalid rename `{}`, expected `from:to`", pair).into())
+                    }
+                };
+                // every column keeps a distinct name, so no two renames may share
+                // a source or a target
+                if renames.iter().any(|(other, _)| *other == from) {
+                    return Err(format!("field `{}` is renamed more than once", from).into());
+                }
+                if renames.iter().any(|(_, other)| *other == to) {
+                    return Err(format!("more than one field is renamed to `{}`", to).into());
+                }
+                renames.push((from, to));
+            }
+            Box::new(RenameFields { renames })
+        }
+        "filter" => {
+            let mut ops = vec![];
+            for op in list(setting("ops")?) {
+                ops.push(match op.as_str() {
+                    "snapshot" => Op::Snapshot,
+                    "insert" => Op::Insert,
+                    "update" => Op::Update,
+                    "delete" => Op::Delete,
+                    _ => return Err(format!("invalid op `{}`", op).into()),
+                });
+            }
+            Box::new(Filter { ops })
+        }
+        other => return Err(format!("unknown processor type `{}`", other).into()),
+    };
+    let on_error = match config.config.get("on_error").map(String::as_str) {
+        None | Some("fail") => OnError::Fail,
+        Some("skip") => OnError::Skip,
+        Some(other) => {
+            return Err(format!("invalid on_error `{}`, expected `fail` or `skip`", other).into())
+        }
+    };
+    Ok(Step::new(name, processor).on_error(on_error))
+}
+
+fn list(value: &str) -> Vec<String> {
+    value
+        .split(',')
+        .map(|item| item.trim().to_string())
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn images(record: &mut Record) -> impl Iterator<Item = &mut Row> {
+    record.before.iter_mut().chain(record.after.iter_mut())
+}
+
+// DropFields removes columns from both row images
+struct DropFields {
+    fields: Vec<String>,
+}
+
+impl Processor for DropFields {
+    fn process(&mut self, mut record: Record) -> Result<Vec<Record>, Error> {
+        if let Some(field) = self.fields.iter().find(|f| record.key.contains(f)) {
+            return Err(format!("cannot drop key column `{}`", field).into());
+        }
+        for row in images(&mut record) {
+            for field in &self.fields {
+                row.remove(field);
+            }
+        }
+        Ok(vec![record])
+    }
+}
+
+// RenameFields renames columns in both row images and in the key
+struct RenameFields {
+    renames: Vec<(String, String)>,
+}
+
+impl Processor for RenameFields {
+    fn process(&mut self, mut record: Record) -> Result<Vec<Record>, Error> {
+        // a target may only be taken by a column that is renamed away itself
+        for row in images(&mut record) {
+            for (from, to) in &self.renames {
+                if row.get(from).is_some()
+                    && row.get(to).is_some()
+                    && !self.renames.iter().any(|(other, _)| other == to)
+                {
+                    return Err(format!(
+                        "cannot rename `{}` to `{}`, which already exists",
+                        from, to
+                    )
+                    .into());
+                }
+            }
+        }
+        for key in record.key.iter_mut() {
+            if let Some((_, to)) = self.renames.iter().find(|(from, _)| from == key) {
+                *key = to.clone();
+            }
+        }
+        for row in images(&mut record) {
+            for (name, _) in row.iter_mut() {
+                if let Some((_, to)) = self.renames.iter().find(|(from, _)| from == name) {
+                    *name = to.clone();
+                }
+            }
+        }
+        Ok(vec![record])
+    }
+}
+
+// Filter keeps the records whose operation is one of `ops`
+struct Filter {
+    ops: Vec<Op>,
+}
+
+impl Processor for Filter {
+    fn process(&mut self, record: Record) -> Result<Vec<Record>, Error> {
+        if self.ops.contains(&record.op) {
+            Ok(vec![record])
+        } else {
+            Ok(vec![])
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use record::{Metadata, Value};
+    use std::collections::HashMap;
+
+    fn record(op: Op, id: i64) -> Record {
+        Record {
+            op,
+            key: vec!["id".to_string()],
+            before: None,
+            after: Some(Row::from_iter([
+                ("id".to_string(), Value::Int(id)),
+                (
+                    "email".to_string(),
+                    Value::String("a@example.com".to_string()),
+                ),
+            ])),
+            metadata: Metadata::default(),
+            event_time: None,
+        }
+    }
+
+    fn step(processor_type: &str, settings: &[(&str, &str)]) -> Result<Step, Error> {
+        let config = ProcessorConfig {
+            processor_type: processor_type.to_string(),
+            config: settings
+                .iter()
+                .map(|(k, v)| (k.to_string(), v.to_string()))
+                .collect::<HashMap<_, _>>(),
+        };
+        build("test", &config)
+    }
+
+    fn columns(record: &Record) -> Vec<&str> {
+        record.row().unwrap().iter().map(|(n, _)| n).collect()
+    }
+
+    #[tokio::test]
+    async fn builds_builtin_processors() {
+        let mut drop = step("drop_fields", &[("fields", "email")]).unwrap();
+        let records = drop.process(record(Op::Insert, 1)).await.unwrap();
+        assert_eq!(columns(&records[0]), vec!["id"]);
+
+        let mut rename = step("rename_fields", &[("fields", "id:order_id")]).unwrap();
+        let records = rename.process(record(Op::Insert, 1)).await.unwrap();
+        assert_eq!(columns(&records[0]), vec!["order_id", "email"]);
+        assert_eq!(records[0].key, vec!["order_id"]);
+
+        let mut filter = step("filter", &[("ops", "insert, update")]).unwrap();
+        assert_eq!(
+            filter.process(record(Op::Update, 1)).await.unwrap().len(),
+            1
+        );
+        assert!(filter
+            .process(record(Op::Delete, 1))
+            .await
+            .unwrap()
+            .is_empty());
+
+        assert!(step("lookup", &[]).is_err());
+        assert!(step("filter", &[("ops", "upsert")]).is_err());
+        assert!(step("drop_fields", &[]).is_err());
+    }
+
+    #[tokio::test]
+    async fn rejects_colliding_renames() {
+        assert!(step("rename_fields", &[("fields", "id:  ")]).is_err());
+        let error = step("rename_fields", &[("fields", "id:key, email:key")])
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "more than one field is renamed to `key`");
+        let error = step("rename_fields", &[("fields", "id:a, id:b")])
+            .err()
+            .unwrap();
+        assert_eq!(error.to_string(), "field `id` is renamed more than once");
+
+        let mut onto = step("rename_fields", &[("fields", "id:email")]).unwrap();
+        let error = onto.process(record(Op::Insert, 1)).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "processor `test`: cannot rename `id` to `email`, which already exists"
+        );
+
+        // columns can still swap names
+        let mut swap = step("rename_fields", &[("fields", "id:email, email:id")]).unwrap();
+        let records = swap.process(record(Op::Insert, 1)).await.unwrap();
+        assert_eq!(columns(&records[0]), vec!["email", "id"]);
+        assert_eq!(records[0].key, vec!["email"]);
+    }
+
+    #[tokio::test]
+    async fn handles_errors_per_step() {
+        let mut fail = step("drop_fields", &[("fields", "id")]).unwrap();
+        let error = fail.process(record(Op::Insert, 1)).await.unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "processor `test`: cannot drop key column `id`"
+        );
+
+        let mut skip = step("drop_fields", &[("fields", "id"), ("on_error", "skip")]).unwrap();
+        assert!(skip
+            .process(record(Op::Insert, 1))
+            .await
+            .unwrap()
+            .is_empty());
+    }
+}
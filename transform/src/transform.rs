@@ -1,20 +1,37 @@
+use record::{Error, Record};
 
+use crate::processor::Step;
+
+// Transform runs records through a chain of processors in order
+#[derive(Default)]
 pub struct Transform {
-    pipeline: Pipeline,
+    steps: Vec<Step>,
 }
 
 impl Transform {
     pub fn new() -> Transform {
-        Transform {
-            pipeline: Pipeline::new(),
-        }
+        Transform { steps: vec![] }
+    }
+
+    pub fn add_processor(&mut self, step: Step) {
+        self.steps.push(step);
     }
 
-    pub fn add_processor(&mut self, processor: Processor) {
-        self.pipeline.add_processor(processor);
+    pub fn is_empty(&self) -> bool {
+        self.steps.is_empty()
     }
 
-    pub fn execute(&self, input: Vec<String>) -> Vec<String> {
-        self.pipeline.execute(input)
+    // execute feeds every record through each step; a step may drop a record or
+    // turn it into several, and the records keep their relative order
+    pub async fn execute(&mut self, input: Vec<Record>) -> Result<Vec<Record>, Error> {
+        let mut records = input;
+        for step in &mut self.steps {
+            let mut output = Vec::with_capacity(records.len());
+            for record in records {
+                output.extend(step.process(record).await?);
+            }
+            records = output;
+        }
+        Ok(records)
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,155 @@
+use std::{fmt::Display, time::Duration};
+
+use config::{OnUnhealthy, RetryConfig};
+use tracing::{info, warn};
+
+use crate::{
+    health::{Health, Status},
+    retry::Backoff,
+};
+
+// CircuitBreaker guards the calls made through one connector. A failed call is
+// retried with backoff; once the backoff gives up the circuit opens and the
+// connector is reported unhealthy. Then, depending on the connector's
+// `on_unhealthy`, the caller either fails or waits `open_ms` and tries once more,
+// half open: a success closes the circuit and a failure opens it again.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: String,
+    config: RetryConfig,
+    health: Health,
+    backoff: Option<Backoff>,
+    open: bool,
+}
+
+impl CircuitBreaker {
+    // new reports the connector `name` healthy in `health` and guards its calls
+    pub fn new(name: &str, config: &RetryConfig, health: Health) -> Self {
+        health.report(name, Status::Healthy, None);
+        CircuitBreaker {
+            name: name.to_string(),
+            config: config.clone(),
+            health,
+            backoff: None,
+            open: false,
+        }
+    }
+
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    // succeeded closes the circuit after a call went through
+    pub fn succeeded(&mut self) {
+        if self.backoff.take().is_some() {
+            if self.open {
+                info!("{} recovered", self.name);
+            }
+            self.open = false;
+            self.health.report(&self.name, Status::Healthy, None);
+        }
+    }
+
+    // failed records a failed call and waits until it is time to make it again,
+    // or returns the error when the call should not be made again
+    pub async fn failed<E: Display>(&mut self, error: E) -> Result<(), E> {
+        let backoff = self
+            .backoff
+            .get_or_insert_with(|| Backoff::new(&self.config));
+        if !self.open {
+            if let Some(delay) = backoff.next_delay() {
+                warn!(
+                    "{} failed (attempt {}), retrying in {:?}: {}",
+                    self.name,
+                    backoff.attempts(),
+                    delay,
+                    error
+                );
+                self.health
+                    .report(&self.name, Status::Retrying, Some(error.to_string()));
+                tokio::time::sleep(delay).await;
+                return Ok(());
+            }
+            self.open = true;
+        }
+
+        self.health
+            .report(&self.name, Status::Unhealthy, Some(error.to_string()));
+        match self.config.on_unhealthy {
+            OnUnhealthy::Fail => Err(error),
+            OnUnhealthy::Pause => {
+                let open = Duration::from_millis(self.config.open_ms);
+                warn!(
+                    "{} is unhealthy, trying again in {:?}: {}",
+                    self.name, open, error
+                );
+                tokio::time::sleep(open).await;
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(on_unhealthy: OnUnhealthy) -> RetryConfig {
+        RetryConfig {
+            initial_backoff_ms: 1,
+            max_backoff_ms: 1,
+            max_attempts: 2,
+            open_ms: 1,
+            on_unhealthy,
+            ..RetryConfig::default()
+        }
+    }
+
+    fn status(health: &Health) -> Status {
+        health.components()["pipeline.sinks.db"].status
+    }
+
+    #[tokio::test]
+    async fn opens_once_retries_give_up() {
+        let health = Health::new();
+        let mut breaker = CircuitBreaker::new(
+            "pipeline.sinks.db",
+            &config(OnUnhealthy::Pause),
+            health.clone(),
+        );
+        assert_eq!(status(&health), Status::Healthy);
+
+        breaker.failed("refused").await.unwrap();
+        assert_eq!(status(&health), Status::Retrying);
+        assert!(health.is_healthy());
+
+        // the second attempt was the last, so the circuit opens and pauses
+        breaker.failed("refused").await.unwrap();
+        assert!(breaker.is_open());
+        assert!(!health.is_healthy());
+        assert_eq!(
+            health.components()["pipeline.sinks.db"].error.as_deref(),
+            Some("refused")
+        );
+
+        // a half open call that fails keeps it open, one that succeeds closes it
+        breaker.failed("refused").await.unwrap();
+        assert!(breaker.is_open());
+        breaker.succeeded();
+        assert!(!breaker.is_open());
+        assert_eq!(status(&health), Status::Healthy);
+    }
+
+    #[tokio::test]
+    async fn fails_when_asked_to() {
+        let health = Health::new();
+        let mut breaker = CircuitBreaker::new(
+            "pipeline.sinks.db",
+            &config(OnUnhealthy::Fail),
+            health.clone(),
+        );
+        breaker.failed("refused").await.unwrap();
+        assert_eq!(breaker.failed("refused").await, Err("refused"));
+        assert_eq!(status(&health), Status::Unhealthy);
+    }
+}
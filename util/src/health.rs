@@ -0,0 +1,60 @@
+use std::{
+    collections::BTreeMap,
+    sync::{Arc, RwLock},
+};
+
+use serde_derive::Serialize;
+
+// Status is how a component is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Healthy,
+    // Retrying is a component whose calls are failing but still being retried
+    Retrying,
+    // Unhealthy is a component that gave up retrying
+    Unhealthy,
+}
+
+// Component is the last status a component reported, with the error behind it
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct Component {
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub error: Option<String>,
+}
+
+// Health collects the status of named components, such as the sources and
+// sinks of running pipelines. Clones share the same registry.
+#[derive(Debug, Clone, Default)]
+pub struct Health {
+    components: Arc<RwLock<BTreeMap<String, Component>>>,
+}
+
+impl Health {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // report records the status of a component
+    pub fn report(&self, name: &str, status: Status, error: Option<String>) {
+        self.components
+            .write()
+            .expect("health lock")
+            .insert(name.to_string(), Component { status, error });
+    }
+
+    // components is the status of every component that has reported, by name
+    pub fn components(&self) -> BTreeMap<String, Component> {
+        self.components.read().expect("health lock").clone()
+    }
+
+    // is_healthy is whether no component is unhealthy
+    pub fn is_healthy(&self) -> bool {
+        self.components
+            .read()
+            .expect("health lock")
+            .values()
+            .all(|component| component.status != Status::Unhealthy)
+    }
+}
@@ -1,3 +1,14 @@
+pub mod breaker;
+pub mod health;
+pub mod mysql;
+pub mod pg;
+pub mod retry;
+pub mod sqlite;
+
+pub use breaker::CircuitBreaker;
+pub use health::{Component, Health, Status};
+pub use retry::Backoff;
+
 pub fn add(left: u64, right: u64) -> u64 {
     left + right
 }
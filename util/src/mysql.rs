@@ -0,0 +1,89 @@
+use std::time::Duration;
+
+use config::MysqlConfig;
+use mysql_async::{Conn, OptsBuilder, Pool, PoolConstraints, PoolOpts};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+// Server is a pool of connections to a MySQL server, capped by the connector's
+// pool_max_size. Sessions run in UTC, so TIMESTAMP values read the same through
+// queries as through the binlog. Clones share the pool.
+#[derive(Clone)]
+pub struct Server {
+    address: String,
+    pool: Pool,
+    connect_timeout: Duration,
+}
+
+impl Server {
+    pub fn new(mysql: &MysqlConfig) -> Result<Self, Error> {
+        let mut pool_opts = PoolOpts::default();
+        if let Some(max_size) = mysql.pool_max_size {
+            let constraints = PoolConstraints::new(0, max_size)
+                .ok_or_else(|| format!("invalid pool_max_size `{}`", max_size))?;
+            pool_opts = pool_opts.with_constraints(constraints);
+        }
+        let opts = OptsBuilder::default()
+            .ip_or_hostname(mysql.host.clone())
+            .tcp_port(mysql.port)
+            .user(Some(mysql.user.clone()))
+            .pass(Some(mysql.password.expose()))
+            .prefer_socket(false)
+            .setup(vec!["SET time_zone = '+00:00'"])
+            .pool_opts(pool_opts);
+        Ok(Server {
+            address: format!("{}:{}", mysql.host, mysql.port),
+            pool: Pool::new(opts),
+            connect_timeout: Duration::from_secs(mysql.connect_timeout),
+        })
+    }
+
+    // get_conn takes a pooled connection, giving up after the connector's
+    // connect_timeout
+    pub async fn get_conn(&self) -> Result<Conn, Error> {
+        match tokio::time::timeout(self.connect_timeout, self.pool.get_conn()).await {
+            Ok(Ok(conn)) => Ok(conn),
+            Ok(Err(e)) => {
+                Err(format!("failed to connect to MySQL at {}: {}", self.address, e).into())
+            }
+            Err(_) => Err(format!(
+                "failed to connect to MySQL at {}: timed out after {:?}",
+                self.address, self.connect_timeout
+            )
+            .into()),
+        }
+    }
+
+    // disconnect closes the pool once every connection taken from it is back
+    pub async fn disconnect(self) -> Result<(), Error> {
+        self.pool.disconnect().await?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::{RetryConfig, Secret};
+
+    #[tokio::test]
+    async fn reports_the_server_it_cannot_reach() {
+        let server = Server::new(&MysqlConfig {
+            host: "127.0.0.1".to_string(),
+            port: 1,
+            user: "root".to_string(),
+            password: Secret::new("secret"),
+            server_id: None,
+            pool_max_size: Some(2),
+            connect_timeout: 5,
+            retry: RetryConfig::default(),
+        })
+        .unwrap();
+        let err = server.get_conn().await.unwrap_err().to_string();
+        assert!(
+            err.starts_with("failed to connect to MySQL at 127.0.0.1:1"),
+            "{}",
+            err
+        );
+    }
+}
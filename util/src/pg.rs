@@ -0,0 +1,451 @@
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::BufReader,
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use config::{RdsConfig, SslMode};
+use deadpool_postgres::{
+    Config, ManagerConfig, Pool, PoolConfig, RecyclingMethod, Runtime, Timeouts,
+};
+use rustls::{
+    client::{
+        danger::{HandshakeSignatureValid, ServerCertVerified, ServerCertVerifier},
+        WebPkiServerVerifier,
+    },
+    crypto::{self, CryptoProvider},
+    pki_types::{CertificateDer, PrivateKeyDer, ServerName, UnixTime},
+    CertificateError, ClientConfig, DigitallySignedStruct, RootCertStore, SignatureScheme,
+};
+use tokio_postgres::NoTls;
+use tokio_postgres_rustls::MakeRustlsConnect;
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+// Pools hands out one pool per connector and database, so every source and sink
+// using a connector shares its connections instead of each opening its own.
+// Clones share the same pools.
+#[derive(Clone, Default)]
+pub struct Pools {
+    pools: Arc<Mutex<HashMap<(String, String), Pool>>>,
+}
+
+impl Pools {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    // get returns the pool of the connector named `connector` for `database`,
+    // creating it on first use. A stage that holds `min_size` connections at once
+    // grows a pool whose size was not configured to fit them.
+    pub fn get(
+        &self,
+        connector: &str,
+        rds: &RdsConfig,
+        database: &str,
+        min_size: usize,
+    ) -> Result<Pool, Error> {
+        if let Some(max_size) = rds.pool_max_size.filter(|max_size| *max_size < min_size) {
+            return Err(format!(
+                "connector `{}` needs a pool_max_size of at least {}, not {}",
+                connector, min_size, max_size
+            )
+            .into());
+        }
+        let mut pools = self.pools.lock().expect("pools lock");
+        let key = (connector.to_string(), database.to_string());
+        let pool = match pools.get(&key) {
+            Some(pool) => pool.clone(),
+            None => {
+                let pool = create_pool(rds, database)?;
+                pools.insert(key, pool.clone());
+                pool
+            }
+        };
+        if pool.status().max_size < min_size {
+            pool.resize(min_size);
+        }
+        Ok(pool)
+    }
+}
+
+// create_pool builds a pool of connections to a Postgres database, tuned and
+// secured by the connector's settings
+pub fn create_pool(rds: &RdsConfig, database: &str) -> Result<Pool, Error> {
+    let mut cfg = Config::new();
+    cfg.host = Some(rds.host.clone());
+    cfg.port = Some(rds.port);
+    cfg.user = Some(rds.user.clone());
+    cfg.password = Some(rds.password.expose().to_string());
+    cfg.dbname = Some(database.to_string());
+    cfg.application_name = Some(rds.application_name.clone());
+    if let Some(timeout) = rds.statement_timeout {
+        cfg.options = Some(format!("-c statement_timeout={}", timeout));
+    }
+    let connect_timeout = Duration::from_secs(rds.connect_timeout);
+    cfg.connect_timeout = Some(connect_timeout);
+    cfg.keepalives = Some(true);
+    cfg.keepalives_idle = Some(Duration::from_secs(rds.keepalives_idle));
+    // a pooled connection can go from one stage to another, so whatever session
+    // state one left behind is reset, and one left inside a transaction is dropped
+    cfg.manager = Some(ManagerConfig {
+        recycling_method: RecyclingMethod::Clean,
+    });
+    // the pool is shared by every stage on the connector, so a wait for a free
+    // connection is bounded: stages holding connections the others need fail
+    // their wait and retry instead of blocking each other forever
+    cfg.pool = Some(PoolConfig {
+        max_size: rds.pool_max_size.unwrap_or(PoolConfig::default().max_size),
+        timeouts: Timeouts {
+            wait: Some(Duration::from_secs(rds.pool_timeout)),
+            create: Some(connect_timeout),
+            recycle: Some(connect_timeout),
+        },
+        ..PoolConfig::default()
+    });
+    cfg.ssl_mode = Some(match rds.sslmode {
+        SslMode::Disable => deadpool_postgres::SslMode::Disable,
+        SslMode::Prefer => deadpool_postgres::SslMode::Prefer,
+        SslMode::Require | SslMode::VerifyCa | SslMode::VerifyFull => {
+            deadpool_postgres::SslMode::Require
+        }
+    });
+    let pool = match tls_config(rds)? {
+        Some(tls) => cfg.create_pool(Some(Runtime::Tokio1), MakeRustlsConnect::new(tls))?,
+        None => cfg.create_pool(Some(Runtime::Tokio1), NoTls)?,
+    };
+    Ok(pool)
+}
+
+// tls_config is how TLS connections through the connector are made, or None
+// when it does not use TLS
+pub fn tls_config(rds: &RdsConfig) -> Result<Option<ClientConfig>, Error> {
+    if rds.sslmode == SslMode::Disable {
+        return Ok(None);
+    }
+    let provider = Arc::new(crypto::ring::default_provider());
+    let builder = ClientConfig::builder_with_provider(provider.clone())
+        .with_safe_default_protocol_versions()?;
+    let verifier = verifier(rds, provider)?;
+    let builder = builder
+        .dangerous()
+        .with_custom_certificate_verifier(verifier);
+
+    let config = match (&rds.sslcert, &rds.sslkey) {
+        (Some(cert), Some(key)) => {
+            builder.with_client_auth_cert(certificates(cert)?, private_key(key)?)?
+        }
+        _ => builder.with_no_client_auth(),
+    };
+    Ok(Some(config))
+}
+
+// verifier checks the server certificate as libpq does: `prefer` never does,
+// `require` checks it chains to `sslrootcert` when one is given, `verify-ca`
+// always checks the chain and `verify-full` the host name as well. Without
+// `sslrootcert` the last two trust the usual web authorities.
+fn verifier(
+    rds: &RdsConfig,
+    provider: Arc<CryptoProvider>,
+) -> Result<Arc<dyn ServerCertVerifier>, Error> {
+    let verifier: Arc<dyn ServerCertVerifier> = match rds.sslmode {
+        SslMode::Disable | SslMode::Prefer => Arc::new(Unverified(provider)),
+        SslMode::Require if rds.sslrootcert.is_none() => Arc::new(Unverified(provider)),
+        SslMode::Require | SslMode::VerifyCa => Arc::new(AnyName(
+            WebPkiServerVerifier::builder_with_provider(roots(rds)?, provider).build()?,
+        )),
+        SslMode::VerifyFull => {
+            WebPkiServerVerifier::builder_with_provider(roots(rds)?, provider).build()?
+        }
+    };
+    Ok(verifier)
+}
+
+// roots are the authorities in `sslrootcert`, or the usual web ones
+fn roots(rds: &RdsConfig) -> Result<Arc<RootCertStore>, Error> {
+    let mut roots = RootCertStore::empty();
+    match &rds.sslrootcert {
+        Some(path) => {
+            for cert in certificates(path)? {
+                roots.add(cert)?;
+            }
+        }
+        None => roots.extend(webpki_roots::TLS_SERVER_ROOTS.iter().cloned()),
+    }
+    Ok(Arc::new(roots))
+}
+
+fn certificates(path: &str) -> Result<Vec<CertificateDer<'static>>, Error> {
+    let file = File::open(path).map_err(|e| format!("cannot read `{}`: {}", path, e))?;
+    let certificates = rustls_pemfile::certs(&mut BufReader::new(file))
+        .collect::<Result<Vec<_>, _>>()
+        .map_err(|e| format!("cannot read `{}`: {}", path, e))?;
+    if certificates.is_empty() {
+        return Err(format!("`{}` holds no PEM certificate", path).into());
+    }
+    Ok(certificates)
+}
+
+fn private_key(path: &str) -> Result<PrivateKeyDer<'static>, Error> {
+    let file = File::open(path).map_err(|e| format!("cannot read `{}`: {}", path, e))?;
+    rustls_pemfile::private_key(&mut BufReader::new(file))
+        .map_err(|e| format!("cannot read `{}`: {}", path, e))?
+        .ok_or_else(|| format!("`{}` holds no PEM private key", path).into())
+}
+
+// Unverified accepts any server certificate, so the connection is encrypted but
+// the server is not authenticated. Handshake signatures are still checked.
+#[derive(Debug)]
+struct Unverified(Arc<CryptoProvider>);
+
+impl ServerCertVerifier for Unverified {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &CertificateDer<'_>,
+        _intermediates: &[CertificateDer<'_>],
+        _server_name: &ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        Ok(ServerCertVerified::assertion())
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls12_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        crypto::verify_tls13_signature(
+            message,
+            cert,
+            dss,
+            &self.0.signature_verification_algorithms,
+        )
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.signature_verification_algorithms.supported_schemes()
+    }
+}
+
+// AnyName verifies a server certificate chains to a trusted authority, whatever
+// host it names
+#[derive(Debug)]
+struct AnyName(Arc<WebPkiServerVerifier>);
+
+impl ServerCertVerifier for AnyName {
+    fn verify_server_cert(
+        &self,
+        end_entity: &CertificateDer<'_>,
+        intermediates: &[CertificateDer<'_>],
+        server_name: &ServerName<'_>,
+        ocsp_response: &[u8],
+        now: UnixTime,
+    ) -> Result<ServerCertVerified, rustls::Error> {
+        match self
+            .0
+            .verify_server_cert(end_entity, intermediates, server_name, ocsp_response, now)
+        {
+            Err(rustls::Error::InvalidCertificate(
+                CertificateError::NotValidForName | CertificateError::NotValidForNameContext { .. },
+            )) => Ok(ServerCertVerified::assertion()),
+            verified => verified,
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls12_signature(message, cert, dss)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &CertificateDer<'_>,
+        dss: &DigitallySignedStruct,
+    ) -> Result<HandshakeSignatureValid, rustls::Error> {
+        self.0.verify_tls13_signature(message, cert, dss)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<SignatureScheme> {
+        self.0.supported_verify_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use config::Secret;
+
+    fn rds(sslmode: SslMode) -> RdsConfig {
+        RdsConfig {
+            host: "localhost".to_string(),
+            port: 5432,
+            user: "postgres".to_string(),
+            password: Secret::new("password"),
+            sslmode,
+            sslrootcert: None,
+            sslcert: None,
+            sslkey: None,
+            pool_max_size: None,
+            connect_timeout: 10,
+            pool_timeout: 30,
+            statement_timeout: None,
+            application_name: "fust".to_string(),
+            keepalives_idle: 60,
+            retry: Default::default(),
+        }
+    }
+
+    fn cert(name: &str) -> Option<String> {
+        Some(format!(
+            "{}/tests/certs/{}",
+            env!("CARGO_MANIFEST_DIR"),
+            name
+        ))
+    }
+
+    #[test]
+    fn builds_tls_for_every_sslmode() {
+        assert!(tls_config(&rds(SslMode::Disable)).unwrap().is_none());
+        for sslmode in [
+            SslMode::Prefer,
+            SslMode::Require,
+            SslMode::VerifyCa,
+            SslMode::VerifyFull,
+        ] {
+            let tls = tls_config(&RdsConfig {
+                sslrootcert: cert("ca.crt"),
+                ..rds(sslmode)
+            })
+            .unwrap()
+            .unwrap();
+            assert!(!tls.client_auth_cert_resolver.has_certs());
+        }
+
+        let tls = tls_config(&RdsConfig {
+            sslcert: cert("client.crt"),
+            sslkey: cert("client.key"),
+            ..rds(SslMode::Require)
+        })
+        .unwrap()
+        .unwrap();
+        assert!(tls.client_auth_cert_resolver.has_certs());
+    }
+
+    #[test]
+    fn verifies_servers_like_libpq() {
+        let accepts = |sslmode: SslMode, sslrootcert: Option<String>, presented: &str| {
+            let rds = RdsConfig {
+                sslrootcert,
+                ..rds(sslmode)
+            };
+            let provider = Arc::new(crypto::ring::default_provider());
+            let presented = certificates(&cert(presented).unwrap()).unwrap();
+            verifier(&rds, provider)
+                .unwrap()
+                .verify_server_cert(
+                    &presented[0],
+                    &[],
+                    &ServerName::try_from("localhost").unwrap(),
+                    &[],
+                    UnixTime::now(),
+                )
+                .is_ok()
+        };
+        // the test authority signed a certificate for `postgres`, not `localhost`
+        assert!(accepts(SslMode::Prefer, None, "untrusted.crt"));
+        assert!(accepts(SslMode::Prefer, cert("ca.crt"), "untrusted.crt"));
+        assert!(accepts(SslMode::Require, None, "untrusted.crt"));
+        assert!(!accepts(SslMode::Require, cert("ca.crt"), "untrusted.crt"));
+        assert!(accepts(SslMode::Require, cert("ca.crt"), "client.crt"));
+        assert!(!accepts(SslMode::VerifyCa, cert("ca.crt"), "untrusted.crt"));
+        assert!(!accepts(SslMode::VerifyCa, None, "client.crt"));
+        assert!(accepts(SslMode::VerifyCa, cert("ca.crt"), "client.crt"));
+        assert!(!accepts(SslMode::VerifyFull, cert("ca.crt"), "client.crt"));
+        assert!(!accepts(
+            SslMode::VerifyFull,
+            cert("ca.crt"),
+            "untrusted.crt"
+        ));
+    }
+
+    #[test]
+    fn reports_unusable_certificates() {
+        let error = tls_config(&RdsConfig {
+            sslrootcert: cert("missing.crt"),
+            ..rds(SslMode::VerifyFull)
+        })
+        .unwrap_err();
+        assert!(error.to_string().starts_with("cannot read `"), "{}", error);
+
+        let error = tls_config(&RdsConfig {
+            sslcert: cert("client.crt"),
+            sslkey: cert("ca.crt"),
+            ..rds(SslMode::Require)
+        })
+        .unwrap_err();
+        assert!(
+            error
+                .to_string()
+                .ends_with("ca.crt` holds no PEM private key"),
+            "{}",
+            error
+        );
+    }
+
+    #[test]
+    fn shares_a_pool_per_connector_and_database() {
+        let pools = Pools::new();
+        let rds = rds(SslMode::Disable);
+        let orders = pools.get("db", &rds, "shop", 1).unwrap();
+        assert_eq!(orders.status().max_size, PoolConfig::default().max_size);
+        // stages sharing the pool give up waiting on each other's connections
+        assert_eq!(orders.timeouts().wait, Some(Duration::from_secs(30)));
+
+        // a parallel snapshot grows the pool every stage of the connector shares
+        let wide = PoolConfig::default().max_size + 8;
+        pools.get("db", &rds, "shop", wide).unwrap();
+        assert_eq!(orders.status().max_size, wide);
+        let other = pools.get("db", &rds, "warehouse", 1).unwrap();
+        assert_eq!(other.status().max_size, PoolConfig::default().max_size);
+
+        let error = pools
+            .get(
+                "small",
+                &RdsConfig {
+                    pool_max_size: Some(4),
+                    ..rds.clone()
+                },
+                "shop",
+                5,
+            )
+            .err()
+            .unwrap();
+        assert_eq!(
+            error.to_string(),
+            "connector `small` needs a pool_max_size of at least 5, not 4"
+        );
+    }
+}
@@ -0,0 +1,120 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    time::{Duration, Instant},
+};
+
+use config::RetryConfig;
+
+// Backoff paces the retries of one call: each wait grows by the multiplier up to
+// the cap and is jittered, and it runs out once the call has used up its
+// attempts or its time
+#[derive(Debug, Clone)]
+pub struct Backoff {
+    config: RetryConfig,
+    attempts: u32,
+    started: Instant,
+    wait: Duration,
+}
+
+impl Backoff {
+    pub fn new(config: &RetryConfig) -> Self {
+        Backoff {
+            config: config.clone(),
+            attempts: 0,
+            started: Instant::now(),
+            wait: Duration::from_millis(config.initial_backoff_ms),
+        }
+    }
+
+    // attempts is how many attempts have failed so far
+    pub fn attempts(&self) -> u32 {
+        self.attempts
+    }
+
+    // next_delay records a failed attempt and returns how long to wait before the
+    // next one, or None when the call should give up
+    pub fn next_delay(&mut self) -> Option<Duration> {
+        self.attempts += 1;
+        if self.attempts >= self.config.max_attempts {
+            return None;
+        }
+        let delay = jittered(self.wait, self.config.jitter);
+        let deadline = Duration::from_millis(self.config.max_elapsed_ms);
+        let elapsed = self.started.elapsed().checked_add(delay);
+        if elapsed.is_none_or(|elapsed| elapsed > deadline) {
+            return None;
+        }
+        let max = Duration::from_millis(self.config.max_backoff_ms);
+        self.wait = scaled(self.wait, self.config.multiplier).min(max);
+        Some(delay)
+    }
+}
+
+// jittered moves `wait` by a random amount of up to `jitter` of it either way
+fn jittered(wait: Duration, jitter: f64) -> Duration {
+    // every RandomState is seeded afresh, which is random enough to spread retries
+    let random = RandomState::new().build_hasher().finish() as f64 / u64::MAX as f64;
+    scaled(wait, 1.0 - jitter + 2.0 * jitter * random)
+}
+
+// scaled multiplies `wait` by `factor`, saturating instead of overflowing
+fn scaled(wait: Duration, factor: f64) -> Duration {
+    Duration::try_from_secs_f64(wait.as_secs_f64() * factor).unwrap_or(Duration::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(max_attempts: u32) -> RetryConfig {
+        RetryConfig {
+            initial_backoff_ms: 100,
+            max_backoff_ms: 350,
+            jitter: 0.0,
+            max_attempts,
+            ..RetryConfig::default()
+        }
+    }
+
+    #[test]
+    fn backs_off_exponentially_up_to_the_cap() {
+        let mut backoff = Backoff::new(&config(5));
+        let delays: Vec<Option<Duration>> = (0..5).map(|_| backoff.next_delay()).collect();
+        assert_eq!(
+            delays,
+            [100, 200, 350, 350]
+                .into_iter()
+                .map(|ms| Some(Duration::from_millis(ms)))
+                .chain([None])
+                .collect::<Vec<_>>()
+        );
+
+        let mut backoff = Backoff::new(&RetryConfig {
+            max_elapsed_ms: 150,
+            ..config(5)
+        });
+        assert_eq!(backoff.next_delay(), Some(Duration::from_millis(100)));
+        // the next wait would take the call past its time
+        assert_eq!(backoff.next_delay(), None);
+
+        // a multiplier the configuration should have refused still only reaches the cap
+        let mut backoff = Backoff::new(&RetryConfig {
+            multiplier: 1e300,
+            ..config(4)
+        });
+        let delays: Vec<Option<Duration>> = (0..3).map(|_| backoff.next_delay()).collect();
+        assert_eq!(
+            delays,
+            [100, 350, 350]
+                .into_iter()
+                .map(|ms| Some(Duration::from_millis(ms)))
+                .collect::<Vec<_>>()
+        );
+
+        for _ in 0..100 {
+            let wait = jittered(Duration::from_millis(1000), 0.2);
+            assert!(wait >= Duration::from_millis(800) && wait <= Duration::from_millis(1200));
+        }
+    }
+}
@@ -0,0 +1,96 @@
+use std::{
+    sync::{Arc, Mutex},
+    time::Duration,
+};
+
+use config::SqliteConfig;
+use rusqlite::{Connection, OpenFlags};
+
+type Error = Box<dyn std::error::Error + Send + Sync>;
+
+// Database is a connection to a SQLite file. SQLite calls block, so they run
+// on the blocking threads of the runtime, one at a time. Clones share the
+// connection.
+#[derive(Clone)]
+pub struct Database {
+    path: String,
+    conn: Arc<Mutex<Connection>>,
+}
+
+impl Database {
+    // open connects to the connector's database file. With `create`, a missing
+    // file is created and switched to write-ahead logging, so that sources can
+    // read it while a sink writes.
+    pub async fn open(sqlite: &SqliteConfig, create: bool) -> Result<Self, Error> {
+        let path = sqlite.path.clone();
+        let busy_timeout = Duration::from_millis(sqlite.busy_timeout);
+        let conn = tokio::task::spawn_blocking(move || -> Result<Connection, Error> {
+            let mut flags = OpenFlags::SQLITE_OPEN_READ_WRITE
+                | OpenFlags::SQLITE_OPEN_URI
+                | OpenFlags::SQLITE_OPEN_NO_MUTEX;
+            if create {
+                flags |= OpenFlags::SQLITE_OPEN_CREATE;
+            }
+            let conn = Connection::open_with_flags(&path, flags)
+                .map_err(|e| format!("failed to open SQLite database `{}`: {}", path, e))?;
+            conn.busy_timeout(busy_timeout)?;
+            if create {
+                conn.query_row("PRAGMA journal_mode = WAL", [], |_| Ok(()))?;
+            }
+            Ok(conn)
+        })
+        .await??;
+        Ok(Database {
+            path: sqlite.path.clone(),
+            conn: Arc::new(Mutex::new(conn)),
+        })
+    }
+
+    pub fn path(&self) -> &str {
+        &self.path
+    }
+
+    // call runs `f` with the connection on a blocking thread
+    pub async fn call<T, F>(&self, f: F) -> Result<T, Error>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Connection) -> Result<T, Error> + Send + 'static,
+    {
+        let conn = self.conn.clone();
+        tokio::task::spawn_blocking(move || {
+            let mut conn = conn.lock().expect("sqlite connection lock");
+            f(&mut conn)
+        })
+        .await?
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sqlite(path: &str) -> SqliteConfig {
+        SqliteConfig {
+            path: path.to_string(),
+            busy_timeout: 1000,
+            retry: Default::default(),
+        }
+    }
+
+    #[tokio::test]
+    async fn opens_database_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("edge.db");
+        let path = path.to_str().unwrap();
+
+        assert!(Database::open(&sqlite(path), false).await.is_err());
+
+        let database = Database::open(&sqlite(path), true).await.unwrap();
+        let mode: String = database
+            .call(|conn| Ok(conn.query_row("PRAGMA journal_mode", [], |row| row.get(0))?))
+            .await
+            .unwrap();
+        assert_eq!(mode, "wal");
+        assert!(Database::open(&sqlite(path), false).await.is_ok());
+    }
+}
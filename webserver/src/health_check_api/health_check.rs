@@ -1,28 +1,58 @@
-use axum::{http::StatusCode, Json};
+use std::collections::BTreeMap;
+
+use axum::{extract::State, http::StatusCode, Json};
 use serde_derive::Serialize;
 use tracing::info;
+use util::{Component, Health};
 
-pub async fn health() -> (StatusCode, Json<Status>) {
+// health reports every component of the running pipelines, answering 503 while
+// any of them is unhealthy
+pub async fn health(State(health): State<Health>) -> (StatusCode, Json<Status>) {
     info!("health");
+    let healthy = health.is_healthy();
     let stat = Status {
-        status: 0,
-        description: String::from("running"),
+        status: if healthy { 0 } else { 1 },
+        description: String::from(if healthy { "running" } else { "unhealthy" }),
+        components: health.components(),
+    };
+    let code = if healthy {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
     };
 
-    (StatusCode::OK, Json(stat))
+    (code, Json(stat))
 }
 
 #[derive(Serialize)]
 pub struct Status {
     status: i8,
     description: String,
+    components: BTreeMap<String, Component>,
 }
 
-
 #[tokio::test]
 async fn health_check_returns_ok() {
-    let (status, body) = health().await;
+    let (status, body) = health(State(Health::new())).await;
 
     assert_eq!(status, StatusCode::OK);
     assert_eq!(body.0.status, 0);
-}
\ No newline at end of file
+}
+
+#[tokio::test]
+async fn health_check_reports_unhealthy_components() {
+    let health_registry = Health::new();
+    health_registry.report(
+        "orders.sinks.db",
+        util::Status::Unhealthy,
+        Some("refused".into()),
+    );
+    let (status, body) = health(State(health_registry)).await;
+
+    assert_eq!(status, StatusCode::SERVICE_UNAVAILABLE);
+    assert_eq!(body.0.status, 1);
+    assert_eq!(
+        serde_json::to_value(&body.0.components).unwrap(),
+        serde_json::json!({"orders.sinks.db": {"status": "unhealthy", "error": "refused"}})
+    );
+}
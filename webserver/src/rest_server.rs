@@ -1,15 +1,20 @@
 use axum::{http::StatusCode, routing::get, Json, Router};
 use tokio::{net::TcpListener, sync::broadcast::Receiver};
 use tracing::info;
+use util::Health;
 
-use crate::system_info::system;
+use crate::{health_check_api, system_info::system};
 
-pub async fn serve(mut rx: Receiver<()>) {
+// serve answers REST requests until `rx` signals shutdown; `/health` reports
+// the components `health` collects
+pub async fn serve(mut rx: Receiver<()>, health: Health) {
     info!("running rest server...");
 
     let app = Router::new()
         .route("/", get(root))
-        .route("/status", get(status));
+        .route("/status", get(status))
+        .route("/health", get(health_check_api::health))
+        .with_state(health);
 
     let listener = TcpListener::bind("0.0.0.0:3000").await.unwrap();
     axum::serve(listener, app).with_graceful_shutdown(
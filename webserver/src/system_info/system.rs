@@ -20,6 +20,7 @@ pub struct Status {
 }
 
 #[derive(Serialize)]
+#[allow(clippy::upper_case_acronyms)]
 struct CPU {
     cpus: usize,
     global_cpu_usage: f32,
@@ -48,6 +49,7 @@ struct Disk {
 }
 
 impl Status {
+    #[allow(clippy::new_without_default)]
     pub fn new() -> Self {
         let mut sys = System::new_all();
         sys.refresh_all();